@@ -0,0 +1,55 @@
+use calculator::{Engine, Value};
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ROWS: usize = 1_000_000;
+const EXPR: &str = "a * x + b - (x - a) / (b + 2)";
+
+fn measure(name: &str, mut f: impl FnMut() -> f64) -> Duration {
+    // 一度温めてから計測する
+    black_box(f());
+
+    let start = Instant::now();
+    let sum = black_box(f());
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<12} {:>10.2?} ({:>6.1} ns/row, sum = {})",
+        name,
+        elapsed,
+        elapsed.as_nanos() as f64 / ROWS as f64,
+        sum
+    );
+    elapsed
+}
+
+fn main() {
+    let mut engine = Engine::new();
+    let ast = engine.parse(EXPR).unwrap();
+    let compiled = engine.compile(EXPR, &["a", "x", "b"]).unwrap();
+
+    let rows: Vec<[f64; 3]> = (0..ROWS).map(|i| [1.5, i as f64, (i % 7) as f64]).collect();
+
+    let tree = measure("tree-walk", || {
+        let mut sum = 0.0;
+        for [a, x, b] in &rows {
+            engine.set("a", Value::Number(*a));
+            engine.set("x", Value::Number(*x));
+            engine.set("b", Value::Number(*b));
+            sum += engine.eval_ast(&ast).unwrap().as_number().unwrap();
+        }
+        sum
+    });
+
+    let closure = measure("compiled", || {
+        rows.iter()
+            .map(|row| compiled.call(row).unwrap())
+            .sum::<f64>()
+    });
+
+    println!(
+        "speedup      {:.1}x",
+        tree.as_secs_f64() / closure.as_secs_f64()
+    );
+}
@@ -0,0 +1,52 @@
+/* Generated by calculator::capi::header(); do not edit. */
+#ifndef CALCULATOR_H
+#define CALCULATOR_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CALC_OK 0 /* success */
+#define CALC_ERR_LEXER 1 /* invalid character or number */
+#define CALC_ERR_PARSER 2 /* syntax error */
+#define CALC_ERR_EVAL 3 /* evaluation failed */
+#define CALC_ERR_NOT_A_NUMBER 4 /* the result is not a real number */
+#define CALC_ERR_INVALID_ARGUMENT 5 /* NULL or non-UTF-8 input */
+#define CALC_ERR_INTERNAL 6 /* the calculator panicked */
+
+#define CALC_MESSAGE_LEN 256
+
+/* When code is not CALC_OK, [start, end) are byte offsets of the error in the input. */
+typedef struct calc_error {
+    int code;
+    size_t start;
+    size_t end;
+    char message[CALC_MESSAGE_LEN];
+} calc_error;
+
+/* Keeps variables and the result history between evaluations. */
+typedef struct calc_session calc_session;
+
+/* Evaluates one statement in a fresh session. result and error may be NULL. */
+int calc_eval(const char *input, double *result, calc_error *error);
+
+/* Creates a session; free it with calc_session_free. */
+calc_session *calc_session_new(void);
+
+/* Frees a session. session may be NULL. */
+void calc_session_free(calc_session *session);
+
+/* Evaluates one statement in session, keeping variables and history. */
+int calc_session_eval(calc_session *session, const char *input, double *result,
+                      calc_error *error);
+
+/* The NUL-terminated message stored in error; "" when error is NULL. */
+const char *calc_error_message(const calc_error *error);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CALCULATOR_H */
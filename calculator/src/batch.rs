@@ -0,0 +1,215 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::eval::Value;
+use crate::json::Json;
+use crate::lexer::split_statements;
+use crate::numfmt::NumberFormat;
+use crate::token::*;
+use crate::value::Shape;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+// 文ごとに一行ずつ結果を書き出す。エラーが一つでもあれば false。
+// テキスト出力では失敗した文の行に `error` と書き、詳細は err に書く。
+// engine の変数は呼び出しをまたいで持ち越す。
+// numbers はテキスト出力の数値の表記で、JSON では数値のまま書く
+pub fn run_batch(
+    name: &str,
+    input: &str,
+    format: OutputFormat,
+    numbers: &NumberFormat,
+    engine: &mut Engine,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<bool> {
+    let mut ok = true;
+
+    for loc in split_statements(input) {
+        let result = match engine.eval_statement(input, &loc) {
+            Ok(None) => continue,
+            Ok(Some(v)) => Ok(v),
+            Err(e) => {
+                ok = false;
+                Err(e)
+            }
+        };
+
+        match format {
+            OutputFormat::Text => match result {
+                Ok(v) => writeln!(out, "{}", numbers.value(&v))?,
+                Err(e) => {
+                    writeln!(out, "error")?;
+                    let at = e.loc().unwrap_or(Location::new(loc.end(), loc.end()));
+                    let (line, col) = at.line_col(input);
+                    writeln!(err, "{}:{}:{}: error: {}", name, line, col, e)?;
+                }
+            },
+            OutputFormat::Json => {
+                writeln!(out, "{}", result_json(input, &loc, &result))?;
+            }
+        }
+    }
+
+    Ok(ok)
+}
+
+pub fn value_json(v: &Value) -> Json {
+    match v {
+        Value::Number(n) => number_json(*n),
+        Value::Complex(z) => Json::object([("re", number_json(z.re)), ("im", number_json(z.im))]),
+        Value::Interval(x) => Json::object([("lo", number_json(x.lo)), ("hi", number_json(x.hi))]),
+        Value::Array(a) => {
+            let row = |r: &[f64]| Json::Array(r.iter().map(|x| number_json(*x)).collect());
+            match a.shape() {
+                Shape::Vector(_) => row(a.data()),
+                Shape::Matrix(..) => Json::Array(a.rows().into_iter().map(row).collect()),
+            }
+        }
+    }
+}
+
+// JSON の数値は有限の値しか書けないので、無限大と NaN は "inf", "-inf", "NaN" の文字列にする
+fn number_json(x: f64) -> Json {
+    if x.is_finite() {
+        Json::Number(x)
+    } else {
+        Json::string(x.to_string())
+    }
+}
+
+pub fn location_json(input: &str, loc: &Location) -> Json {
+    let (line, col) = loc.line_col(input);
+    Json::object([
+        ("start", Json::Number(loc.start() as f64)),
+        ("end", Json::Number(loc.end() as f64)),
+        ("line", Json::Number(line as f64)),
+        ("column", Json::Number(col as f64)),
+    ])
+}
+
+fn result_json(input: &str, loc: &Location, result: &Result<Value, Error>) -> Json {
+    let (line, _) = loc.line_col(input);
+    let source = Json::string(input[loc.start()..loc.end()].trim());
+
+    let mut entries = vec![("line", Json::Number(line as f64)), ("input", source)];
+    match result {
+        Ok(v) => entries.push(("value", value_json(v))),
+        Err(e) => {
+            let at = e.loc().unwrap_or(Location::new(loc.end(), loc.end()));
+            entries.push((
+                "error",
+                Json::object([
+                    ("kind", Json::string(e.kind())),
+                    ("message", Json::string(e.to_string())),
+                    ("location", location_json(input, &at)),
+                ]),
+            ));
+        }
+    }
+
+    Json::object(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interval::Interval;
+    use crate::value::Array;
+
+    fn run(input: &str, format: OutputFormat) -> (bool, String, String) {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let ok = run_batch(
+            "test",
+            input,
+            format,
+            &NumberFormat::default(),
+            &mut Engine::new(),
+            &mut out,
+            &mut err,
+        )
+        .unwrap();
+        (
+            ok,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_run_batch_text() {
+        assert_eq!(
+            run("x = 1 + 2\n\n# comment\n(x\n * 4)\n", OutputFormat::Text),
+            (true, "3\n12\n".to_string(), String::new())
+        );
+        assert_eq!(
+            run("1 / 0\n2 +\n5", OutputFormat::Text),
+            (
+                false,
+                "error\nerror\n5\n".to_string(),
+                concat!(
+                    "test:1:3: error: division by zero\n",
+                    "test:2:4: error: expected number, name, history reference, '(', '[', '+' or '-', found end of input\n",
+                )
+                    .to_string()
+            )
+        );
+
+        // 変数は呼び出しをまたいで持ち越す
+        let mut engine = Engine::new();
+        let mut out = Vec::new();
+        for input in ["x = 2", "x * 3"] {
+            let numbers = NumberFormat::default();
+            run_batch(
+                "test",
+                input,
+                OutputFormat::Text,
+                &numbers,
+                &mut engine,
+                &mut out,
+                &mut io::sink(),
+            )
+            .unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), "2\n6\n");
+    }
+
+    #[test]
+    fn test_run_batch_json() {
+        let (ok, out, _) = run("1 + 2\n3 $", OutputFormat::Json);
+
+        assert!(!ok);
+        assert_eq!(
+            out,
+            concat!(
+                r#"{"line":1,"input":"1 + 2","value":3}"#,
+                "\n",
+                r#"{"line":2,"input":"3 $","error":{"kind":"lexer","message":"invalid character '$'","location":{"start":8,"end":9,"line":2,"column":3}}}"#,
+                "\n"
+            )
+        );
+
+        let v = Value::Interval(Interval::new(f64::NEG_INFINITY, f64::INFINITY));
+        assert_eq!(value_json(&v).to_string(), r#"{"lo":"-inf","hi":"inf"}"#);
+        let v = Value::Array(Array::vector(vec![1.0, f64::NAN]));
+        assert_eq!(value_json(&v).to_string(), r#"[1,"NaN"]"#);
+    }
+}
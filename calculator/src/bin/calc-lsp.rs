@@ -0,0 +1,62 @@
+use calculator::cli::{args_or_exit, read_message, write_message, Server};
+use calculator::{Engine, Mode};
+
+use std::io::{stdin, stdout};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: calc-lsp [--mode real|complex|interval] [--implicit-mul]
+
+Speaks the Language Server Protocol over stdin and stdout.";
+
+fn main() -> ExitCode {
+    let engine = match args_or_exit("calc-lsp", USAGE, parse_args(std::env::args().skip(1))) {
+        Ok(engine) => engine,
+        Err(code) => return code,
+    };
+    let mut server = Server::with_engine(engine);
+    let mut input = stdin().lock();
+    let mut output = stdout().lock();
+
+    loop {
+        let body = match read_message(&mut input) {
+            Ok(Some(body)) => body,
+            // クライアントが exit を送らずに終了した
+            Ok(None) => return ExitCode::FAILURE,
+            Err(e) => {
+                eprintln!("calc-lsp: {}", e);
+                return ExitCode::from(2);
+            }
+        };
+
+        for reply in server.handle_message(&body) {
+            if let Err(e) = write_message(&mut output, &reply) {
+                eprintln!("calc-lsp: {}", e);
+                return ExitCode::from(2);
+            }
+        }
+        if let Some(code) = server.exit_code() {
+            return ExitCode::from(code as u8);
+        }
+    }
+}
+
+// --help なら None
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Engine>, String> {
+    let mut engine = Engine::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                let mode = args
+                    .next()
+                    .ok_or("--mode requires real, complex or interval")?;
+                engine.set_mode(mode.parse::<Mode>()?);
+            }
+            "--implicit-mul" => engine.parser_mut().set_implicit_mul(true),
+            "-h" | "--help" => return Ok(None),
+            s => return Err(format!("unknown option: {}", s)),
+        }
+    }
+
+    Ok(Some(engine))
+}
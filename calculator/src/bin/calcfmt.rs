@@ -0,0 +1,128 @@
+use calculator::cli::{args_or_exit, FormatOptions, Formatter};
+use calculator::{Location, Parser};
+
+use std::io::{stdin, stdout, Read, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: calcfmt [--check] [--width N] [--indent N] [--implicit-mul] [FILE...]";
+
+struct Args {
+    check: bool,
+    implicit_mul: bool,
+    options: FormatOptions,
+    files: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let args = match args_or_exit("calcfmt", USAGE, parse_args(std::env::args().skip(1))) {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
+    let mut parser = Parser::default();
+    parser.set_implicit_mul(args.implicit_mul);
+    let formatter = Formatter::new(parser, args.options.clone());
+
+    if args.files.is_empty() {
+        let mut input = String::new();
+        if let Err(e) = stdin().read_to_string(&mut input) {
+            eprintln!("calcfmt: <stdin>: {}", e);
+            return ExitCode::from(2);
+        }
+        return match run(&formatter, "<stdin>", &input, args.check) {
+            Ok(Some(formatted)) => {
+                let _ = stdout().write_all(formatted.as_bytes());
+                ExitCode::SUCCESS
+            }
+            Ok(None) => ExitCode::SUCCESS,
+            Err(code) => code,
+        };
+    }
+
+    let mut code = ExitCode::SUCCESS;
+    for path in &args.files {
+        let input = match std::fs::read_to_string(path) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("calcfmt: {}: {}", path, e);
+                code = ExitCode::from(2);
+                continue;
+            }
+        };
+
+        match run(&formatter, path, &input, args.check) {
+            Ok(Some(formatted)) if formatted != input => {
+                if let Err(e) = std::fs::write(path, formatted) {
+                    eprintln!("calcfmt: {}: {}", path, e);
+                    code = ExitCode::from(2);
+                }
+            }
+            Ok(_) => {}
+            Err(c) => code = c,
+        }
+    }
+
+    code
+}
+
+// check モードでは差分を報告するだけで整形結果は返さない
+fn run(
+    formatter: &Formatter,
+    path: &str,
+    input: &str,
+    check: bool,
+) -> Result<Option<String>, ExitCode> {
+    let formatted = match formatter.format(input) {
+        Ok(formatted) => formatted,
+        Err(e) => {
+            // 入力の終わりで失敗した場合は末尾を指す
+            let at = e.loc().unwrap_or(Location::new(input.len(), input.len()));
+            let (line, col) = at.line_col(input);
+            eprintln!("calcfmt: {}:{}:{}: error: {}", path, line, col, e);
+            return Err(ExitCode::from(2));
+        }
+    };
+
+    if !check {
+        return Ok(Some(formatted));
+    }
+
+    if formatted != input {
+        println!("would reformat: {}", path);
+        return Err(ExitCode::from(1));
+    }
+
+    Ok(None)
+}
+
+// --help なら None
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut ret = Args {
+        check: false,
+        implicit_mul: false,
+        options: FormatOptions::default(),
+        files: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => ret.check = true,
+            "--implicit-mul" => ret.implicit_mul = true,
+            "--width" | "--indent" => {
+                let n = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or(format!("{} requires a number", arg))?;
+                if arg == "--width" {
+                    ret.options.width = n;
+                } else {
+                    ret.options.indent = n;
+                }
+            }
+            "-h" | "--help" => return Ok(None),
+            s if s.starts_with('-') => return Err(format!("unknown option: {}", s)),
+            _ => ret.files.push(arg),
+        }
+    }
+
+    Ok(Some(ret))
+}
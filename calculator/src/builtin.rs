@@ -0,0 +1,107 @@
+use crate::complex::Complex;
+use crate::eval::{EvalErrorKind, Mode};
+use crate::value::{Array, Value};
+
+pub type BuiltinFn = fn(&[Value], Mode) -> Result<Value, EvalErrorKind>;
+
+// 組み込み関数。引数の個数は呼び出し側で確認してから f を呼ぶ
+#[derive(Debug, Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+    pub help: &'static str,
+    pub f: BuiltinFn,
+}
+
+static BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "det",
+        arity: 1,
+        help: "determinant of a square matrix",
+        f: |args, _| Ok(Value::Number(array(&args[0])?.det()?)),
+    },
+    Builtin {
+        name: "transpose",
+        arity: 1,
+        help: "transpose of a matrix (a vector becomes a column)",
+        f: |args, _| Ok(Value::Array(array(&args[0])?.transpose())),
+    },
+    Builtin {
+        name: "inv",
+        arity: 1,
+        help: "inverse of a square matrix",
+        f: |args, _| Ok(Value::Array(array(&args[0])?.inv()?)),
+    },
+    Builtin {
+        name: "abs",
+        arity: 1,
+        help: "absolute value, or modulus of a complex number",
+        f: |args, _| match &args[0] {
+            Value::Number(n) => Ok(Value::Number(n.abs())),
+            Value::Complex(z) => Ok(Value::Number(z.abs())),
+            Value::Interval(x) => Ok(Value::Interval(x.abs())),
+            Value::Array(a) => Ok(Value::Array(a.map(f64::abs))),
+        },
+    },
+    Builtin {
+        name: "arg",
+        arity: 1,
+        help: "argument (phase angle in radians) of a complex number",
+        f: |args, _| Ok(Value::Number(complex(&args[0])?.arg())),
+    },
+    Builtin {
+        name: "conj",
+        arity: 1,
+        help: "complex conjugate",
+        f: |args, _| match &args[0] {
+            Value::Complex(z) => Ok(Value::Complex(z.conj())),
+            v => Ok(Value::Number(complex(v)?.re)),
+        },
+    },
+    Builtin {
+        name: "sqrt",
+        arity: 1,
+        help: "square root; negative numbers need complex mode",
+        f: |args, mode| match &args[0] {
+            Value::Number(n) if *n >= 0.0 => Ok(Value::Number(n.sqrt())),
+            Value::Number(n) if mode == Mode::Complex => {
+                Ok(Value::Complex(Complex::from(*n).sqrt()))
+            }
+            Value::Number(_) => Err(EvalErrorKind::Domain("sqrt".to_string())),
+            Value::Complex(z) => Ok(Value::Complex(z.sqrt())),
+            Value::Interval(x) => x
+                .sqrt()
+                .map(Value::Interval)
+                .ok_or_else(|| EvalErrorKind::Domain("sqrt".to_string())),
+            Value::Array(a) if a.data().iter().all(|x| *x >= 0.0) => {
+                Ok(Value::Array(a.map(f64::sqrt)))
+            }
+            Value::Array(_) => Err(EvalErrorKind::Domain("sqrt".to_string())),
+        },
+    },
+];
+
+pub fn builtins() -> &'static [Builtin] {
+    BUILTINS
+}
+
+pub fn find_builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+fn complex(v: &Value) -> Result<Complex, EvalErrorKind> {
+    v.as_complex().ok_or_else(|| EvalErrorKind::TypeMismatch {
+        expected: "number".to_string(),
+        found: v.describe(),
+    })
+}
+
+fn array(v: &Value) -> Result<&Array, EvalErrorKind> {
+    match v {
+        Value::Array(a) => Ok(a),
+        _ => Err(EvalErrorKind::TypeMismatch {
+            expected: "matrix".to_string(),
+            found: v.describe(),
+        }),
+    }
+}
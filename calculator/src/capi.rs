@@ -0,0 +1,505 @@
+// C から電卓を使うための関数。ヘッダーはテストの中で header() から作り、include/calculator.h に置く
+use std::ffi::{c_char, c_int, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::eval::Value;
+
+pub const CALC_OK: c_int = 0;
+pub const CALC_ERR_LEXER: c_int = 1;
+pub const CALC_ERR_PARSER: c_int = 2;
+pub const CALC_ERR_EVAL: c_int = 3;
+pub const CALC_ERR_NOT_A_NUMBER: c_int = 4;
+pub const CALC_ERR_INVALID_ARGUMENT: c_int = 5;
+pub const CALC_ERR_INTERNAL: c_int = 6;
+
+// 末尾の NUL を含む
+pub const CALC_MESSAGE_LEN: usize = 256;
+
+// 名前、値、説明
+#[cfg(test)]
+const CODES: &[(&str, c_int, &str)] = &[
+    ("CALC_OK", CALC_OK, "success"),
+    (
+        "CALC_ERR_LEXER",
+        CALC_ERR_LEXER,
+        "invalid character or number",
+    ),
+    ("CALC_ERR_PARSER", CALC_ERR_PARSER, "syntax error"),
+    ("CALC_ERR_EVAL", CALC_ERR_EVAL, "evaluation failed"),
+    (
+        "CALC_ERR_NOT_A_NUMBER",
+        CALC_ERR_NOT_A_NUMBER,
+        "the result is not a real number",
+    ),
+    (
+        "CALC_ERR_INVALID_ARGUMENT",
+        CALC_ERR_INVALID_ARGUMENT,
+        "NULL or non-UTF-8 input",
+    ),
+    (
+        "CALC_ERR_INTERNAL",
+        CALC_ERR_INTERNAL,
+        "the calculator panicked",
+    ),
+];
+
+// code が CALC_OK でなければ start..end は入力中の誤りのバイト位置
+#[repr(C)]
+pub struct CalcError {
+    pub code: c_int,
+    pub start: usize,
+    pub end: usize,
+    pub message: [c_char; CALC_MESSAGE_LEN],
+}
+
+// 変数と結果の履歴を呼び出しの間で保持する
+pub struct CalcSession {
+    engine: Engine,
+}
+
+struct Failure {
+    code: c_int,
+    start: usize,
+    end: usize,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: c_int, start: usize, end: usize, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            start,
+            end,
+            message: message.into(),
+        }
+    }
+
+    fn from_error(e: &Error, input: &str) -> Self {
+        let code = match e {
+            Error::Lexer(_) => CALC_ERR_LEXER,
+            Error::Parser(_) => CALC_ERR_PARSER,
+            Error::Eval(_) => CALC_ERR_EVAL,
+        };
+        let (start, end) = e
+            .loc()
+            .map_or((input.len(), input.len()), |l| (l.start(), l.end()));
+        Self::new(code, start, end, e.to_string())
+    }
+}
+
+/// # Safety
+/// `input` must be NULL or a NUL-terminated string. `result` and `error` may be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn calc_eval(
+    input: *const c_char,
+    result: *mut f64,
+    error: *mut CalcError,
+) -> c_int {
+    let mut engine = Engine::new();
+    run(&mut engine, input, result, error)
+}
+
+#[no_mangle]
+pub extern "C" fn calc_session_new() -> *mut CalcSession {
+    Box::into_raw(Box::new(CalcSession {
+        engine: Engine::new(),
+    }))
+}
+
+/// # Safety
+/// `session` must be NULL or a pointer returned by `calc_session_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn calc_session_free(session: *mut CalcSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// # Safety
+/// `session` must be a live session, and the other pointers as for `calc_eval`.
+#[no_mangle]
+pub unsafe extern "C" fn calc_session_eval(
+    session: *mut CalcSession,
+    input: *const c_char,
+    result: *mut f64,
+    error: *mut CalcError,
+) -> c_int {
+    match session.as_mut() {
+        Some(session) => run(&mut session.engine, input, result, error),
+        None => report(
+            error,
+            Failure::new(CALC_ERR_INVALID_ARGUMENT, 0, 0, "session is NULL"),
+        ),
+    }
+}
+
+/// # Safety
+/// `error` must be NULL or point to a `calc_error` filled by this library.
+#[no_mangle]
+pub unsafe extern "C" fn calc_error_message(error: *const CalcError) -> *const c_char {
+    match error.as_ref() {
+        Some(e) => e.message.as_ptr(),
+        None => c"".as_ptr(),
+    }
+}
+
+unsafe fn run(
+    engine: &mut Engine,
+    input: *const c_char,
+    result: *mut f64,
+    error: *mut CalcError,
+) -> c_int {
+    if input.is_null() {
+        return report(
+            error,
+            Failure::new(CALC_ERR_INVALID_ARGUMENT, 0, 0, "input is NULL"),
+        );
+    }
+    let input = match CStr::from_ptr(input).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            let at = e.valid_up_to();
+            let f = Failure::new(CALC_ERR_INVALID_ARGUMENT, at, at + 1, "input is not UTF-8");
+            return report(error, f);
+        }
+    };
+
+    let outcome = catch_unwind(AssertUnwindSafe(|| eval_number(engine, input)));
+    match outcome {
+        Ok(Ok(n)) => {
+            if let Some(result) = result.as_mut() {
+                *result = n;
+            }
+            report(error, Failure::new(CALC_OK, 0, 0, ""))
+        }
+        Ok(Err(f)) => report(error, f),
+        Err(_) => report(
+            error,
+            Failure::new(CALC_ERR_INTERNAL, 0, input.len(), "internal error"),
+        ),
+    }
+}
+
+fn eval_number(engine: &mut Engine, input: &str) -> Result<f64, Failure> {
+    let v = engine
+        .eval(input)
+        .map_err(|e| Failure::from_error(&e, input))?;
+    let n = match &v {
+        Value::Number(n) => *n,
+        v => {
+            let message = format!("result is a {}, not a number", v.type_name());
+            return Err(Failure::new(CALC_ERR_NOT_A_NUMBER, 0, input.len(), message));
+        }
+    };
+    engine.env_mut().push_history(v);
+    Ok(n)
+}
+
+// error に書き込んで code を返す。メッセージは文字の境目で切り詰める
+unsafe fn report(error: *mut CalcError, f: Failure) -> c_int {
+    if let Some(error) = error.as_mut() {
+        let mut len = f.message.len().min(CALC_MESSAGE_LEN - 1);
+        while !f.message.is_char_boundary(len) {
+            len -= 1;
+        }
+        for (dst, src) in error.message.iter_mut().zip(&f.message.as_bytes()[..len]) {
+            *dst = *src as c_char;
+        }
+        error.message[len] = 0;
+        error.code = f.code;
+        error.start = f.start;
+        error.end = f.end;
+    }
+    f.code
+}
+
+// include/calculator.h の内容。定数は上の定義から、構造体と関数の宣言は Rust 側の実際の型から作る
+#[cfg(test)]
+fn header() -> String {
+    let mut out = String::from(
+        "/* Generated by calculator::capi::header(); do not edit. */\n\
+         #ifndef CALCULATOR_H\n\
+         #define CALCULATOR_H\n\
+         \n\
+         #include <stddef.h>\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {\n\
+         #endif\n\
+         \n",
+    );
+
+    for (name, value, help) in CODES {
+        out.push_str(&format!("#define {} {} /* {} */\n", name, value, help));
+    }
+    out.push_str(&format!(
+        "\n#define CALC_MESSAGE_LEN {}\n\n",
+        CALC_MESSAGE_LEN
+    ));
+
+    out.push_str(
+        "/* When code is not CALC_OK, [start, end) are byte offsets of the error in the input. */\n\
+         typedef struct calc_error {\n",
+    );
+    for field in error_fields() {
+        out.push_str(&format!("    {};\n", field));
+    }
+    out.push_str(
+        "} calc_error;\n\
+         \n\
+         /* Keeps variables and the result history between evaluations. */\n\
+         typedef struct calc_session calc_session;\n",
+    );
+
+    let functions = [
+        (
+            "Evaluates one statement in a fresh session. result and error may be NULL.",
+            declare(
+                "calc_eval",
+                calc_eval as unsafe extern "C" fn(_, _, _) -> _,
+                &["input", "result", "error"],
+            ),
+        ),
+        (
+            "Creates a session; free it with calc_session_free.",
+            declare(
+                "calc_session_new",
+                calc_session_new as extern "C" fn() -> _,
+                &[],
+            ),
+        ),
+        (
+            "Frees a session. session may be NULL.",
+            declare(
+                "calc_session_free",
+                calc_session_free as unsafe extern "C" fn(_),
+                &["session"],
+            ),
+        ),
+        (
+            "Evaluates one statement in session, keeping variables and history.",
+            declare(
+                "calc_session_eval",
+                calc_session_eval as unsafe extern "C" fn(_, _, _, _) -> _,
+                &["session", "input", "result", "error"],
+            ),
+        ),
+        (
+            "The NUL-terminated message stored in error; \"\" when error is NULL.",
+            declare(
+                "calc_error_message",
+                calc_error_message as unsafe extern "C" fn(_) -> _,
+                &["error"],
+            ),
+        ),
+    ];
+    for (help, prototype) in functions {
+        out.push_str(&format!("\n/* {} */\n{};\n", help, prototype));
+    }
+
+    out.push_str(
+        "\n\
+         #ifdef __cplusplus\n\
+         }\n\
+         #endif\n\
+         \n\
+         #endif /* CALCULATOR_H */\n",
+    );
+    out
+}
+
+// ヘッダーに書く C の型。宣言する名前を受け取り `const char *input` のように書く
+#[cfg(test)]
+trait CType {
+    const NAME: &'static str;
+
+    fn declare(name: &str) -> String {
+        match Self::NAME.ends_with('*') {
+            true => format!("{}{}", Self::NAME, name),
+            false => format!("{} {}", Self::NAME, name),
+        }
+    }
+}
+
+#[cfg(test)]
+macro_rules! c_types {
+    ($($t:ty => $name:literal),* $(,)?) => {
+        $(impl CType for $t {
+            const NAME: &'static str = $name;
+        })*
+    };
+}
+
+#[cfg(test)]
+c_types! {
+    () => "void",
+    c_int => "int",
+    usize => "size_t",
+    *const c_char => "const char *",
+    *mut f64 => "double *",
+    *mut CalcError => "calc_error *",
+    *const CalcError => "const calc_error *",
+    *mut CalcSession => "calc_session *",
+}
+
+// 長さは CALC_MESSAGE_LEN と一致していなければならない
+#[cfg(test)]
+impl CType for [c_char; CALC_MESSAGE_LEN] {
+    const NAME: &'static str = "char";
+
+    fn declare(name: &str) -> String {
+        format!("char {}[CALC_MESSAGE_LEN]", name)
+    }
+}
+
+// 関数ポインタの型から C のプロトタイプを作る。引数の名前の数は型の引数の数と一致させる
+#[cfg(test)]
+trait CFunction {
+    fn prototype(name: &str, params: &[&str]) -> String;
+}
+
+#[cfg(test)]
+macro_rules! c_functions {
+    ($($arg:ident),*) => {
+        impl<R: CType, $($arg: CType),*> CFunction for extern "C" fn($($arg),*) -> R {
+            fn prototype(name: &str, params: &[&str]) -> String {
+                let args: &[fn(&str) -> String] = &[$(<$arg as CType>::declare),*];
+                prototype::<R>(name, args, params)
+            }
+        }
+
+        impl<R: CType, $($arg: CType),*> CFunction for unsafe extern "C" fn($($arg),*) -> R {
+            fn prototype(name: &str, params: &[&str]) -> String {
+                let args: &[fn(&str) -> String] = &[$(<$arg as CType>::declare),*];
+                prototype::<R>(name, args, params)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+c_functions!();
+#[cfg(test)]
+c_functions!(A);
+#[cfg(test)]
+c_functions!(A, B, C);
+#[cfg(test)]
+c_functions!(A, B, C, D);
+
+#[cfg(test)]
+fn declare<F: CFunction>(name: &str, _: F, params: &[&str]) -> String {
+    F::prototype(name, params)
+}
+
+// 80 桁を超える宣言は引数の区切りで折り返し、続きの行を `(` の次にそろえる
+#[cfg(test)]
+fn prototype<R: CType>(name: &str, args: &[fn(&str) -> String], params: &[&str]) -> String {
+    assert_eq!(args.len(), params.len(), "parameter names of {}", name);
+    let head = R::declare(&format!("{}(", name));
+    let args: Vec<String> = match args.len() {
+        0 => vec!["void".to_string()],
+        _ => args.iter().zip(params).map(|(arg, p)| arg(p)).collect(),
+    };
+
+    let indent = " ".repeat(head.len());
+    let mut out = head;
+    let mut line = out.len();
+    for (i, arg) in args.iter().enumerate() {
+        let last = i + 1 == args.len();
+        let piece = format!("{}{}", arg, if last { ")" } else { ", " });
+        if i > 0 && line + piece.trim_end().len() + usize::from(last) > 80 {
+            out = out.trim_end().to_string();
+            out.push('\n');
+            out.push_str(&indent);
+            line = indent.len();
+        }
+        line += piece.len();
+        out.push_str(&piece);
+    }
+    out
+}
+
+// calc_error のメンバーを宣言の順に。位置と大きさで漏れや並びの違いを調べる
+#[cfg(test)]
+fn error_fields() -> Vec<String> {
+    use std::mem::{offset_of, size_of};
+
+    fn field<T: CType>(_: impl Fn(&CalcError) -> &T, name: &str) -> (String, usize) {
+        (T::declare(name), size_of::<T>())
+    }
+
+    macro_rules! fields {
+        ($($f:ident),*) => {
+            [$((offset_of!(CalcError, $f), field(|e| &e.$f, stringify!($f)))),*]
+        };
+    }
+
+    let fields = fields!(code, start, end, message);
+    let mut end = 0;
+    for (offset, (decl, size)) in &fields {
+        assert!(*offset >= end, "calc_error fields out of order at {}", decl);
+        end = offset + size;
+    }
+    let align = std::mem::align_of::<CalcError>();
+    assert_eq!(
+        end.next_multiple_of(align),
+        size_of::<CalcError>(),
+        "calc_error has fields missing from the header"
+    );
+    fields.into_iter().map(|(_, (decl, _))| decl).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::MaybeUninit;
+
+    fn eval(session: *mut CalcSession, input: &CStr) -> (c_int, f64, CalcError) {
+        let mut result = 0.0;
+        let mut error = unsafe { MaybeUninit::<CalcError>::zeroed().assume_init() };
+        let code = unsafe { calc_session_eval(session, input.as_ptr(), &mut result, &mut error) };
+        (code, result, error)
+    }
+
+    fn message(error: &CalcError) -> String {
+        unsafe { CStr::from_ptr(calc_error_message(error)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_capi_session() {
+        let session = calc_session_new();
+
+        assert_eq!(eval(session, c"x = 2 * 3").0, CALC_OK);
+        let (code, result, _) = eval(session, c"x + ans");
+        assert_eq!((code, result), (CALC_OK, 12.0));
+
+        let (code, _, error) = eval(session, c"1 + y");
+        assert_eq!(code, CALC_ERR_EVAL);
+        assert_eq!((error.start, error.end), (4, 5));
+        assert_eq!(message(&error), "undefined variable 'y'");
+
+        let (code, _, error) = eval(session, c"[1, 2]");
+        assert_eq!(code, CALC_ERR_NOT_A_NUMBER);
+        assert_eq!(message(&error), "result is a vector, not a number");
+
+        unsafe { calc_session_free(session) };
+        assert_eq!(
+            unsafe { calc_eval(std::ptr::null(), std::ptr::null_mut(), std::ptr::null_mut()) },
+            CALC_ERR_INVALID_ARGUMENT
+        );
+    }
+
+    #[test]
+    fn test_capi_header_is_current() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/include/calculator.h");
+        // CALC_UPDATE_HEADER=1 cargo test で書き直す
+        if std::env::var_os("CALC_UPDATE_HEADER").is_some() {
+            std::fs::write(path, header()).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(path).unwrap(), header());
+    }
+}
@@ -0,0 +1,253 @@
+use crate::eval::{find_function, Env, EvalError, EvalErrorKind, Value, SOLVE};
+use crate::parser::{Ast, AstKind, BinOpKind, UniOpKind};
+use crate::token::*;
+use crate::value::factorial;
+
+type Closure = Box<dyn Fn(&[f64]) -> Result<f64, EvalError>>;
+
+// 変数を引数スロットに解決し、クロージャの木に変換した式
+pub struct Compiled {
+    params: Vec<String>,
+    loc: Location,
+    f: Closure,
+}
+
+impl std::fmt::Debug for Compiled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Compiled")
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Compiled {
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.params.iter().position(|p| p == name)
+    }
+
+    // args は params と同じ順に並べる
+    pub fn call(&self, args: &[f64]) -> Result<f64, EvalError> {
+        if args.len() != self.params.len() {
+            return Err(EvalError::argument_count(
+                self.params.len(),
+                args.len(),
+                self.loc.clone(),
+            ));
+        }
+        (self.f)(args)
+    }
+}
+
+// params にない変数は env の値を定数として埋め込む
+pub fn compile(ast: &Ast, params: &[&str], env: &Env) -> Result<Compiled, EvalError> {
+    let f = compile_node(ast, params, env)?;
+    Ok(Compiled {
+        params: params.iter().map(|p| p.to_string()).collect(),
+        loc: ast.loc(),
+        f,
+    })
+}
+
+fn compile_node(ast: &Ast, params: &[&str], env: &Env) -> Result<Closure, EvalError> {
+    match &ast.value {
+        AstKind::Num(n) => {
+            let n = *n;
+            Ok(Box::new(move |_| Ok(n)))
+        }
+        AstKind::Var(name) => {
+            if let Some(i) = params.iter().position(|p| p == name) {
+                return Ok(Box::new(move |args| Ok(args[i])));
+            }
+            constant(env.resolve(name), ast)
+        }
+        AstKind::History(n) => constant(env.history_ref(*n), ast),
+        AstKind::Assign { .. } => Err(EvalError::unsupported("assignment", ast.loc())),
+        AstKind::Equation { .. } => Err(EvalError::unsupported("equation", ast.loc())),
+        AstKind::Imag(_) => Err(EvalError::unsupported("complex number", ast.loc())),
+        AstKind::Vector(_) => Err(EvalError::unsupported("vector", ast.loc())),
+        AstKind::Call { name, .. } if name == SOLVE => {
+            Err(EvalError::unsupported("solve", ast.loc()))
+        }
+        AstKind::Call { name, args } => {
+            let builtin = find_function(name, args.len(), ast.loc())?;
+            let Some(f) = scalar_builtin(builtin.name) else {
+                return Err(EvalError::unsupported(
+                    &format!("'{}'", builtin.name),
+                    ast.loc(),
+                ));
+            };
+            let e = compile_node(&args[0], params, env)?;
+            let loc = ast.loc();
+            Ok(Box::new(move |args| {
+                f(e(args)?).ok_or_else(|| {
+                    EvalError::new(EvalErrorKind::Domain(builtin.name.to_string()), loc.clone())
+                })
+            }))
+        }
+        AstKind::UniOp { op, e } => {
+            let e = compile_node(e, params, env)?;
+            match op.value {
+                UniOpKind::Plus => Ok(e),
+                UniOpKind::Minus => Ok(Box::new(move |args| Ok(-e(args)?))),
+                UniOpKind::Percent => Ok(Box::new(move |args| Ok(e(args)? / 100.0))),
+                UniOpKind::Factorial => {
+                    let loc = op.loc();
+                    Ok(Box::new(move |args| {
+                        factorial(e(args)?).ok_or_else(|| {
+                            EvalError::new(EvalErrorKind::Domain("!".to_string()), loc.clone())
+                        })
+                    }))
+                }
+            }
+        }
+        AstKind::BinOp { op, l, r } => {
+            let l = compile_node(l, params, env)?;
+            let r = compile_node(r, params, env)?;
+            match op.value {
+                BinOpKind::Add => Ok(Box::new(move |args| Ok(l(args)? + r(args)?))),
+                BinOpKind::Sub => Ok(Box::new(move |args| Ok(l(args)? - r(args)?))),
+                BinOpKind::Mul => Ok(Box::new(move |args| Ok(l(args)? * r(args)?))),
+                BinOpKind::MatMul => Err(EvalError::unsupported("'@'", op.loc())),
+                BinOpKind::Pow => {
+                    let loc = op.loc();
+                    Ok(Box::new(move |args| {
+                        let (x, y) = (l(args)?, r(args)?);
+                        let z = x.powf(y);
+                        if z.is_nan() && !x.is_nan() && !y.is_nan() {
+                            return Err(EvalError::new(
+                                EvalErrorKind::Domain("^".to_string()),
+                                loc.clone(),
+                            ));
+                        }
+                        Ok(z)
+                    }))
+                }
+                BinOpKind::Div => {
+                    let loc = op.loc();
+                    // 解釈実行と同じく左、右の順に評価してから 0 を調べる
+                    Ok(Box::new(move |args| {
+                        let l = l(args)?;
+                        let r = r(args)?;
+                        if r == 0.0 {
+                            return Err(EvalError::division_by_zero(loc.clone()));
+                        }
+                        Ok(l / r)
+                    }))
+                }
+            }
+        }
+    }
+}
+
+// 実数を取って実数を返す組み込み関数。None は定義域の外で、実数モードの評価と同じく誤りにする
+fn scalar_builtin(name: &str) -> Option<fn(f64) -> Option<f64>> {
+    match name {
+        "abs" => Some(|x| Some(x.abs())),
+        "sqrt" => Some(|x| (x >= 0.0).then(|| x.sqrt())),
+        "arg" => Some(|x| Some(0f64.atan2(x))),
+        "conj" => Some(Some),
+        _ => None,
+    }
+}
+
+fn constant(value: Result<Value, EvalErrorKind>, ast: &Ast) -> Result<Closure, EvalError> {
+    match value {
+        Ok(Value::Number(n)) => Ok(Box::new(move |_| Ok(n))),
+        Ok(v) => Err(EvalError::unsupported(v.type_name(), ast.loc())),
+        Err(kind) => Err(EvalError::new(kind, ast.loc())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_in;
+
+    #[test]
+    fn test_compile_matches_eval() {
+        let ast = "a * x^2 + b - x / 4".parse::<Ast>().unwrap();
+        let compiled = compile(&ast, &["x", "a", "b"], &Env::new()).unwrap();
+
+        for x in [-2.0, 0.5, 3.0, 100.0] {
+            let mut env = Env::new();
+            env.set("x", Value::Number(x));
+            env.set("a", Value::Number(1.5));
+            env.set("b", Value::Number(-7.0));
+
+            assert_eq!(
+                compiled.call(&[x, 1.5, -7.0]).map(Value::Number),
+                eval_in(&ast, &mut env)
+            );
+        }
+        assert_eq!(compiled.slot("b"), Some(2));
+
+        let ast = "sqrt(x) + abs(x - 3) * arg(x - 1)".parse::<Ast>().unwrap();
+        let compiled = compile(&ast, &["x"], &Env::new()).unwrap();
+        for x in [0.0, 0.5, 2.0, 9.0] {
+            let mut env = Env::new();
+            env.set("x", Value::Number(x));
+            assert_eq!(
+                compiled.call(&[x]).map(Value::Number),
+                eval_in(&ast, &mut env)
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let mut env = Env::new();
+        env.set("k", Value::Number(10.0));
+
+        let ast = "k / x".parse::<Ast>().unwrap();
+        let compiled = compile(&ast, &["x"], &env).unwrap();
+        assert_eq!(compiled.call(&[4.0]), Ok(2.5));
+        assert_eq!(
+            compiled.call(&[0.0]).map_err(|e| e.value),
+            Err(EvalErrorKind::DivisionByZero)
+        );
+        assert!(compiled.call(&[]).is_err());
+
+        let ast = "x + y".parse::<Ast>().unwrap();
+        assert_eq!(
+            compile(&ast, &["x"], &env).map(|_| ()).map_err(|e| e.value),
+            Err(EvalErrorKind::UndefinedVariable("y".to_string()))
+        );
+
+        let ast = "sqrt(x)".parse::<Ast>().unwrap();
+        let compiled = compile(&ast, &["x"], &env).unwrap();
+        assert_eq!(
+            compiled.call(&[-1.0]).map_err(|e| e.value),
+            Err(EvalErrorKind::Domain("sqrt".to_string()))
+        );
+        // 両辺が誤りになるときは解釈実行と同じく左辺の誤りを返す
+        let ast = "sqrt(x) / sqrt(y - 1)".parse::<Ast>().unwrap();
+        let compiled = compile(&ast, &["x", "y"], &env).unwrap();
+        let mut eval_env = Env::new();
+        eval_env.set("x", Value::Number(-1.0));
+        eval_env.set("y", Value::Number(1.0));
+        assert_eq!(
+            compiled.call(&[-1.0, 1.0]).map(Value::Number),
+            eval_in(&ast, &mut eval_env)
+        );
+        assert_eq!(
+            compiled.call(&[-1.0, 1.0]),
+            Err(EvalError::new(
+                EvalErrorKind::Domain("sqrt".to_string()),
+                Location::new(0, 7)
+            ))
+        );
+        assert_eq!(
+            compiled.call(&[-1.0, -1.0]).map_err(|e| e.loc()),
+            Err(Location::new(0, 7))
+        );
+        let ast = "det(x)".parse::<Ast>().unwrap();
+        assert_eq!(
+            compile(&ast, &["x"], &env).map(|_| ()).map_err(|e| e.value),
+            Err(EvalErrorKind::Unsupported("'det'".to_string()))
+        );
+    }
+}
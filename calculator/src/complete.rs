@@ -0,0 +1,199 @@
+use crate::builtin::builtins;
+use crate::engine::Engine;
+use crate::eval::SOLVE;
+use crate::lexer::lexer_lossless;
+use crate::repl::split_command;
+use crate::token::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandidateKind {
+    Function,
+    Variable,
+    Command,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Candidate {
+    pub text: String,
+    pub kind: CandidateKind,
+}
+
+// start から cursor までを候補のどれかで置き換える。位置はバイト単位
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Completion {
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+// cursor の直前の名前を補完する。`:` で始まる行ではコマンド名を、引数では式の名前を補う。
+// 演算子を待っている位置(数値や `)` の直後)では、暗黙の乗算が有効でなければ候補を出さない
+pub fn complete(engine: &Engine, commands: &[&str], line: &str, cursor: usize) -> Completion {
+    let cursor = floor_char_boundary(line, cursor.min(line.len()));
+
+    let Some(command) = line.strip_prefix(':') else {
+        return complete_expr(engine, line, cursor, 0);
+    };
+
+    // `:` の前には補うものがない
+    if cursor == 0 {
+        return Completion::default();
+    }
+    let (name, _) = split_command(command);
+    if cursor <= 1 + name.len() {
+        let prefix = &line[1..cursor];
+        let candidates = commands
+            .iter()
+            .filter(|c| c.starts_with(prefix))
+            .map(|c| Candidate {
+                text: c.to_string(),
+                kind: CandidateKind::Command,
+            })
+            .collect();
+        return Completion {
+            start: 1,
+            candidates,
+        };
+    }
+
+    // 引数の前の空白にカーソルがあれば引数の先頭から補う
+    let rest = &command[name.len()..];
+    let offset = 1 + name.len() + rest.len() - rest.trim_start().len();
+    let cursor = cursor.max(offset);
+    complete_expr(engine, &line[offset..], cursor - offset, offset)
+}
+
+fn complete_expr(engine: &Engine, line: &str, cursor: usize, offset: usize) -> Completion {
+    let none = Completion {
+        start: offset + cursor,
+        candidates: vec![],
+    };
+
+    let Ok((tokens, trailing)) = lexer_lossless(&line[..cursor]) else {
+        return none;
+    };
+    if trailing
+        .iter()
+        .any(|t| matches!(t.value, TriviaKind::Comment(_)))
+    {
+        return none;
+    }
+
+    let (prefix, start, before) = match tokens.split_last() {
+        Some((last, rest)) if matches!(last.kind(), TokenKind::Ident(_)) && trailing.is_empty() => {
+            (last.text.as_str(), last.loc().start(), rest.last())
+        }
+        _ => ("", cursor, tokens.last()),
+    };
+
+    let operand_ended = before.is_some_and(|t| {
+        matches!(
+            t.kind(),
+            TokenKind::Number(_)
+                | TokenKind::Imaginary(_)
+                | TokenKind::Ident(_)
+                | TokenKind::History(_)
+                | TokenKind::RParen
+                | TokenKind::RBracket
+                | TokenKind::Bang
+                | TokenKind::Percent
+        )
+    });
+    if operand_ended && !engine.parser().implicit_mul() {
+        return none;
+    }
+
+    let mut candidates: Vec<Candidate> = engine
+        .env()
+        .vars()
+        .into_iter()
+        .map(|(name, _)| name.to_string())
+        .chain(
+            (!engine.env().history().is_empty() && engine.get("ans").is_none())
+                .then(|| "ans".to_string()),
+        )
+        .filter(|name| name.starts_with(prefix))
+        .map(|text| Candidate {
+            text,
+            kind: CandidateKind::Variable,
+        })
+        .collect();
+
+    // 後ろに `(` が続いていなければ関数名に付けて補う
+    let paren = if line[cursor..].trim_start().starts_with('(') {
+        ""
+    } else {
+        "("
+    };
+    candidates.extend(
+        builtins()
+            .iter()
+            .map(|b| b.name)
+            .chain([SOLVE])
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Candidate {
+                text: format!("{}{}", name, paren),
+                kind: CandidateKind::Function,
+            }),
+    );
+
+    Completion {
+        start: offset + start,
+        candidates,
+    }
+}
+
+fn floor_char_boundary(s: &str, mut i: usize) -> usize {
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    fn texts(c: &Completion) -> Vec<&str> {
+        c.candidates.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_complete_names() {
+        let mut engine = Engine::new();
+        engine.set("delta", Value::Number(1.0));
+
+        let c = complete(&engine, &[], "1 + de", 6);
+        assert_eq!(c.start, 4);
+        assert_eq!(texts(&c), vec!["delta", "det("]);
+        assert_eq!(c.candidates[1].kind, CandidateKind::Function);
+
+        // 直後に `(` があれば付けない
+        assert_eq!(
+            texts(&complete(&engine, &[], "de([1])", 2)),
+            vec!["delta", "det"]
+        );
+        // 数値の後ろは演算子を待っている
+        assert!(complete(&engine, &[], "2 de", 4).candidates.is_empty());
+        assert!(complete(&engine, &[], "1 # de", 6).candidates.is_empty());
+
+        engine.parser_mut().set_implicit_mul(true);
+        assert_eq!(texts(&complete(&engine, &[], "2 del", 5)), vec!["delta"]);
+    }
+
+    #[test]
+    fn test_complete_commands() {
+        let mut engine = Engine::new();
+        engine.env_mut().push_history(Value::Number(3.0));
+        let commands = ["tokens", "trace", "type", "quit"];
+
+        let c = complete(&engine, &commands, ":t", 2);
+        assert_eq!(c.start, 1);
+        assert_eq!(texts(&c), vec!["tokens", "trace", "type"]);
+        assert_eq!(complete(&engine, &commands, ":t", 0), Completion::default());
+
+        let c = complete(&engine, &commands, ":trace 1 + a", 12);
+        assert_eq!(c.start, 11);
+        assert_eq!(texts(&c), vec!["ans", "abs(", "arg("]);
+    }
+}
@@ -0,0 +1,177 @@
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const I: Complex = Complex { re: 0.0, im: 1.0 };
+
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    pub fn from_polar(r: f64, theta: f64) -> Self {
+        Self::new(r * theta.cos(), r * theta.sin())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.re == 0.0 && self.im == 0.0
+    }
+
+    pub fn abs(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(&self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    // 主値(実部が非負になる方)。負の実数の平方根が純虚数になるよう極形式は使わない
+    pub fn sqrt(&self) -> Self {
+        let r = self.abs();
+        let re = ((r + self.re) / 2.0).sqrt();
+        let im = ((r - self.re) / 2.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    pub fn add(&self, other: &Complex) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+
+    pub fn sub(&self, other: &Complex) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+
+    pub fn mul(&self, other: &Complex) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+
+    pub fn div(&self, other: &Complex) -> Self {
+        let d = other.re * other.re + other.im * other.im;
+        Self::new(
+            (self.re * other.re + self.im * other.im) / d,
+            (self.im * other.re - self.re * other.im) / d,
+        )
+    }
+
+    // 整数乗は掛け算を繰り返して求め、`i^2` がちょうど -1 になるようにする
+    pub fn powf(&self, n: f64) -> Self {
+        if n.fract() == 0.0 && n.abs() <= 1024.0 {
+            let mut ret = Self::new(1.0, 0.0);
+            let mut base = *self;
+            let mut k = n.abs() as u32;
+            while k > 0 {
+                if k & 1 == 1 {
+                    ret = ret.mul(&base);
+                }
+                base = base.mul(&base);
+                k >>= 1;
+            }
+            return if n < 0.0 {
+                Self::new(1.0, 0.0).div(&ret)
+            } else {
+                ret
+            };
+        }
+        if self.is_zero() {
+            return Self::new(0f64.powf(n), 0.0);
+        }
+        Self::from_polar(self.abs().powf(n), self.arg() * n)
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::new(-self.re, -self.im)
+    }
+
+    pub(crate) fn format(&self, format: ComplexFormat) -> String {
+        self.format_with(format, &|x| x.to_string())
+    }
+
+    // 実数部分の表記を num に任せる。
+    // 直交形式では 0 の部分は省く: `3 - 4i`, `1i`, `2`。
+    // 単独の `i` は名前として読まれるので、虚部の係数 1 も書いて入力に戻せるようにする
+    pub(crate) fn format_with(&self, format: ComplexFormat, num: &dyn Fn(f64) -> String) -> String {
+        if format == ComplexFormat::Polar {
+            return format!("{}∠{}", num(self.abs()), num(self.arg()));
+        }
+
+        let imag = |im: f64| format!("{}i", num(im));
+        match (self.re, self.im) {
+            (re, 0.0) => num(re),
+            (0.0, im) => imag(im),
+            (re, im) if im < 0.0 => format!("{} - {}", num(re), imag(-im)),
+            (re, im) => format!("{} + {}", num(re), imag(im)),
+        }
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Self {
+        Self::new(re, 0.0)
+    }
+}
+
+impl std::fmt::Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.format(ComplexFormat::Rect))
+    }
+}
+
+// 複素数の表示形式。直交形式 `a + bi` か極形式 `r∠θ`(θ はラジアン)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ComplexFormat {
+    #[default]
+    Rect,
+    Polar,
+}
+
+impl FromStr for ComplexFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rect" => Ok(ComplexFormat::Rect),
+            "polar" => Ok(ComplexFormat::Polar),
+            _ => Err(format!("unknown complex format: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complex_arith() {
+        let a = Complex::new(3.0, 4.0);
+        let b = Complex::new(1.0, -2.0);
+
+        assert_eq!(a.mul(&b), Complex::new(11.0, -2.0));
+        assert_eq!(a.mul(&b).div(&b), a);
+        assert_eq!(a.abs(), 5.0);
+        assert_eq!(a.conj(), Complex::new(3.0, -4.0));
+        assert_eq!(Complex::from(-4.0).sqrt(), Complex::new(0.0, 2.0));
+        assert_eq!(Complex::new(3.0, -4.0).sqrt(), Complex::new(2.0, -1.0));
+    }
+
+    #[test]
+    fn test_complex_display() {
+        assert_eq!(Complex::new(11.0, -2.0).to_string(), "11 - 2i");
+        assert_eq!(Complex::I.to_string(), "1i");
+        assert_eq!(Complex::new(0.0, -1.0).to_string(), "-1i");
+        assert_eq!(Complex::new(2.5, 0.0).to_string(), "2.5");
+        assert_eq!(
+            Complex::new(0.0, 2.0).format(ComplexFormat::Polar),
+            format!("2∠{}", std::f64::consts::FRAC_PI_2)
+        );
+    }
+}
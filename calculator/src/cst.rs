@@ -0,0 +1,421 @@
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::lexer::lexer_lossless;
+use crate::parser::{Ast, BinOp, Parser, UniOp};
+use crate::token::*;
+
+// 括弧やトリビアを含め、ソースをそのまま復元できる構文木
+#[derive(Debug, Clone, PartialEq)]
+pub enum CstKind {
+    Num(CstToken),
+    Var(CstToken),
+    Assign {
+        name: CstToken,
+        eq: CstToken,
+        e: Box<CstNode>,
+    },
+    Equation {
+        l: Box<CstNode>,
+        eq: CstToken,
+        r: Box<CstNode>,
+    },
+    Paren {
+        open: CstToken,
+        e: Box<CstNode>,
+        close: CstToken,
+    },
+    Prefix {
+        op: UniOp,
+        token: CstToken,
+        e: Box<CstNode>,
+    },
+    Postfix {
+        op: UniOp,
+        token: CstToken,
+        e: Box<CstNode>,
+    },
+    BinOp {
+        op: BinOp,
+        token: CstToken,
+        l: Box<CstNode>,
+        r: Box<CstNode>,
+    },
+    // 演算子なしで並べた項の積。op の位置は両辺にまたがる
+    Implicit {
+        op: BinOp,
+        l: Box<CstNode>,
+        r: Box<CstNode>,
+    },
+    // 要素の間の区切りは commas に持つ(items.len() - 1 個)
+    Vector {
+        open: CstToken,
+        items: Vec<CstNode>,
+        commas: Vec<CstToken>,
+        close: CstToken,
+    },
+    Call {
+        name: CstToken,
+        open: CstToken,
+        args: Vec<CstNode>,
+        commas: Vec<CstToken>,
+        close: CstToken,
+    },
+}
+
+pub type CstNode = Annotation<CstKind>;
+
+impl CstNode {
+    pub fn num(token: CstToken) -> Self {
+        let loc = token.loc();
+        Self::new(CstKind::Num(token), loc)
+    }
+
+    pub fn var(token: CstToken) -> Self {
+        let loc = token.loc();
+        Self::new(CstKind::Var(token), loc)
+    }
+
+    pub fn assign(name: CstToken, eq: CstToken, e: CstNode) -> Self {
+        let loc = name.loc().merge(&e.loc());
+        Self::new(
+            CstKind::Assign {
+                name,
+                eq,
+                e: Box::new(e),
+            },
+            loc,
+        )
+    }
+
+    pub fn equation(l: CstNode, eq: CstToken, r: CstNode) -> Self {
+        let loc = l.loc().merge(&r.loc());
+        Self::new(
+            CstKind::Equation {
+                l: Box::new(l),
+                eq,
+                r: Box::new(r),
+            },
+            loc,
+        )
+    }
+
+    pub fn paren(open: CstToken, e: CstNode, close: CstToken) -> Self {
+        let loc = open.loc().merge(&close.loc());
+        Self::new(
+            CstKind::Paren {
+                open,
+                e: Box::new(e),
+                close,
+            },
+            loc,
+        )
+    }
+
+    pub fn prefix(op: UniOp, token: CstToken, e: CstNode) -> Self {
+        let loc = token.loc().merge(&e.loc());
+        Self::new(
+            CstKind::Prefix {
+                op,
+                token,
+                e: Box::new(e),
+            },
+            loc,
+        )
+    }
+
+    pub fn postfix(op: UniOp, token: CstToken, e: CstNode) -> Self {
+        let loc = e.loc().merge(&token.loc());
+        Self::new(
+            CstKind::Postfix {
+                op,
+                token,
+                e: Box::new(e),
+            },
+            loc,
+        )
+    }
+
+    pub fn binop(op: BinOp, token: CstToken, l: CstNode, r: CstNode) -> Self {
+        let loc = l.loc().merge(&r.loc());
+        Self::new(
+            CstKind::BinOp {
+                op,
+                token,
+                l: Box::new(l),
+                r: Box::new(r),
+            },
+            loc,
+        )
+    }
+
+    pub fn implicit(op: BinOp, l: CstNode, r: CstNode) -> Self {
+        let loc = l.loc().merge(&r.loc());
+        Self::new(
+            CstKind::Implicit {
+                op,
+                l: Box::new(l),
+                r: Box::new(r),
+            },
+            loc,
+        )
+    }
+
+    pub fn vector(
+        open: CstToken,
+        items: Vec<CstNode>,
+        commas: Vec<CstToken>,
+        close: CstToken,
+    ) -> Self {
+        let loc = open.loc().merge(&close.loc());
+        Self::new(
+            CstKind::Vector {
+                open,
+                items,
+                commas,
+                close,
+            },
+            loc,
+        )
+    }
+
+    pub fn call(
+        name: CstToken,
+        open: CstToken,
+        args: Vec<CstNode>,
+        commas: Vec<CstToken>,
+        close: CstToken,
+    ) -> Self {
+        let loc = name.loc().merge(&close.loc());
+        Self::new(
+            CstKind::Call {
+                name,
+                open,
+                args,
+                commas,
+                close,
+            },
+            loc,
+        )
+    }
+
+    // 括弧とトリビアを捨てて抽象構文木に落とす
+    pub fn to_ast(&self) -> Ast {
+        match &self.value {
+            CstKind::Num(t) => match t.kind() {
+                TokenKind::Number(n) => Ast::num(n, t.loc()),
+                TokenKind::Imaginary(n) => Ast::imag(n, t.loc()),
+                _ => unreachable!("number node must hold a number token"),
+            },
+            CstKind::Var(t) => match t.kind() {
+                TokenKind::History(n) => Ast::history(n, t.loc()),
+                _ => Ast::var(&t.text, t.loc()),
+            },
+            CstKind::Assign { name, e, .. } => {
+                let e = e.to_ast();
+                let loc = name.loc().merge(&e.loc());
+                Ast::assign(&name.text, e, loc)
+            }
+            CstKind::Equation { l, r, .. } => {
+                let l = l.to_ast();
+                let r = r.to_ast();
+                let loc = l.loc().merge(&r.loc());
+                Ast::equation(l, r, loc)
+            }
+            CstKind::Paren { e, .. } => e.to_ast(),
+            CstKind::Prefix { op, e, .. } | CstKind::Postfix { op, e, .. } => {
+                let e = e.to_ast();
+                let loc = op.loc().merge(&e.loc());
+                Ast::uniop(op.clone(), e, loc)
+            }
+            CstKind::BinOp { op, l, r, .. } | CstKind::Implicit { op, l, r } => {
+                let l = l.to_ast();
+                let r = r.to_ast();
+                let loc = l.loc().merge(&r.loc());
+                Ast::binop(op.clone(), l, r, loc)
+            }
+            CstKind::Vector {
+                open, items, close, ..
+            } => {
+                let items = items.iter().map(|e| e.to_ast()).collect();
+                Ast::vector(items, open.loc().merge(&close.loc()))
+            }
+            CstKind::Call {
+                name, args, close, ..
+            } => {
+                let args = args.iter().map(|e| e.to_ast()).collect();
+                Ast::call(&name.text, args, name.loc().merge(&close.loc()))
+            }
+        }
+    }
+
+    // ソース順にトークンを辿る
+    pub fn tokens(&self) -> Vec<&CstToken> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a CstToken>) {
+        match &self.value {
+            CstKind::Num(t) | CstKind::Var(t) => tokens.push(t),
+            CstKind::Assign { name, eq, e } => {
+                tokens.push(name);
+                tokens.push(eq);
+                e.collect_tokens(tokens);
+            }
+            CstKind::Equation { l, eq, r } => {
+                l.collect_tokens(tokens);
+                tokens.push(eq);
+                r.collect_tokens(tokens);
+            }
+            CstKind::Paren { open, e, close } => {
+                tokens.push(open);
+                e.collect_tokens(tokens);
+                tokens.push(close);
+            }
+            CstKind::Prefix { token, e, .. } => {
+                tokens.push(token);
+                e.collect_tokens(tokens);
+            }
+            CstKind::Postfix { token, e, .. } => {
+                e.collect_tokens(tokens);
+                tokens.push(token);
+            }
+            CstKind::BinOp { token, l, r, .. } => {
+                l.collect_tokens(tokens);
+                tokens.push(token);
+                r.collect_tokens(tokens);
+            }
+            CstKind::Implicit { l, r, .. } => {
+                l.collect_tokens(tokens);
+                r.collect_tokens(tokens);
+            }
+            CstKind::Vector {
+                open,
+                items,
+                commas,
+                close,
+            } => {
+                tokens.push(open);
+                collect_list(items, commas, tokens);
+                tokens.push(close);
+            }
+            CstKind::Call {
+                name,
+                open,
+                args,
+                commas,
+                close,
+            } => {
+                tokens.push(name);
+                tokens.push(open);
+                collect_list(args, commas, tokens);
+                tokens.push(close);
+            }
+        }
+    }
+}
+
+fn collect_list<'a>(items: &'a [CstNode], commas: &'a [CstToken], tokens: &mut Vec<&'a CstToken>) {
+    for (i, e) in items.iter().enumerate() {
+        if i > 0 {
+            tokens.push(&commas[i - 1]);
+        }
+        e.collect_tokens(tokens);
+    }
+}
+
+impl std::fmt::Display for CstNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for t in self.tokens() {
+            t.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cst {
+    pub root: CstNode,
+    pub trailing: Vec<Trivia>,
+}
+
+impl Cst {
+    pub fn new(root: CstNode, trailing: Vec<Trivia>) -> Self {
+        Self { root, trailing }
+    }
+
+    pub fn to_ast(&self) -> Ast {
+        self.root.to_ast()
+    }
+}
+
+impl std::fmt::Display for Cst {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.root.fmt(f)?;
+        for t in &self.trailing {
+            t.value.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cst {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tokens, trailing) = lexer_lossless(s)?;
+        let cst = Parser::default().parse_cst(tokens, trailing)?;
+        Ok(cst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cst_roundtrip() {
+        let inputs = [
+            "1 + 2",
+            "  ( 1+2 ) *\t3  # comment\n",
+            "# head\n-(4) / ((2))\n",
+            "[ [1,2] ,[3 , 4]] @ det( m )",
+        ];
+
+        for input in inputs {
+            let cst = input.parse::<Cst>().unwrap();
+            assert_eq!(cst.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_cst_to_ast() {
+        let inputs = [
+            "1 + 2 * 3",
+            "(1 + 2) * 3",
+            "-(4) / ((2))",
+            "((1))",
+            "x = (y) * 2",
+            "solve(x^2 - 2 = 0, x)",
+            "[1, (2)] * inv([[1, 0], [0, 1]])",
+            "f()",
+        ];
+
+        for input in inputs {
+            let cst = input.parse::<Cst>().unwrap();
+            assert_eq!(cst.to_ast(), input.parse::<Ast>().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_cst_keeps_parens() {
+        let cst = "(1)".parse::<Cst>().unwrap();
+
+        match &cst.root.value {
+            CstKind::Paren { e, .. } => assert!(matches!(e.value, CstKind::Num(_))),
+            _ => panic!("expected paren"),
+        }
+        assert_eq!(cst.root.loc(), Location::new(0, 3));
+    }
+}
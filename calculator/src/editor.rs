@@ -0,0 +1,883 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, stdin, stdout, BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    Char(char),
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Esc,
+    Unknown,
+}
+
+// 端末から届くバイト列をキーに変換する。読みすぎた分は次回に回す
+pub struct KeyReader<R> {
+    inner: R,
+    pending: Vec<u8>,
+}
+
+impl<R: Read> KeyReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(b) = self.pending.pop() {
+            return Ok(Some(b));
+        }
+
+        let mut buf = [0u8; 1];
+        match self.inner.read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    pub fn read_key(&mut self) -> io::Result<Option<Key>> {
+        let Some(b) = self.next_byte()? else {
+            return Ok(None);
+        };
+
+        let key = match b {
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x1b => self.read_escape()?,
+            1..=26 => Key::Ctrl((b'a' + b - 1) as char),
+            // 残りの制御文字は Ctrl-@ と Ctrl-\ ] ^ _。文字として挿入しない
+            0 | 0x1c..=0x1f => Key::Ctrl((b + 0x40) as char),
+            0x20..=0x7e => Key::Char(b as char),
+            _ => self.read_utf8(b)?,
+        };
+
+        Ok(Some(key))
+    }
+
+    fn read_escape(&mut self) -> io::Result<Key> {
+        let Some(b) = self.next_byte()? else {
+            return Ok(Key::Esc);
+        };
+        if b != b'[' && b != b'O' {
+            self.pending.push(b);
+            return Ok(Key::Esc);
+        }
+
+        let Some(b) = self.next_byte()? else {
+            return Ok(Key::Unknown);
+        };
+        let key = match b {
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            b'0'..=b'9' => {
+                // ESC [ n ~ の形式
+                let mut n = (b - b'0') as u32;
+                loop {
+                    match self.next_byte()? {
+                        Some(d @ b'0'..=b'9') => n = n * 10 + (d - b'0') as u32,
+                        Some(b'~') => break,
+                        _ => return Ok(Key::Unknown),
+                    }
+                }
+                match n {
+                    1 | 7 => Key::Home,
+                    3 => Key::Delete,
+                    4 | 8 => Key::End,
+                    _ => Key::Unknown,
+                }
+            }
+            _ => Key::Unknown,
+        };
+
+        Ok(key)
+    }
+
+    fn read_utf8(&mut self, first: u8) -> io::Result<Key> {
+        let len = match first {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Ok(Key::Unknown),
+        };
+
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            match self.next_byte()? {
+                Some(b) => bytes.push(b),
+                None => return Ok(Key::Unknown),
+            }
+        }
+
+        Ok(std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .map(Key::Char)
+            .unwrap_or(Key::Unknown))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct LineBuffer {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    // 内容を置き換えてカーソルを末尾に置く
+    pub fn set(&mut self, s: &str) {
+        self.chars = s.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    // start からカーソルまでを s で置き換え、カーソルをその後ろに置く
+    pub fn replace(&mut self, start: usize, s: &str) {
+        let start = start.min(self.cursor);
+        let len = s.chars().count();
+        self.chars.splice(start..self.cursor, s.chars());
+        self.cursor = start + len;
+    }
+
+    pub fn kill_to_end(&mut self) {
+        self.chars.truncate(self.cursor);
+    }
+
+    pub fn kill_to_start(&mut self) {
+        self.chars.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    pub fn kill_word(&mut self) {
+        let end = self.cursor;
+        while self.cursor > 0 && self.chars[self.cursor - 1] == ' ' {
+            self.cursor -= 1;
+        }
+        while self.cursor > 0 && self.chars[self.cursor - 1] != ' ' {
+            self.cursor -= 1;
+        }
+        self.chars.drain(self.cursor..end);
+    }
+}
+
+// 入力履歴。path があれば追記で保存する
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct History {
+    entries: Vec<String>,
+    max: usize,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    pub fn new(max: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            max,
+            path: None,
+        }
+    }
+
+    // ファイルが無ければ空の履歴から始める
+    pub fn load(path: PathBuf, max: usize) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if !line.is_empty() {
+                        entries.push(line);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut history = Self {
+            entries,
+            max,
+            path: Some(path),
+        };
+        if history.entries.len() > max {
+            history.entries.drain(..history.entries.len() - max);
+            history.save()?;
+        }
+
+        Ok(history)
+    }
+
+    // $CALCULATOR_HISTORY か ~/.calculator_history
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("CALCULATOR_HISTORY")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".calculator_history"))
+            })
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.entries.get(i).map(|s| s.as_str())
+    }
+
+    pub fn add(&mut self, line: &str) -> io::Result<()> {
+        let line = line.trim_end();
+        if line.is_empty() || line.contains('\n') || self.entries.last().is_some_and(|l| l == line)
+        {
+            return Ok(());
+        }
+
+        self.entries.push(line.to_string());
+        if self.entries.len() > self.max {
+            self.entries.remove(0);
+        }
+
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut file = File::create(path)?;
+            for line in &self.entries {
+                writeln!(file, "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+
+    // before より前を新しい順に探す
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|e| e.contains(query))
+    }
+}
+
+// 表示用に行を飾る。返す文字列は見た目の幅が line と同じでなければならない
+pub trait Highlighter {
+    // cursor は文字単位の位置
+    fn highlight(&self, line: &str, cursor: usize) -> String;
+}
+
+// Tab で呼ばれる。返した開始位置からカーソルまでを候補で置き換える。位置はどれも文字単位
+pub trait Completer {
+    fn complete(&self, line: &str, cursor: usize) -> (usize, Vec<String>);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Input {
+    Line(String),
+    Interrupt,
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Continue,
+    Submit,
+    Interrupt,
+    Eof,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+struct Search {
+    query: String,
+    found: Option<usize>,
+}
+
+// 一行分の編集状態。端末とは切り離してキー入力だけで状態が変わる
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct EditState {
+    buf: LineBuffer,
+    index: usize,
+    draft: String,
+    search: Option<Search>,
+}
+
+impl EditState {
+    pub fn new(history: &History) -> Self {
+        Self {
+            index: history.len(),
+            ..Self::default()
+        }
+    }
+
+    pub fn buffer(&self) -> &LineBuffer {
+        &self.buf
+    }
+
+    pub fn handle(&mut self, key: Key, history: &History) -> Action {
+        if self.search.is_some() {
+            return self.handle_search(key, history);
+        }
+
+        match key {
+            Key::Enter => return Action::Submit,
+            Key::Ctrl('c') => return Action::Interrupt,
+            Key::Ctrl('d') if self.buf.is_empty() => return Action::Eof,
+            Key::Ctrl('d') | Key::Delete => self.buf.delete(),
+            Key::Char(c) => self.buf.insert(c),
+            Key::Backspace | Key::Ctrl('h') => self.buf.backspace(),
+            Key::Left | Key::Ctrl('b') => self.buf.left(),
+            Key::Right | Key::Ctrl('f') => self.buf.right(),
+            Key::Home | Key::Ctrl('a') => self.buf.home(),
+            Key::End | Key::Ctrl('e') => self.buf.end(),
+            Key::Ctrl('k') => self.buf.kill_to_end(),
+            Key::Ctrl('u') => self.buf.kill_to_start(),
+            Key::Ctrl('w') => self.buf.kill_word(),
+            Key::Up | Key::Ctrl('p') => self.history_prev(history),
+            Key::Down | Key::Ctrl('n') => self.history_next(history),
+            Key::Ctrl('r') => {
+                self.search = Some(Search::default());
+            }
+            _ => {}
+        }
+
+        Action::Continue
+    }
+
+    // 候補の共通部分まで補う。それ以上進めないときは一覧を見せるために候補を返す
+    pub fn complete(&mut self, completer: &dyn Completer) -> Vec<String> {
+        if self.search.is_some() {
+            return vec![];
+        }
+
+        let text = self.buf.text();
+        let cursor = self.buf.cursor();
+        let (start, candidates) = completer.complete(&text, cursor);
+        let Some(first) = candidates.first() else {
+            return vec![];
+        };
+
+        let common = candidates.iter().skip(1).fold(first.as_str(), |common, c| {
+            let len = common
+                .char_indices()
+                .zip(c.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((i, a), _)| i + a.len_utf8());
+            &common[..len]
+        });
+        if common.chars().count() > cursor.saturating_sub(start) {
+            self.buf.replace(start, common);
+            return vec![];
+        }
+
+        match candidates.len() {
+            1 => vec![],
+            _ => candidates,
+        }
+    }
+
+    fn history_prev(&mut self, history: &History) {
+        if self.index == 0 {
+            return;
+        }
+        if self.index == history.len() {
+            self.draft = self.buf.text();
+        }
+        self.index -= 1;
+        self.buf.set(history.get(self.index).unwrap_or_default());
+    }
+
+    fn history_next(&mut self, history: &History) {
+        if self.index >= history.len() {
+            return;
+        }
+        self.index += 1;
+        match history.get(self.index) {
+            Some(line) => self.buf.set(line),
+            None => self.buf.set(&self.draft.clone()),
+        }
+    }
+
+    fn handle_search(&mut self, key: Key, history: &History) -> Action {
+        let search = self.search.as_mut().unwrap();
+
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                let before = search.found.map(|i| i + 1).unwrap_or(history.len());
+                search.found = history.search(&search.query, before);
+            }
+            Key::Backspace => {
+                search.query.pop();
+                search.found = history.search(&search.query, history.len());
+            }
+            Key::Ctrl('r') => {
+                let before = search.found.unwrap_or(history.len());
+                if let Some(i) = history.search(&search.query, before) {
+                    search.found = Some(i);
+                }
+            }
+            Key::Ctrl('g') | Key::Esc | Key::Ctrl('c') => {
+                self.search = None;
+            }
+            _ => {
+                // 見つかった行を採用して通常の編集に戻る
+                if let Some(i) = search.found {
+                    self.buf.set(history.get(i).unwrap_or_default());
+                    self.index = i;
+                }
+                self.search = None;
+                if key == Key::Enter {
+                    return Action::Submit;
+                }
+                return self.handle(key, history);
+            }
+        }
+
+        Action::Continue
+    }
+
+    // 行頭に戻って書き直し、カーソル位置まで移動するエスケープ列を返す。
+    // 履歴の検索中は highlighter を使わない
+    pub fn render(
+        &self,
+        prompt: &str,
+        history: &History,
+        highlighter: Option<&dyn Highlighter>,
+    ) -> String {
+        let (prompt, line, cursor) = match &self.search {
+            Some(search) => {
+                let found = search
+                    .found
+                    .and_then(|i| history.get(i))
+                    .unwrap_or_default();
+                let prompt = format!("(reverse-i-search)'{}': ", search.query);
+                (prompt, found.to_string(), found.chars().count())
+            }
+            None => {
+                let text = self.buf.text();
+                let line = match highlighter {
+                    Some(h) => h.highlight(&text, self.buf.cursor()),
+                    None => text,
+                };
+                (prompt.to_string(), line, self.buf.cursor())
+            }
+        };
+
+        let col = prompt.chars().count() + cursor;
+        let mut ret = format!("\r{}{}\x1b[K\r", prompt, line);
+        if col > 0 {
+            ret.push_str(&format!("\x1b[{}C", col));
+        }
+        ret
+    }
+}
+
+pub struct Editor {
+    history: History,
+}
+
+impl Editor {
+    pub fn new(history: History) -> Self {
+        Self { history }
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        self.read_line_with(prompt, None, None)
+    }
+
+    // 端末でなければ highlighter も completer も使わず、色のない入力になる
+    pub fn read_line_with(
+        &mut self,
+        prompt: &str,
+        highlighter: Option<&dyn Highlighter>,
+        completer: Option<&dyn Completer>,
+    ) -> io::Result<Input> {
+        let raw = match stdout().is_terminal() {
+            true => raw::RawMode::enable(),
+            false => None,
+        };
+        let input = match raw {
+            Some(_guard) => self.read_line_raw(prompt, highlighter, completer)?,
+            None => read_line_plain(prompt)?,
+        };
+
+        if let Input::Line(line) = &input {
+            self.history.add(line)?;
+        }
+
+        Ok(input)
+    }
+
+    fn read_line_raw(
+        &mut self,
+        prompt: &str,
+        highlighter: Option<&dyn Highlighter>,
+        completer: Option<&dyn Completer>,
+    ) -> io::Result<Input> {
+        let mut keys = KeyReader::new(stdin().lock());
+        let mut out = stdout().lock();
+        let mut state = EditState::new(&self.history);
+
+        out.write_all(state.render(prompt, &self.history, highlighter).as_bytes())?;
+        out.flush()?;
+
+        loop {
+            let Some(key) = keys.read_key()? else {
+                write!(out, "\r\n")?;
+                return Ok(Input::Eof);
+            };
+
+            let action = match (key, completer) {
+                (Key::Tab, Some(completer)) => {
+                    // 候補が絞れなければ次の行に並べてから入力行を描き直す
+                    let candidates = state.complete(completer);
+                    if !candidates.is_empty() {
+                        write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+                    }
+                    Action::Continue
+                }
+                _ => state.handle(key, &self.history),
+            };
+            out.write_all(state.render(prompt, &self.history, highlighter).as_bytes())?;
+
+            match action {
+                Action::Continue => {}
+                Action::Submit => {
+                    write!(out, "\r\n")?;
+                    return Ok(Input::Line(state.buffer().text()));
+                }
+                Action::Interrupt => {
+                    write!(out, "^C\r\n")?;
+                    return Ok(Input::Interrupt);
+                }
+                Action::Eof => {
+                    write!(out, "\r\n")?;
+                    return Ok(Input::Eof);
+                }
+            }
+            out.flush()?;
+        }
+    }
+}
+
+fn read_line_plain(prompt: &str) -> io::Result<Input> {
+    let mut out = stdout().lock();
+    out.write_all(prompt.as_bytes())?;
+    out.flush()?;
+
+    let mut line = String::new();
+    if stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(Input::Eof);
+    }
+
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(Input::Line(line))
+}
+
+// termios を使った raw モードの切り替え。構造体の中身には触れず libc に任せる
+#[cfg(unix)]
+mod raw {
+    use std::io::{stdin, IsTerminal};
+
+    #[repr(C, align(8))]
+    #[derive(Clone, Copy)]
+    struct Termios([u8; 256]);
+
+    extern "C" {
+        fn tcgetattr(fd: i32, termios: *mut Termios) -> i32;
+        fn tcsetattr(fd: i32, optional_actions: i32, termios: *const Termios) -> i32;
+        fn cfmakeraw(termios: *mut Termios);
+    }
+
+    const STDIN_FILENO: i32 = 0;
+    const TCSANOW: i32 = 0;
+
+    pub struct RawMode {
+        orig: Termios,
+    }
+
+    impl RawMode {
+        pub fn enable() -> Option<Self> {
+            if !stdin().is_terminal() {
+                return None;
+            }
+
+            let mut orig = Termios([0; 256]);
+            // SAFETY: orig は termios 構造体より十分大きく、整列も満たしている
+            unsafe {
+                if tcgetattr(STDIN_FILENO, &mut orig) != 0 {
+                    return None;
+                }
+                let mut raw = orig;
+                cfmakeraw(&mut raw);
+                if tcsetattr(STDIN_FILENO, TCSANOW, &raw) != 0 {
+                    return None;
+                }
+            }
+
+            Some(Self { orig })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            // SAFETY: enable で取得した元の設定に戻すだけ
+            unsafe {
+                tcsetattr(STDIN_FILENO, TCSANOW, &self.orig);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod raw {
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<Self> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut reader = KeyReader::new(bytes);
+        let mut ret = Vec::new();
+        while let Some(key) = reader.read_key().unwrap() {
+            ret.push(key);
+        }
+        ret
+    }
+
+    fn type_keys(state: &mut EditState, history: &History, keys: &[Key]) -> Action {
+        let mut action = Action::Continue;
+        for key in keys {
+            action = state.handle(*key, history);
+        }
+        action
+    }
+
+    #[test]
+    fn test_read_key() {
+        assert_eq!(
+            keys(b"a\x1b[D\x1b[3~\x7f\x01\r\x1bx"),
+            vec![
+                Key::Char('a'),
+                Key::Left,
+                Key::Delete,
+                Key::Backspace,
+                Key::Ctrl('a'),
+                Key::Enter,
+                Key::Esc,
+                Key::Char('x'),
+            ]
+        );
+        assert_eq!(keys("√".as_bytes()), vec![Key::Char('√')]);
+        assert_eq!(
+            keys(b"\x00\x1c\x1f~"),
+            vec![
+                Key::Ctrl('@'),
+                Key::Ctrl('\\'),
+                Key::Ctrl('_'),
+                Key::Char('~'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edit_line() {
+        let history = History::new(10);
+        let mut state = EditState::new(&history);
+
+        let action = type_keys(
+            &mut state,
+            &history,
+            &[
+                Key::Char('1'),
+                Key::Char('2'),
+                Key::Home,
+                Key::Char('('),
+                Key::End,
+                Key::Char(')'),
+                Key::Left,
+                Key::Backspace,
+                Key::Enter,
+            ],
+        );
+
+        assert_eq!(action, Action::Submit);
+        assert_eq!(state.buffer().text(), "(1)");
+    }
+
+    #[test]
+    fn test_render_highlighted() {
+        struct Upper;
+        impl Highlighter for Upper {
+            fn highlight(&self, line: &str, cursor: usize) -> String {
+                format!("{}@{}", line.to_uppercase(), cursor)
+            }
+        }
+
+        let history = History::new(10);
+        let mut state = EditState::new(&history);
+        type_keys(&mut state, &history, &[Key::Char('a'), Key::Char('b')]);
+
+        assert_eq!(state.render("> ", &history, None), "\r> ab\x1b[K\r\x1b[4C");
+        assert_eq!(
+            state.render("> ", &history, Some(&Upper)),
+            "\r> AB@2\x1b[K\r\x1b[4C"
+        );
+    }
+
+    #[test]
+    fn test_complete() {
+        struct Names;
+        impl Completer for Names {
+            fn complete(&self, line: &str, cursor: usize) -> (usize, Vec<String>) {
+                let start = line[..cursor].rfind(' ').map_or(0, |i| i + 1);
+                let names = ["sqrt(", "sin(", "cos("];
+                let prefix = &line[start..cursor];
+                let found = names.iter().filter(|n| n.starts_with(prefix));
+                (start, found.map(|n| n.to_string()).collect())
+            }
+        }
+
+        let history = History::new(10);
+        let mut state = EditState::new(&history);
+        type_keys(&mut state, &history, &[Key::Char('1'), Key::Char(' ')]);
+        type_keys(&mut state, &history, &[Key::Char('c')]);
+        assert!(state.complete(&Names).is_empty());
+        assert_eq!(state.buffer().text(), "1 cos(");
+
+        type_keys(&mut state, &history, &[Key::Char(' '), Key::Char('s')]);
+        assert_eq!(state.complete(&Names), vec!["sqrt(", "sin("]);
+        assert_eq!(state.buffer().text(), "1 cos( s");
+        type_keys(&mut state, &history, &[Key::Char('q')]);
+        assert!(state.complete(&Names).is_empty());
+        assert_eq!(state.buffer().text(), "1 cos( sqrt(");
+        assert_eq!(state.buffer().cursor(), 12);
+    }
+
+    #[test]
+    fn test_history_navigation_and_search() {
+        let mut history = History::new(10);
+        for line in ["1 + 2", "3 * 4", "1 + 5"] {
+            history.add(line).unwrap();
+        }
+
+        let mut state = EditState::new(&history);
+        type_keys(&mut state, &history, &[Key::Char('x'), Key::Up, Key::Up]);
+        assert_eq!(state.buffer().text(), "3 * 4");
+        type_keys(&mut state, &history, &[Key::Down, Key::Down]);
+        assert_eq!(state.buffer().text(), "x");
+
+        let mut state = EditState::new(&history);
+        let action = type_keys(
+            &mut state,
+            &history,
+            &[Key::Ctrl('r'), Key::Char('1'), Key::Ctrl('r'), Key::Enter],
+        );
+        assert_eq!(action, Action::Submit);
+        assert_eq!(state.buffer().text(), "1 + 2");
+    }
+
+    #[test]
+    fn test_history_persist() {
+        let path = std::env::temp_dir().join(format!("calculator_history_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::load(path.clone(), 2).unwrap();
+        for line in ["1", "2", "2", "3"] {
+            history.add(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["2", "3"]);
+
+        let history = History::load(path.clone(), 2).unwrap();
+        assert_eq!(history.entries(), ["2", "3"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use crate::compile::{compile, Compiled};
+use crate::error::Error;
+use crate::eval::{eval_in, solve_in, Env, Mode, Value};
+use crate::lexer::lexer_limited;
+use crate::limits::Limits;
+use crate::parser::{Ast, Parser};
+use crate::solve::Solution;
+use crate::token::*;
+use crate::trace::{trace, Trace};
+
+/// A calculator session: the parser rules in use and the state kept between calls,
+/// such as variables, result history, the evaluation mode and the resource limits.
+///
+/// ```
+/// use calculator::{Engine, Value};
+///
+/// let mut engine = Engine::new();
+/// engine.eval("x = 4").unwrap();
+/// assert_eq!(engine.eval("x / 2").unwrap(), Value::Number(2.0));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Engine {
+    parser: Parser,
+    env: Env,
+}
+
+impl Engine {
+    /// Creates a session with the standard operators and default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a session that parses with `parser`, e.g. one with extra operators.
+    pub fn with_parser(parser: Parser) -> Self {
+        Self {
+            parser,
+            env: Env::new(),
+        }
+    }
+
+    /// The parser used for every input.
+    pub fn parser(&self) -> &Parser {
+        &self.parser
+    }
+
+    /// Mutable access to the parser, e.g. to enable implicit multiplication.
+    pub fn parser_mut(&mut self) -> &mut Parser {
+        &mut self.parser
+    }
+
+    /// The session state: variables, history and mode.
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    /// Mutable access to the session state.
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    /// The number system results are computed in.
+    pub fn mode(&self) -> Mode {
+        self.env.mode()
+    }
+
+    /// Switches between real, complex and interval arithmetic.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.env.set_mode(mode)
+    }
+
+    /// The resource limits currently in force.
+    pub fn limits(&self) -> &Limits {
+        self.parser.limits()
+    }
+
+    /// Sets the limits used by the lexer, the parser and the evaluator alike.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.parser.set_limits(limits);
+        self.env.set_limits(limits);
+    }
+
+    /// The value of variable `name`, if it is defined.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.env.get(name)
+    }
+
+    /// Defines or overwrites variable `name`.
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.env.set(name, value)
+    }
+
+    /// Parses a single statement without evaluating it.
+    pub fn parse(&self, input: &str) -> Result<Ast, Error> {
+        let loc = Location::new(0, input.len());
+        let (tokens, trailing) = lexer_limited(input, &loc, self.limits())?;
+        Ok(self.parser.parse_cst(tokens, trailing)?.to_ast())
+    }
+
+    /// Parses and evaluates a single statement. Assignments update the session.
+    pub fn eval(&mut self, input: &str) -> Result<Value, Error> {
+        let ast = self.parse(input)?;
+        self.eval_ast(&ast)
+    }
+
+    /// Evaluates an already parsed statement, e.g. one from [`Engine::parse`].
+    pub fn eval_ast(&mut self, ast: &Ast) -> Result<Value, Error> {
+        Ok(eval_in(ast, &mut self.env)?)
+    }
+
+    /// Solves an equation such as `x^2 = 2` or a `solve(...)` call and returns the root
+    /// together with an estimate of its error.
+    pub fn solve(&mut self, input: &str) -> Result<Solution, Error> {
+        let ast = self.parse(input)?;
+        Ok(solve_in(&ast, &mut self.env)?)
+    }
+
+    /// Evaluates `input` one reduction at a time and records every step.
+    ///
+    /// Only lexer and parser errors are returned as `Err`; an evaluation error ends the
+    /// trace and is recorded in it.
+    pub fn trace(&mut self, input: &str) -> Result<Trace, Error> {
+        let ast = self.parse(input)?;
+        Ok(trace(&ast, &mut self.env, self.parser.table()))
+    }
+
+    /// Compiles `input` into a function of `params` for repeated evaluation.
+    ///
+    /// Other variables are looked up once, so later changes to them are not seen.
+    pub fn compile(&self, input: &str, params: &[&str]) -> Result<Compiled, Error> {
+        let ast = self.parse(input)?;
+        Ok(compile(&ast, params, &self.env)?)
+    }
+
+    /// Evaluates the statement at `loc` in `input`, returning `None` when it is empty.
+    ///
+    /// Error locations are relative to the whole of `input`.
+    pub fn eval_statement(&mut self, input: &str, loc: &Location) -> Result<Option<Value>, Error> {
+        let (tokens, trailing) = lexer_limited(input, loc, self.limits())?;
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+
+        let ast = self.parser.parse_cst(tokens, trailing)?.to_ast();
+        self.eval_ast(&ast).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_session() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval("x = 2"), Ok(Value::Number(2.0)));
+        assert_eq!(engine.eval("y = x * 3"), Ok(Value::Number(6.0)));
+        assert_eq!(engine.eval("x + y"), Ok(Value::Number(8.0)));
+        assert_eq!(engine.get("y"), Some(Value::Number(6.0)));
+        assert!(matches!(engine.eval("z"), Err(Error::Eval(_))));
+    }
+
+    #[test]
+    fn test_engine_limits() {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits {
+            max_input_bytes: 16,
+            max_depth: 4,
+            max_steps: 8,
+            ..Limits::default()
+        });
+
+        assert_eq!(engine.eval("((1 + 2))"), Ok(Value::Number(3.0)));
+        let message = |r: Result<Value, Error>| r.unwrap_err().to_string();
+        assert_eq!(
+            message(engine.eval("1 + 2 + 3 + 4 + 5 + 6")),
+            "input is 21 bytes long, the limit is 16"
+        );
+        assert_eq!(
+            message(engine.eval("((((1))))")),
+            "expression is nested too deeply"
+        );
+        assert_eq!(
+            message(engine.eval("1+2+3+4+5")),
+            "evaluation exceeded the limit of 8 steps"
+        );
+
+        // 長い連なりは評価や破棄でスタックを使い果たす前に解析で止める
+        let flat = vec!["1"; 30_000].join("+");
+        assert_eq!(
+            message(Engine::new().eval(&flat)),
+            "expression is nested too deeply"
+        );
+    }
+}
@@ -1,10 +1,12 @@
-use crate::parser::ParserError;
-use crate::token::LexError;
+use crate::eval::{EvalError, EvalErrorKind};
+use crate::parser::{expected_list, ParserError};
+use crate::token::{LexError, LexErrorKind, Location};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
     Lexer(LexError),
     Parser(ParserError),
+    Eval(EvalError),
 }
 
 impl From<LexError> for Error {
@@ -18,3 +20,152 @@ impl From<ParserError> for Error {
         Error::Parser(e)
     }
 }
+
+impl From<EvalError> for Error {
+    fn from(e: EvalError) -> Self {
+        Error::Eval(e)
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Lexer(_) => "lexer",
+            Error::Parser(_) => "parser",
+            Error::Eval(_) => "eval",
+        }
+    }
+
+    // 入力の終わりで失敗した場合は末尾の空の範囲
+    pub fn loc(&self) -> Option<Location> {
+        use crate::parser::ParserError::*;
+        match self {
+            Error::Lexer(e) => Some(e.loc()),
+            Error::Parser(e) => match e {
+                UnexpectedToken { found: t, .. }
+                | UnclosedOpenParen(t)
+                | TooDeep(t)
+                | TooManyNodes(t) => Some(t.loc()),
+                Eof { loc, .. } => Some(loc.clone()),
+            },
+            Error::Eval(e) => Some(e.loc()),
+        }
+    }
+
+    // source 中の行と列を添えたメッセージ。
+    // 構文エラーは "expected number, '(' or '-' at 1:5, found '*'" の形になる
+    pub fn describe(&self, source: &str) -> String {
+        use crate::parser::ParserError::*;
+        let Some(loc) = self.loc() else {
+            return self.to_string();
+        };
+        let (line, col) = loc.line_col(source);
+        match self {
+            Error::Parser(UnexpectedToken { found, expected }) => format!(
+                "expected {} at {}:{}, found '{}'",
+                expected_list(expected),
+                line,
+                col,
+                found.value
+            ),
+            Error::Parser(Eof { expected, .. }) => format!(
+                "expected {} at {}:{}, found end of input",
+                expected_list(expected),
+                line,
+                col
+            ),
+            _ => format!("{} at {}:{}", self, line, col),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use crate::parser::ParserError::*;
+        match self {
+            Error::Lexer(e) => match &e.value {
+                LexErrorKind::InvalidChar(c) => write!(f, "invalid character '{}'", c),
+                LexErrorKind::Eof => write!(f, "unexpected end of input"),
+                LexErrorKind::InputTooLong { len, max } => {
+                    write!(f, "input is {} bytes long, the limit is {}", len, max)
+                }
+                LexErrorKind::NumberTooLarge => write!(f, "number literal is too large"),
+            },
+            Error::Parser(e) => match e {
+                UnexpectedToken { found, expected } => write!(
+                    f,
+                    "expected {}, found '{}'",
+                    expected_list(expected),
+                    found.value
+                ),
+                UnclosedOpenParen(t) => write!(f, "unclosed '{}'", t.value),
+                TooDeep(_) => write!(f, "expression is nested too deeply"),
+                TooManyNodes(_) => write!(f, "expression is too long"),
+                Eof { expected, .. } => write!(
+                    f,
+                    "expected {}, found end of input",
+                    expected_list(expected)
+                ),
+            },
+            Error::Eval(e) => match &e.value {
+                EvalErrorKind::DivisionByZero => write!(f, "division by zero"),
+                EvalErrorKind::UndefinedVariable(name) => {
+                    write!(f, "undefined variable '{}'", name)
+                }
+                EvalErrorKind::ArgumentCount { expected, found } => {
+                    write!(f, "expected {} arguments, found {}", expected, found)
+                }
+                EvalErrorKind::ArgumentRange { min, max, found } => {
+                    let sep = if max - min == 1 { "or" } else { "to" };
+                    write!(
+                        f,
+                        "expected {} {} {} arguments, found {}",
+                        min, sep, max, found
+                    )
+                }
+                EvalErrorKind::Unsupported(what) => write!(f, "{} is not supported here", what),
+                EvalErrorKind::UndefinedFunction(name) => {
+                    write!(f, "undefined function '{}'", name)
+                }
+                EvalErrorKind::ShapeMismatch { left, right } => {
+                    write!(f, "shape mismatch: {} and {}", left, right)
+                }
+                EvalErrorKind::TypeMismatch { expected, found } => {
+                    write!(f, "expected {}, found {}", expected, found)
+                }
+                EvalErrorKind::RaggedMatrix => write!(f, "matrix rows must have the same length"),
+                EvalErrorKind::NotSquare(shape) => write!(f, "matrix {} is not square", shape),
+                EvalErrorKind::SingularMatrix => write!(f, "matrix is singular"),
+                EvalErrorKind::HistoryOutOfRange { reference, len } => write!(
+                    f,
+                    "'{}' is past the end of the history ({} results so far)",
+                    reference, len
+                ),
+                EvalErrorKind::InvalidInterval => {
+                    write!(f, "an interval is written [lo, hi] with lo <= hi")
+                }
+                EvalErrorKind::TooManySteps(max) => {
+                    write!(f, "evaluation exceeded the limit of {} steps", max)
+                }
+                EvalErrorKind::NumberTooLarge => write!(f, "result is too large"),
+                EvalErrorKind::NoUnknown => write!(
+                    f,
+                    "the equation has no unknown; use solve(equation, name) to solve for a variable"
+                ),
+                EvalErrorKind::TooManyUnknowns(names) => write!(
+                    f,
+                    "the equation has more than one unknown: {}",
+                    names.join(", ")
+                ),
+                EvalErrorKind::NoConvergence(n) => {
+                    write!(f, "no root found after {} iterations", n)
+                }
+                EvalErrorKind::Domain(name) => {
+                    write!(f, "argument is outside the domain of '{}'", name)
+                }
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
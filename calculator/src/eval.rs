@@ -0,0 +1,806 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::builtin::{find_builtin, Builtin};
+use crate::complex::Complex;
+use crate::interval::Interval;
+use crate::limits::Limits;
+use crate::parser::{Ast, AstKind, BinOp, UniOp, UniOpKind};
+use crate::solve::{find_root, Solution, SolveError};
+use crate::token::*;
+use crate::value::Shape;
+
+pub use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EvalErrorKind {
+    DivisionByZero,
+    UndefinedVariable(String),
+    ArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+    ArgumentRange {
+        min: usize,
+        max: usize,
+        found: usize,
+    },
+    Unsupported(String),
+    UndefinedFunction(String),
+    ShapeMismatch {
+        left: String,
+        right: String,
+    },
+    TypeMismatch {
+        expected: String,
+        found: String,
+    },
+    RaggedMatrix,
+    NotSquare(String),
+    SingularMatrix,
+    Domain(String),
+    InvalidInterval,
+    HistoryOutOfRange {
+        reference: String,
+        len: usize,
+    },
+    TooManySteps(usize),
+    NumberTooLarge,
+    NoUnknown,
+    TooManyUnknowns(Vec<String>),
+    NoConvergence(usize),
+}
+
+impl EvalErrorKind {
+    pub fn shape_mismatch(left: Shape, right: Shape) -> Self {
+        EvalErrorKind::ShapeMismatch {
+            left: left.to_string(),
+            right: right.to_string(),
+        }
+    }
+
+    // 二項演算の両辺のどちらかが expected でない
+    pub fn type_mismatch(expected: &str, l: &Value, r: &Value) -> Self {
+        EvalErrorKind::TypeMismatch {
+            expected: expected.to_string(),
+            found: format!("{} and {}", l.describe(), r.describe()),
+        }
+    }
+}
+
+pub type EvalError = Annotation<EvalErrorKind>;
+
+impl EvalError {
+    pub fn division_by_zero(loc: Location) -> Self {
+        Self::new(EvalErrorKind::DivisionByZero, loc)
+    }
+
+    pub fn undefined_variable(name: &str, loc: Location) -> Self {
+        Self::new(EvalErrorKind::UndefinedVariable(name.to_string()), loc)
+    }
+
+    pub fn argument_count(expected: usize, found: usize, loc: Location) -> Self {
+        Self::new(EvalErrorKind::ArgumentCount { expected, found }, loc)
+    }
+
+    pub fn argument_range(min: usize, max: usize, found: usize, loc: Location) -> Self {
+        Self::new(EvalErrorKind::ArgumentRange { min, max, found }, loc)
+    }
+
+    pub fn unsupported(what: &str, loc: Location) -> Self {
+        Self::new(EvalErrorKind::Unsupported(what.to_string()), loc)
+    }
+}
+
+// Complex は組み込み関数が実数の範囲外の値を返すことを許す。
+// Interval は数値を区間として扱い、`[lo, hi]` を区間の表記とする
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Mode {
+    #[default]
+    Real,
+    Complex,
+    Interval,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "real" => Ok(Mode::Real),
+            "complex" => Ok(Mode::Complex),
+            "interval" => Ok(Mode::Interval),
+            _ => Err(format!("unknown mode: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Mode::Real => write!(f, "real"),
+            Mode::Complex => write!(f, "complex"),
+            Mode::Interval => write!(f, "interval"),
+        }
+    }
+}
+
+// 変数の束縛と評価モード、これまでの結果。steps は評価中の式でたどった節の数
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Env {
+    vars: HashMap<String, Value>,
+    mode: Mode,
+    history: Vec<Value>,
+    limits: Limits,
+    steps: usize,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    fn step(&mut self) -> Result<(), EvalErrorKind> {
+        self.steps += 1;
+        if self.steps > self.limits.max_steps {
+            return Err(EvalErrorKind::TooManySteps(self.limits.max_steps));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_magnitude(&self, v: &Value) -> Result<(), EvalErrorKind> {
+        if v.magnitude() > self.limits.max_magnitude {
+            return Err(EvalErrorKind::NumberTooLarge);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.vars.get(name).cloned()
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    // 同名の変数がなければ `ans` と `_` は直前の結果を指す
+    pub fn resolve(&self, name: &str) -> Result<Value, EvalErrorKind> {
+        if let Some(v) = self.get(name) {
+            return Ok(v);
+        }
+        match name {
+            "ans" | "_" => {
+                self.history
+                    .last()
+                    .cloned()
+                    .ok_or_else(|| EvalErrorKind::HistoryOutOfRange {
+                        reference: name.to_string(),
+                        len: 0,
+                    })
+            }
+            _ => Err(EvalErrorKind::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    // `$n` は 1 始まり
+    pub fn history_ref(&self, n: usize) -> Result<Value, EvalErrorKind> {
+        n.checked_sub(1)
+            .and_then(|i| self.history.get(i))
+            .cloned()
+            .ok_or_else(|| EvalErrorKind::HistoryOutOfRange {
+                reference: format!("${}", n),
+                len: self.history.len(),
+            })
+    }
+
+    pub fn history(&self) -> &[Value] {
+        &self.history
+    }
+
+    pub fn push_history(&mut self, value: Value) {
+        self.history.push(value);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.vars.remove(name)
+    }
+
+    // 名前順に並べて返す
+    pub fn vars(&self) -> Vec<(&str, &Value)> {
+        let mut vars: Vec<_> = self.vars.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
+    }
+}
+
+// 評価の手数は式ごとに数え直す
+pub fn eval_in(ast: &Ast, env: &mut Env) -> Result<Value, EvalError> {
+    env.steps = 0;
+    eval_node(ast, env)
+}
+
+// 方程式か solve の呼び出しを解き、解の誤差の見積もりも返す
+pub fn solve_in(ast: &Ast, env: &mut Env) -> Result<Solution, EvalError> {
+    env.steps = 0;
+    match &ast.value {
+        AstKind::Equation { l, r } => {
+            let name = unknown(ast, env)?;
+            solve_for(l, r, &name, None, env, ast.loc())
+        }
+        AstKind::Call { name, args } if name == SOLVE => solve_call(args, env, ast.loc()),
+        _ => Err(EvalError::new(
+            EvalErrorKind::TypeMismatch {
+                expected: "equation".to_string(),
+                found: "expression".to_string(),
+            },
+            ast.loc(),
+        )),
+    }
+}
+
+fn eval_node(ast: &Ast, env: &mut Env) -> Result<Value, EvalError> {
+    env.step().map_err(|kind| EvalError::new(kind, ast.loc()))?;
+    let v = eval_kind(ast, env)?;
+    env.check_magnitude(&v)
+        .map_err(|kind| EvalError::new(kind, ast.loc()))?;
+    Ok(v)
+}
+
+fn eval_kind(ast: &Ast, env: &mut Env) -> Result<Value, EvalError> {
+    match &ast.value {
+        AstKind::Num(n) if env.mode() == Mode::Interval => Ok(Value::Interval(literal(*n))),
+        AstKind::Num(n) => Ok(Value::Number(*n)),
+        AstKind::Imag(n) => Ok(Value::Complex(Complex::new(0.0, *n))),
+        AstKind::Var(name) => env
+            .resolve(name)
+            .map_err(|kind| EvalError::new(kind, ast.loc())),
+        AstKind::History(n) => env
+            .history_ref(*n)
+            .map_err(|kind| EvalError::new(kind, ast.loc())),
+        AstKind::Assign { name, e } => {
+            let v = eval_node(e, env)?;
+            env.set(name, v.clone());
+            Ok(v)
+        }
+        AstKind::Equation { l, r } => {
+            let name = unknown(ast, env)?;
+            let s = solve_for(l, r, &name, None, env, ast.loc())?;
+            Ok(Value::Number(s.root.x))
+        }
+        AstKind::UniOp { op, e } => {
+            let e = eval_node(e, env)?;
+            apply_uniop(op, e)
+        }
+        AstKind::BinOp { op, l, r } => {
+            let l = eval_node(l, env)?;
+            let r = eval_node(r, env)?;
+            apply_binop(op, &l, &r)
+        }
+        AstKind::Vector(items) => {
+            let items = items
+                .iter()
+                .map(|e| eval_node(e, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            make_vector(items, env.mode(), ast.loc())
+        }
+        AstKind::Call { name, args } if name == SOLVE => {
+            let s = solve_call(args, env, ast.loc())?;
+            Ok(Value::Number(s.root.x))
+        }
+        AstKind::Call { name, args } => {
+            let builtin = find_function(name, args.len(), ast.loc())?;
+            let args = args
+                .iter()
+                .map(|e| eval_node(e, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            (builtin.f)(&args, env.mode()).map_err(|kind| EvalError::new(kind, ast.loc()))
+        }
+    }
+}
+
+// 以下は子を評価し終えた節の計算。トレースでも同じものを使う
+pub(crate) fn apply_uniop(op: &UniOp, e: Value) -> Result<Value, EvalError> {
+    match op.value {
+        UniOpKind::Plus => Ok(e),
+        UniOpKind::Minus => Ok(e.neg()),
+        UniOpKind::Percent => Ok(e.percent()),
+        UniOpKind::Factorial => e.factorial().map_err(|kind| EvalError::new(kind, op.loc())),
+    }
+}
+
+// 形や型の誤りは演算子の位置で報告する
+pub(crate) fn apply_binop(op: &BinOp, l: &Value, r: &Value) -> Result<Value, EvalError> {
+    l.binop(&op.value, r)
+        .map_err(|kind| EvalError::new(kind, op.loc()))
+}
+
+pub(crate) fn make_vector(
+    items: Vec<Value>,
+    mode: Mode,
+    loc: Location,
+) -> Result<Value, EvalError> {
+    if mode == Mode::Interval {
+        return interval(&items).map_err(|kind| EvalError::new(kind, loc));
+    }
+    Value::from_items(items).map_err(|kind| EvalError::new(kind, loc))
+}
+
+// 引数を評価する前に関数の有無と引数の個数を確かめる
+pub(crate) fn find_function(
+    name: &str,
+    argc: usize,
+    loc: Location,
+) -> Result<&'static Builtin, EvalError> {
+    let Some(builtin) = find_builtin(name) else {
+        return Err(EvalError::new(
+            EvalErrorKind::UndefinedFunction(name.to_string()),
+            loc,
+        ));
+    };
+    if argc != builtin.arity {
+        return Err(EvalError::argument_count(builtin.arity, argc, loc));
+    }
+    Ok(builtin)
+}
+
+// 引数を評価せずに受け取る関数。solve(方程式, 変数[, 初期値]) で、方程式でなければ式 = 0 を解く
+pub const SOLVE: &str = "solve";
+
+fn solve_call(args: &[Ast], env: &mut Env, loc: Location) -> Result<Solution, EvalError> {
+    let (e, var, guess) = match args {
+        [e, var] => (e, var, None),
+        [e, var, guess] => (e, var, Some(guess)),
+        _ => return Err(EvalError::argument_range(2, 3, args.len(), loc)),
+    };
+    let AstKind::Var(name) = &var.value else {
+        return Err(EvalError::new(
+            EvalErrorKind::TypeMismatch {
+                expected: "variable name".to_string(),
+                found: "expression".to_string(),
+            },
+            var.loc(),
+        ));
+    };
+    let guess = match guess {
+        Some(g) => Some(number(
+            in_real_mode(env, |env| eval_node(g, env))?,
+            g.loc(),
+        )?),
+        None => None,
+    };
+
+    match &e.value {
+        AstKind::Equation { l, r } => solve_for(l, r, name, guess, env, loc),
+        _ => solve_for(e, &Ast::num(0.0, e.loc()), name, guess, env, loc),
+    }
+}
+
+// 零点は実数で探すので、複素数や区間のモードでも一時的に実数モードで評価する
+fn in_real_mode<T>(env: &mut Env, f: impl FnOnce(&mut Env) -> T) -> T {
+    let mode = env.mode();
+    env.set_mode(Mode::Real);
+    let result = f(env);
+    env.set_mode(mode);
+    result
+}
+
+// 方程式に現れる変数のうち値の決まっていないもの。ちょうど一つでなければならない
+fn unknown(ast: &Ast, env: &Env) -> Result<String, EvalError> {
+    let mut names = Vec::new();
+    free_vars(ast, env, &mut names);
+    match names.len() {
+        0 => Err(EvalError::new(EvalErrorKind::NoUnknown, ast.loc())),
+        1 => Ok(names.remove(0)),
+        _ => Err(EvalError::new(
+            EvalErrorKind::TooManyUnknowns(names),
+            ast.loc(),
+        )),
+    }
+}
+
+fn free_vars(ast: &Ast, env: &Env, names: &mut Vec<String>) {
+    match &ast.value {
+        AstKind::Var(name) if env.resolve(name).is_err() && !names.contains(name) => {
+            names.push(name.clone())
+        }
+        AstKind::Num(_) | AstKind::Imag(_) | AstKind::Var(_) | AstKind::History(_) => {}
+        AstKind::Assign { e, .. } | AstKind::UniOp { e, .. } => free_vars(e, env, names),
+        AstKind::Equation { l, r } | AstKind::BinOp { l, r, .. } => {
+            free_vars(l, env, names);
+            free_vars(r, env, names);
+        }
+        AstKind::Vector(items) | AstKind::Call { args: items, .. } => {
+            items.iter().for_each(|e| free_vars(e, env, names))
+        }
+    }
+}
+
+// name に値を入れながら l - r の零点を探す。name の元の値は解いた後に戻す
+fn solve_for(
+    l: &Ast,
+    r: &Ast,
+    name: &str,
+    guess: Option<f64>,
+    env: &mut Env,
+    loc: Location,
+) -> Result<Solution, EvalError> {
+    let saved = env.get(name);
+    let guess = guess
+        .or(saved.as_ref().and_then(Value::as_number))
+        .unwrap_or(1.0);
+
+    let result = in_real_mode(env, |env| {
+        find_root(
+            |x| {
+                env.set(name, Value::Number(x));
+                let lv = eval_node(l, env)?;
+                let rv = eval_node(r, env)?;
+                match (&lv, &rv) {
+                    (Value::Number(a), Value::Number(b)) => Ok(a - b),
+                    _ => Err(EvalError::new(
+                        EvalErrorKind::type_mismatch("numbers", &lv, &rv),
+                        loc.clone(),
+                    )),
+                }
+            },
+            guess,
+        )
+    });
+
+    match saved {
+        Some(v) => env.set(name, v),
+        None => {
+            env.remove(name);
+        }
+    }
+    match result {
+        Ok(root) => Ok(Solution {
+            name: name.to_string(),
+            root,
+        }),
+        Err(SolveError::Eval(e)) => Err(e),
+        Err(SolveError::NoConvergence(n)) => {
+            Err(EvalError::new(EvalErrorKind::NoConvergence(n), loc))
+        }
+    }
+}
+
+fn number(v: Value, loc: Location) -> Result<f64, EvalError> {
+    v.as_number().ok_or_else(|| {
+        EvalError::new(
+            EvalErrorKind::TypeMismatch {
+                expected: "number".to_string(),
+                found: v.describe(),
+            },
+            loc,
+        )
+    })
+}
+
+// 2 進で表せない小数リテラルは前後の浮動小数点数まで広げる
+fn literal(n: f64) -> Interval {
+    if n.fract() == 0.0 && n.abs() <= 2f64.powi(53) {
+        Interval::point(n)
+    } else {
+        Interval::around(n)
+    }
+}
+
+fn interval(items: &[Value]) -> Result<Value, EvalErrorKind> {
+    let (lo, hi) = match items {
+        [lo, hi] => (lo.as_interval(), hi.as_interval()),
+        _ => return Err(EvalErrorKind::InvalidInterval),
+    };
+    match (lo, hi) {
+        (Some(lo), Some(hi)) if lo.lo <= hi.hi => Ok(Value::Interval(Interval::new(lo.lo, hi.hi))),
+        _ => Err(EvalErrorKind::InvalidInterval),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_str(s: &str) -> Result<Value, EvalError> {
+        eval_in(&s.parse::<Ast>().unwrap(), &mut Env::new())
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval_str("1 + 2 * 3 - 4"), Ok(Value::Number(3.0)));
+        assert_eq!(eval_str("(1 + 2) * -3"), Ok(Value::Number(-9.0)));
+        assert_eq!(eval_str("7 / 2"), Ok(Value::Number(3.5)));
+    }
+
+    #[test]
+    fn test_eval_env() {
+        let mut env = Env::new();
+
+        let ast = "x = 3 * 2".parse::<Ast>().unwrap();
+        assert_eq!(eval_in(&ast, &mut env), Ok(Value::Number(6.0)));
+
+        let ast = "x + y".parse::<Ast>().unwrap();
+        assert_eq!(
+            eval_in(&ast, &mut env),
+            Err(EvalError::undefined_variable("y", Location::new(4, 5)))
+        );
+        env.set("y", Value::Number(1.0));
+        assert_eq!(eval_in(&ast, &mut env), Ok(Value::Number(7.0)));
+    }
+
+    #[test]
+    fn test_eval_matrix() {
+        assert_eq!(eval_str("[1, 2, 3] * 2").unwrap().to_string(), "[2, 4, 6]");
+        assert_eq!(
+            eval_str("[[1, 2], [3, 4]] @ [[5], [6]]")
+                .unwrap()
+                .to_string(),
+            "[[17], [39]]"
+        );
+        assert_eq!(eval_str("det([[1, 2], [3, 4]])"), Ok(Value::Number(-2.0)));
+        assert_eq!(
+            eval_str("transpose([[1, 2, 3]])").unwrap().to_string(),
+            "[[1], [2], [3]]"
+        );
+
+        assert_eq!(
+            eval_str("[1, 2] + [1, 2, 3]"),
+            Err(EvalError::new(
+                EvalErrorKind::ShapeMismatch {
+                    left: "[2]".to_string(),
+                    right: "[3]".to_string()
+                },
+                Location::new(7, 8)
+            ))
+        );
+        assert_eq!(
+            eval_str("[[1], [2, 3]]").map_err(|e| e.value),
+            Err(EvalErrorKind::RaggedMatrix)
+        );
+        assert_eq!(
+            eval_str("inv([[1, 2], [2, 4]])"),
+            Err(EvalError::new(
+                EvalErrorKind::SingularMatrix,
+                Location::new(0, 21)
+            ))
+        );
+        assert_eq!(
+            eval_str("det(1, 2)").map_err(|e| e.value),
+            Err(EvalErrorKind::ArgumentCount {
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_eval_complex() {
+        assert_eq!(
+            eval_str("(3 + 4i) * (1 - 2i)"),
+            Ok(Value::Complex(Complex::new(11.0, -2.0)))
+        );
+        assert_eq!(eval_str("abs(3 + 4i)"), Ok(Value::Number(5.0)));
+        // 表示した結果はそのまま入力に戻せる
+        let z = eval_str("conj(1 + 1i)").unwrap();
+        assert_eq!(z.to_string(), "1 - 1i");
+        assert_eq!(eval_str(&z.to_string()), Ok(z));
+        assert_eq!(
+            eval_str("sqrt(-1)").map_err(|e| e.value),
+            Err(EvalErrorKind::Domain("sqrt".to_string()))
+        );
+
+        let mut env = Env::new();
+        env.set_mode(Mode::Complex);
+        let ast = "sqrt(-1)".parse::<Ast>().unwrap();
+        assert_eq!(eval_in(&ast, &mut env).unwrap().to_string(), "1i");
+        let ast = "arg(-1)".parse::<Ast>().unwrap();
+        assert_eq!(
+            eval_in(&ast, &mut env),
+            Ok(Value::Number(std::f64::consts::PI))
+        );
+    }
+
+    #[test]
+    fn test_eval_interval() {
+        let mut env = Env::new();
+        env.set_mode(Mode::Interval);
+        let mut eval = |s: &str| eval_in(&s.parse::<Ast>().unwrap(), &mut env);
+
+        let Ok(Value::Interval(x)) = eval("[1.9, 2.1] * [2.9, 3.1]") else {
+            panic!("expected interval");
+        };
+        assert!(x.lo <= 1.9 * 2.9 && x.lo > 5.5);
+        assert!(x.hi >= 2.1 * 3.1 && x.hi < 6.52);
+
+        let Ok(Value::Interval(x)) = eval("1 / [-1, 1]") else {
+            panic!("expected interval");
+        };
+        assert_eq!(x, Interval::ENTIRE);
+        assert_eq!(
+            eval("1 / [0, 0]").map_err(|e| e.value),
+            Err(EvalErrorKind::DivisionByZero)
+        );
+        assert_eq!(
+            eval("[2, 1]").map_err(|e| e.value),
+            Err(EvalErrorKind::InvalidInterval)
+        );
+    }
+
+    #[test]
+    fn test_eval_postfix() {
+        assert_eq!(eval_str("-3! + 50%"), Ok(Value::Number(-5.5)));
+        assert_eq!(eval_str("--2 * 0!"), Ok(Value::Number(2.0)));
+        assert_eq!(
+            eval_str("(1 - 3)!"),
+            Err(EvalError::new(
+                EvalErrorKind::Domain("!".to_string()),
+                Location::new(7, 8)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_eval_power() {
+        assert_eq!(eval_str("-2^2 + 2^3^2"), Ok(Value::Number(508.0)));
+        assert_eq!(eval_str("2^3! * 2^-1"), Ok(Value::Number(32.0)));
+        assert_eq!(
+            eval_str("(-8)^(1 / 3)").map_err(|e| e.value),
+            Err(EvalErrorKind::Domain("^".to_string()))
+        );
+        assert_eq!(
+            eval_str("(2i)^2"),
+            Ok(Value::Complex(Complex::new(-4.0, 0.0)))
+        );
+
+        let mut env = Env::new();
+        env.set_mode(Mode::Interval);
+        let Ok(Value::Interval(x)) = eval_in(&"[-2, 1]^2".parse().unwrap(), &mut env) else {
+            panic!("expected interval");
+        };
+        assert!(x.contains(0.0) && x.contains(4.0) && x.lo > -1e-300 && x.hi < 4.0001);
+    }
+
+    #[test]
+    fn test_eval_equation() {
+        let mut env = Env::new();
+        let eval = |s: &str, env: &mut Env| eval_in(&s.parse::<Ast>().unwrap(), env);
+        let near = |v: Result<Value, EvalError>, x: f64| match v {
+            Ok(Value::Number(n)) => (n - x).abs() < 1e-9,
+            _ => false,
+        };
+
+        assert!(near(
+            eval("x * 1.07^5 = 1000", &mut env),
+            1000.0 / 1.07f64.powi(5)
+        ));
+        assert!(near(eval("solve(x^2 - 2 = 0, x)", &mut env), 2f64.sqrt()));
+        assert!(near(
+            eval("solve(x^2 - 2, x, -1)", &mut env),
+            -(2f64.sqrt())
+        ));
+        assert_eq!(env.get("x"), None);
+
+        // 値のある変数は solve で未知数に指定し、解いた後も元の値に戻す
+        env.set("x", Value::Number(-3.0));
+        assert_eq!(
+            eval("x^2 = 4", &mut env).map_err(|e| e.value),
+            Err(EvalErrorKind::NoUnknown)
+        );
+        assert!(near(eval("1 + solve(x^2 = 4, x)", &mut env), -1.0));
+        assert_eq!(env.get("x"), Some(Value::Number(-3.0)));
+
+        assert_eq!(
+            eval("a + b = 1", &mut env).map_err(|e| e.value),
+            Err(EvalErrorKind::TooManyUnknowns(vec![
+                "a".to_string(),
+                "b".to_string()
+            ]))
+        );
+        assert_eq!(
+            eval("y^2 + 1 = 0", &mut env),
+            Err(EvalError::new(
+                EvalErrorKind::NoConvergence(crate::solve::MAX_ITERATIONS),
+                Location::new(0, 11)
+            ))
+        );
+
+        let ast = "t^3 = 27".parse::<Ast>().unwrap();
+        let s = solve_in(&ast, &mut env).unwrap();
+        assert_eq!(s.name, "t");
+        assert!((s.root.x - 3.0).abs() <= s.root.tolerance.max(1e-15));
+
+        assert_eq!(
+            eval("solve(x, x, 1, 2)", &mut env).map_err(|e| e.value),
+            Err(EvalErrorKind::ArgumentRange {
+                min: 2,
+                max: 3,
+                found: 4
+            })
+        );
+        // 区間のモードでも実数で解き、モードは元に戻す
+        env.set_mode(Mode::Interval);
+        assert!(near(eval("solve(x^2 = 4, x, 1)", &mut env), 2.0));
+        assert_eq!(env.mode(), Mode::Interval);
+    }
+
+    #[test]
+    fn test_eval_history() {
+        let mut env = Env::new();
+        let mut eval = |s: &str| eval_in(&s.parse::<Ast>().unwrap(), &mut env);
+        assert_eq!(
+            eval("ans").map_err(|e| e.value),
+            Err(EvalErrorKind::HistoryOutOfRange {
+                reference: "ans".to_string(),
+                len: 0
+            })
+        );
+
+        env.push_history(Value::Number(2.0));
+        env.push_history(Value::Number(5.0));
+        let mut eval = |s: &str| eval_in(&s.parse::<Ast>().unwrap(), &mut env);
+        assert_eq!(eval("ans * $1 + _"), Ok(Value::Number(15.0)));
+        assert_eq!(
+            eval("1 + $3"),
+            Err(EvalError::new(
+                EvalErrorKind::HistoryOutOfRange {
+                    reference: "$3".to_string(),
+                    len: 2
+                },
+                Location::new(4, 6)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_eval_limits() {
+        let mut env = Env::new();
+        env.set_limits(Limits {
+            max_steps: 5,
+            max_magnitude: 1e6,
+            ..Limits::default()
+        });
+
+        let ast = "1 + 2 * 3".parse::<Ast>().unwrap();
+        assert_eq!(eval_in(&ast, &mut env), Ok(Value::Number(7.0)));
+        // 手数は式ごとに数え直す
+        assert_eq!(eval_in(&ast, &mut env), Ok(Value::Number(7.0)));
+
+        let ast = "1 + 2 + 3 + 4".parse::<Ast>().unwrap();
+        assert_eq!(
+            eval_in(&ast, &mut env).map_err(|e| e.value),
+            Err(EvalErrorKind::TooManySteps(5))
+        );
+
+        let ast = "1000 * 2000".parse::<Ast>().unwrap();
+        assert_eq!(
+            eval_in(&ast, &mut env),
+            Err(EvalError::new(
+                EvalErrorKind::NumberTooLarge,
+                Location::new(0, 11)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_eval_division_by_zero() {
+        assert_eq!(
+            eval_str("1 / (2 - 2)"),
+            Err(EvalError::division_by_zero(Location::new(2, 3)))
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::builtin::builtins;
+use crate::error::Error;
+use crate::eval::EvalErrorKind;
+use crate::lexer::lexer_lossless_at;
+use crate::parser::{Expected, ParserError};
+use crate::token::*;
+
+// 誤りを直す編集。入力の span を replacement で置き換える
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fix {
+    pub title: String,
+    pub span: Location,
+    pub replacement: String,
+}
+
+impl Fix {
+    pub fn apply(&self, input: &str) -> String {
+        format!(
+            "{}{}{}",
+            &input[..self.span.start()],
+            self.replacement,
+            &input[self.span.end()..]
+        )
+    }
+}
+
+// input のうち loc の範囲の文で起きた error の直し方。見込みの高い順に並べる
+pub fn suggest(input: &str, loc: &Location, error: &Error) -> Vec<Fix> {
+    let tokens = match lexer_lossless_at(input, loc) {
+        Ok((tokens, _)) => tokens,
+        Err(_) => return vec![],
+    };
+
+    match error {
+        Error::Parser(ParserError::UnclosedOpenParen(_)) => close_brackets(&tokens),
+        Error::Parser(ParserError::UnexpectedToken { found, expected }) => {
+            unexpected(input, found, expected)
+        }
+        Error::Parser(ParserError::Eof { expected, .. })
+            if expected.contains(&Expected::Number) =>
+        {
+            // 末尾に残った演算子を前の空白ごと消す
+            match tokens.as_slice() {
+                [.., prev, last] if is_operator(&last.kind()) => {
+                    vec![remove(
+                        &last.kind(),
+                        Location::new(prev.loc().end(), last.loc().end()),
+                    )]
+                }
+                _ => vec![],
+            }
+        }
+        Error::Eval(e) => match &e.value {
+            EvalErrorKind::UndefinedFunction(name) => {
+                let start = e.loc().start();
+                let span = Location::new(start, start + name.len());
+                closest_builtins(name)
+                    .into_iter()
+                    .map(|b| Fix {
+                        title: format!("replace with '{}'", b),
+                        span: span.clone(),
+                        replacement: b.to_string(),
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+// 閉じていない括弧を内側から順に閉じる
+fn close_brackets(tokens: &[CstToken]) -> Vec<Fix> {
+    let mut stack = Vec::new();
+    for t in tokens {
+        match t.kind() {
+            TokenKind::LParen => stack.push(')'),
+            TokenKind::LBracket => stack.push(']'),
+            TokenKind::RParen | TokenKind::RBracket => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+    let (Some(last), false) = (tokens.last(), stack.is_empty()) else {
+        return vec![];
+    };
+
+    let close: String = stack.into_iter().rev().collect();
+    let end = last.loc().end();
+    vec![Fix {
+        title: format!("insert '{}'", close),
+        span: Location::new(end, end),
+        replacement: close,
+    }]
+}
+
+fn unexpected(input: &str, found: &Token, expected: &[Expected]) -> Vec<Fix> {
+    let loc = found.loc();
+    match &found.value {
+        // 被演算子の位置に来た演算子と、対のない閉じ括弧は消す
+        kind if is_operator(kind) && expected.contains(&Expected::Number) => {
+            let end = loc.end() + input[loc.end()..].len() - input[loc.end()..].trim_start().len();
+            vec![remove(kind, Location::new(loc.start(), end))]
+        }
+        kind @ (TokenKind::RParen | TokenKind::RBracket) if expected.contains(&Expected::End) => {
+            vec![remove(kind, loc)]
+        }
+        // 並べて書かれた項の間に `*` を入れる
+        TokenKind::Number(_)
+        | TokenKind::Imaginary(_)
+        | TokenKind::Ident(_)
+        | TokenKind::History(_)
+        | TokenKind::LParen
+        | TokenKind::LBracket
+            if expected.contains(&Expected::Token(TokenKind::Asterisk)) =>
+        {
+            let spaced = input[..loc.start()].ends_with(char::is_whitespace);
+            vec![Fix {
+                title: "insert '*'".to_string(),
+                span: Location::new(loc.start(), loc.start()),
+                replacement: if spaced { "* " } else { "*" }.to_string(),
+            }]
+        }
+        _ => vec![],
+    }
+}
+
+fn remove(kind: &TokenKind, span: Location) -> Fix {
+    Fix {
+        title: format!("remove '{}'", kind),
+        span,
+        replacement: String::new(),
+    }
+}
+
+fn is_operator(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Equal
+            | TokenKind::Plus
+            | TokenKind::Minus
+            | TokenKind::Asterisk
+            | TokenKind::Slash
+            | TokenKind::Bang
+            | TokenKind::Percent
+            | TokenKind::At
+            | TokenKind::Caret
+            | TokenKind::Comma
+    )
+}
+
+// 編集距離が最も小さい組み込み関数。遠すぎるものは候補にしない
+fn closest_builtins(name: &str) -> Vec<&'static str> {
+    let max = if name.chars().count() < 3 { 1 } else { 2 };
+    let scored: Vec<_> = builtins()
+        .iter()
+        .map(|b| (edit_distance(name, b.name), b.name))
+        .filter(|(d, _)| *d <= max)
+        .collect();
+    let best = scored.iter().map(|(d, _)| *d).min();
+    scored
+        .into_iter()
+        .filter(|(d, _)| Some(*d) == best)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = (prev + usize::from(ca != *cb)).min(row[j] + 1).min(cur + 1);
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    // 一つ目の候補を当てた結果
+    fn fixed(input: &str) -> Option<String> {
+        let e = Engine::new().eval(input).unwrap_err();
+        let fixes = suggest(input, &Location::new(0, input.len()), &e);
+        fixes.first().map(|f| f.apply(input))
+    }
+
+    #[test]
+    fn test_suggest_syntax_fixes() {
+        assert_eq!(fixed("(1 + [2"), Some("(1 + [2])".to_string()));
+        assert_eq!(fixed("1 + * 2"), Some("1 + 2".to_string()));
+        assert_eq!(fixed("1 + 2)"), Some("1 + 2".to_string()));
+        assert_eq!(fixed("3 -  "), Some("3  ".to_string()));
+        assert_eq!(fixed("2 (3 + 4)"), Some("2 * (3 + 4)".to_string()));
+        assert_eq!(fixed("2[1, 2]"), Some("2*[1, 2]".to_string()));
+        assert_eq!(fixed("1 / 0"), None);
+    }
+
+    #[test]
+    fn test_suggest_builtin_names() {
+        assert_eq!(fixed("sqr(4)"), Some("sqrt(4)".to_string()));
+        assert_eq!(fixed("1 + dte([[1]])"), Some("1 + det([[1]])".to_string()));
+        assert_eq!(fixed("nothing(1)"), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
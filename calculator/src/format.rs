@@ -0,0 +1,377 @@
+use crate::cst::{CstKind, CstNode};
+use crate::error::Error;
+use crate::lexer::{lexer_lossless_at, split_statements};
+use crate::parser::{Assoc, Parser};
+use crate::token::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FormatOptions {
+    pub width: usize,
+    pub indent: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            indent: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Formatter {
+    parser: Parser,
+    options: FormatOptions,
+}
+
+pub fn format_source(input: &str, options: &FormatOptions) -> Result<String, Error> {
+    Formatter::new(Parser::default(), options.clone()).format(input)
+}
+
+impl Formatter {
+    pub fn new(parser: Parser, options: FormatOptions) -> Self {
+        Self { parser, options }
+    }
+
+    pub fn format(&self, input: &str) -> Result<String, Error> {
+        let mut lines: Vec<String> = Vec::new();
+
+        for loc in split_statements(input) {
+            let (tokens, trailing) = lexer_lossless_at(input, &loc)?;
+
+            // 空行は連続させない
+            if tokens.is_empty() && trailing.iter().all(|t| !is_comment(t)) {
+                if lines.last().is_some_and(|l| !l.is_empty()) {
+                    lines.push(String::new());
+                }
+                continue;
+            }
+
+            // 式の途中のコメントは文の前に出し、行末のコメントだけ残す
+            let mut comments: Vec<String> = tokens
+                .iter()
+                .flat_map(|t| t.leading.iter())
+                .chain(trailing.iter())
+                .filter_map(comment_text)
+                .collect();
+            let last_comment = match trailing.iter().rev().find_map(comment_text) {
+                Some(_) if !tokens.is_empty() => comments.pop(),
+                _ => None,
+            };
+            lines.extend(comments);
+
+            if tokens.is_empty() {
+                continue;
+            }
+
+            let cst = self.parser.parse_cst(tokens, trailing)?;
+            let mut line = self.format_node(&cst.root, 0, 0);
+            if let Some(comment) = last_comment {
+                line.push(' ');
+                line.push_str(&comment);
+            }
+            lines.push(line);
+        }
+
+        while lines.last().is_some_and(|l| l.is_empty()) {
+            lines.pop();
+        }
+
+        Ok(lines.into_iter().map(|l| l + "\n").collect())
+    }
+
+    pub fn is_formatted(&self, input: &str) -> Result<bool, Error> {
+        Ok(self.format(input)? == input)
+    }
+
+    // indent は二行目以降の字下げ、col は一行目の開始桁。一行目は呼び出し側が配置する
+    fn format_node(&self, node: &CstNode, indent: usize, col: usize) -> String {
+        let node = unparen(node);
+        let flat = self.flat(node);
+
+        if col + flat.len() <= self.options.width {
+            return flat;
+        }
+
+        match &node.value {
+            CstKind::BinOp { .. } | CstKind::Implicit { .. } => self.wrapped(node, indent),
+            CstKind::Assign { name, eq, e } => {
+                let head = format!("{} {} ", name.text, eq.text);
+                let col = col + head.len();
+                format!("{}{}", head, self.format_node(e, indent, col))
+            }
+            _ => flat,
+        }
+    }
+
+    fn flat(&self, node: &CstNode) -> String {
+        match &unparen(node).value {
+            CstKind::Num(t) | CstKind::Var(t) => t.text.clone(),
+            CstKind::Assign { name, eq, e } => {
+                format!("{} {} {}", name.text, eq.text, self.flat(e))
+            }
+            CstKind::Equation { l, eq, r } => {
+                let l = self.flat_operand(l, self.precedence(l) == 0);
+                let r = self.flat_operand(r, self.precedence(r) == 0);
+                format!("{} {} {}", l, eq.text, r)
+            }
+            CstKind::Paren { .. } => unreachable!(),
+            CstKind::Prefix { token, e, .. } => {
+                let e = self.flat_operand(e, self.needs_paren_prefix(node, e));
+                format!("{}{}", token.text, e)
+            }
+            CstKind::Postfix { token, e, .. } => {
+                let e = self.flat_operand(e, self.needs_paren_postfix(node, e));
+                format!("{}{}", e, token.text)
+            }
+            CstKind::BinOp { .. } | CstKind::Implicit { .. } => {
+                let (op, l, r) = binop_parts(unparen(node)).unwrap();
+                let (l_paren, r_paren) = self.needs_paren_binop(node, l, r);
+                let l = self.flat_operand(l, l_paren);
+                let r = self.flat_operand(r, r_paren);
+                format!("{} {} {}", l, op, r)
+            }
+            CstKind::Vector {
+                open, items, close, ..
+            } => format!("{}{}{}", open.text, self.flat_list(items), close.text),
+            CstKind::Call {
+                name,
+                open,
+                args,
+                close,
+                ..
+            } => format!(
+                "{}{}{}{}",
+                name.text,
+                open.text,
+                self.flat_list(args),
+                close.text
+            ),
+        }
+    }
+
+    fn flat_list(&self, items: &[CstNode]) -> String {
+        let items: Vec<String> = items.iter().map(|e| self.flat(e)).collect();
+        items.join(", ")
+    }
+
+    fn flat_operand(&self, node: &CstNode, paren: bool) -> String {
+        if paren {
+            format!("({})", self.flat(node))
+        } else {
+            self.flat(node)
+        }
+    }
+
+    // 長い二項演算を括弧で囲み、演算子の前で改行する
+    fn wrapped(&self, node: &CstNode, indent: usize) -> String {
+        let inner = indent + self.options.indent;
+        let pad = " ".repeat(inner);
+
+        let mut ret = String::from("(\n");
+        for (op, operand, paren) in self.chain(node) {
+            let prefix = op.map(|op| format!("{} ", op)).unwrap_or_default();
+            let body = self.format_operand(operand, paren, inner, inner + prefix.len());
+            ret.push_str(&format!("{}{}{}\n", pad, prefix, body));
+        }
+        ret.push_str(&" ".repeat(indent));
+        ret.push(')');
+        ret
+    }
+
+    fn format_operand(&self, node: &CstNode, paren: bool, indent: usize, col: usize) -> String {
+        if !paren {
+            return self.format_node(node, indent, col);
+        }
+
+        let flat = self.flat_operand(node, paren);
+        if col + flat.len() <= self.options.width {
+            return flat;
+        }
+
+        match &unparen(node).value {
+            CstKind::BinOp { .. } | CstKind::Implicit { .. } => self.wrapped(unparen(node), indent),
+            _ => flat,
+        }
+    }
+
+    // 左結合で同じ優先度の演算子が続く部分を一列に並べる
+    fn chain<'a>(&self, node: &'a CstNode) -> Vec<(Option<&'a str>, &'a CstNode, bool)> {
+        let node = unparen(node);
+        match binop_parts(node) {
+            Some((op, l, r)) => {
+                let (l_paren, r_paren) = self.needs_paren_binop(node, l, r);
+                let mut chain = if l_paren {
+                    vec![(None, l, true)]
+                } else if self.precedence(l) == self.precedence(node) {
+                    self.chain(l)
+                } else {
+                    vec![(None, l, false)]
+                };
+                chain.push((Some(op), r, r_paren));
+                chain
+            }
+            None => vec![(None, node, false)],
+        }
+    }
+
+    fn precedence(&self, node: &CstNode) -> u8 {
+        let table = self.parser.table();
+        match &unparen(node).value {
+            CstKind::Num(_)
+            | CstKind::Var(_)
+            | CstKind::Paren { .. }
+            | CstKind::Vector { .. }
+            | CstKind::Call { .. } => None,
+            CstKind::Assign { .. } | CstKind::Equation { .. } => Some(0),
+            CstKind::Prefix { token, .. } => table.find_prefix(&token.kind()).map(|p| p.bp),
+            CstKind::Postfix { token, .. } => table.find_postfix(&token.kind()).map(|p| p.bp),
+            CstKind::BinOp { token, .. } => table.find_infix(&token.kind()).map(|p| p.bp),
+            CstKind::Implicit { op, .. } => table.find_infix_op(&op.value).map(|p| p.bp),
+        }
+        .unwrap_or(u8::MAX)
+    }
+
+    fn assoc(&self, node: &CstNode) -> Option<Assoc> {
+        match &unparen(node).value {
+            CstKind::BinOp { token, .. } => self
+                .parser
+                .table()
+                .find_infix(&token.kind())
+                .map(|p| p.assoc),
+            CstKind::Implicit { op, .. } => self
+                .parser
+                .table()
+                .find_infix_op(&op.value)
+                .map(|p| p.assoc),
+            _ => None,
+        }
+    }
+
+    fn needs_paren_binop(&self, node: &CstNode, l: &CstNode, r: &CstNode) -> (bool, bool) {
+        let p = self.precedence(node);
+        let assoc = self.assoc(node);
+        let (lp, rp) = (self.precedence(l), self.precedence(r));
+        let l_binop = self.assoc(l).is_some();
+        let r_binop = self.assoc(r).is_some();
+
+        (
+            lp < p || (lp == p && l_binop && assoc == Some(Assoc::Right)),
+            rp < p || (rp == p && r_binop && assoc == Some(Assoc::Left)),
+        )
+    }
+
+    fn needs_paren_prefix(&self, node: &CstNode, e: &CstNode) -> bool {
+        match &unparen(e).value {
+            CstKind::Prefix { .. } | CstKind::Num(_) | CstKind::Var(_) => false,
+            _ => self.precedence(e) <= self.precedence(node),
+        }
+    }
+
+    fn needs_paren_postfix(&self, node: &CstNode, e: &CstNode) -> bool {
+        match &unparen(e).value {
+            CstKind::Postfix { .. } | CstKind::Num(_) | CstKind::Var(_) => false,
+            _ => self.precedence(e) <= self.precedence(node),
+        }
+    }
+}
+
+// 暗黙の乗算は対応する演算子のトークンがないので `*` と書き出す
+fn binop_parts(node: &CstNode) -> Option<(&str, &CstNode, &CstNode)> {
+    match &node.value {
+        CstKind::BinOp { token, l, r, .. } => Some((token.text.as_str(), l, r)),
+        CstKind::Implicit { l, r, .. } => Some(("*", l, r)),
+        _ => None,
+    }
+}
+
+fn unparen(node: &CstNode) -> &CstNode {
+    match &node.value {
+        CstKind::Paren { e, .. } => unparen(e),
+        _ => node,
+    }
+}
+
+fn is_comment(t: &Trivia) -> bool {
+    matches!(t.value, TriviaKind::Comment(_))
+}
+
+fn comment_text(t: &Trivia) -> Option<String> {
+    match &t.value {
+        TriviaKind::Comment(s) => Some(s.trim_end().to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(input: &str) -> String {
+        format_source(input, &FormatOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_format_spacing_and_parens() {
+        assert_eq!(fmt("1+2*3"), "1 + 2 * 3\n");
+        assert_eq!(fmt("((1+2))*(3)"), "(1 + 2) * 3\n");
+        assert_eq!(fmt("(1*2)+(3/4)"), "1 * 2 + 3 / 4\n");
+        assert_eq!(fmt("1-(2+3)"), "1 - (2 + 3)\n");
+        assert_eq!(fmt("(1-2)+3"), "1 - 2 + 3\n");
+        assert_eq!(fmt("- ( 1 + 2 )"), "-(1 + 2)\n");
+        assert_eq!(fmt("-(4)"), "-4\n");
+        assert_eq!(fmt("-(-(5))"), "--5\n");
+        assert_eq!(fmt("((3)!)! + (-3)!"), "3!! + (-3)!\n");
+        assert_eq!(fmt("x=(y)*2"), "x = y * 2\n");
+        assert_eq!(fmt("(2^3)^2+(-2)^(2)"), "(2 ^ 3) ^ 2 + (-2) ^ 2\n");
+        assert_eq!(fmt("solve((x)*2=(1+3),x)"), "solve(x * 2 = 1 + 3, x)\n");
+        assert_eq!(
+            fmt("[ [1,2],[3,(4)] ]@det( m )"),
+            "[[1, 2], [3, 4]] @ det(m)\n"
+        );
+    }
+
+    #[test]
+    fn test_format_implicit_mul() {
+        let mut parser = Parser::default();
+        parser.set_implicit_mul(true);
+        let formatter = Formatter::new(parser, FormatOptions::default());
+
+        assert_eq!(
+            formatter.format("2(3+4) + (1+2)(x)\n1/2x").unwrap(),
+            "2 * (3 + 4) + (1 + 2) * x\n1 / 2 * x\n"
+        );
+    }
+
+    #[test]
+    fn test_format_comments_and_blank_lines() {
+        let input = "\n# head\n1+2   # sum\n\n\n\n(3 # three\n*4)\n\n";
+        assert_eq!(fmt(input), "# head\n1 + 2 # sum\n\n# three\n3 * 4\n");
+    }
+
+    #[test]
+    fn test_format_wrap() {
+        let options = FormatOptions {
+            width: 20,
+            indent: 4,
+        };
+        let input = "1111 + 2222 * 3333 - (4444 + 5555 + 6666 + 7777)";
+        let expected = "(\n    1111\n    + 2222 * 3333\n    - (\n        4444\n        + 5555\n        + 6666\n        + 7777\n    )\n)\n";
+
+        let formatted = format_source(input, &options).unwrap();
+        assert_eq!(formatted, expected);
+        // 再整形しても変わらず、一行に戻せば元の式と同じになる
+        assert_eq!(format_source(&formatted, &options).unwrap(), expected);
+        assert_eq!(fmt(&formatted), fmt(input));
+    }
+
+    #[test]
+    fn test_format_check() {
+        let formatter = Formatter::default();
+
+        assert!(formatter.is_formatted("1 + 2\n").unwrap());
+        assert!(!formatter.is_formatted("1+2\n").unwrap());
+        assert!(formatter.is_formatted("1 +").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use crate::editor::Highlighter;
+use crate::error::Error;
+use crate::lexer::lexer_lossless;
+use crate::parser::Parser;
+use crate::repl::split_command;
+use crate::token::*;
+
+// 端末の SGR の色番号
+const NUMBER: u8 = 36;
+const OPERATOR: u8 = 33;
+const HISTORY: u8 = 35;
+const COMMENT: u8 = 90;
+const MATCH: u8 = 32;
+const ERROR: u8 = 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+struct Style {
+    color: Option<u8>,
+    bold: bool,
+    underline: bool,
+}
+
+impl Style {
+    fn sgr(&self) -> String {
+        let mut codes = vec!["0".to_string()];
+        if self.bold {
+            codes.push("1".to_string());
+        }
+        if self.underline {
+            codes.push("4".to_string());
+        }
+        if let Some(c) = self.color {
+            codes.push(c.to_string());
+        }
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+// 入力中の行に色を付ける。errors が真なら字句・構文の誤りの範囲に赤い下線を引く。
+// 続きの行は単独では式として完結しないので errors を偽にする
+#[derive(Debug, Clone, Copy)]
+pub struct SyntaxHighlighter<'a> {
+    pub parser: &'a Parser,
+    pub errors: bool,
+}
+
+impl Highlighter for SyntaxHighlighter<'_> {
+    fn highlight(&self, line: &str, cursor: usize) -> String {
+        highlight(line, cursor, self.parser, self.errors)
+    }
+}
+
+// cursor は文字単位の位置。`:` で始まる行はコマンド名を太字にし、引数だけを式として扱う
+pub fn highlight(line: &str, cursor: usize, parser: &Parser, errors: bool) -> String {
+    let mut styles = vec![Style::default(); line.chars().count()];
+
+    match line.strip_prefix(':') {
+        Some(command) => {
+            let (name, args) = split_command(command);
+            for s in &mut styles[..name.chars().count() + 1] {
+                s.bold = true;
+            }
+            if !args.is_empty() {
+                // args は前後の空白を除いてあるので、始まりの位置は前の空白から求める
+                let rest = &command[name.len()..];
+                let offset = 1 + name.len() + rest.len() - rest.trim_start().len();
+                let skip = line[..offset].chars().count();
+                let cursor = cursor.saturating_sub(skip);
+                style_expr(args, cursor, parser, false, &mut styles[skip..]);
+            }
+        }
+        None => style_expr(line, cursor, parser, errors, &mut styles),
+    }
+
+    let mut out = String::new();
+    let mut current = Style::default();
+    for (c, style) in line.chars().zip(styles) {
+        if style != current {
+            out.push_str(&style.sgr());
+            current = style;
+        }
+        out.push(c);
+    }
+    if current != Style::default() {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+fn style_expr(line: &str, cursor: usize, parser: &Parser, errors: bool, styles: &mut [Style]) {
+    let span = |styles: &mut [Style], loc: &Location, f: &dyn Fn(&mut Style)| {
+        let (start, end) = (char_index(line, loc.start()), char_index(line, loc.end()));
+        styles[start..end].iter_mut().for_each(f);
+    };
+
+    let (tokens, trailing) = match lexer_lossless(line) {
+        Ok(lexed) => lexed,
+        Err(e) => {
+            if errors {
+                span(styles, &e.loc(), &underline);
+            }
+            return;
+        }
+    };
+
+    let comments = tokens
+        .iter()
+        .flat_map(|t| t.leading.iter())
+        .chain(trailing.iter())
+        .filter(|t| matches!(t.value, TriviaKind::Comment(_)));
+    for t in comments {
+        span(styles, &t.loc(), &|s| s.color = Some(COMMENT));
+    }
+
+    for t in &tokens {
+        let color = match t.kind() {
+            TokenKind::Number(_) | TokenKind::Imaginary(_) => Some(NUMBER),
+            TokenKind::History(_) => Some(HISTORY),
+            TokenKind::Equal
+            | TokenKind::Plus
+            | TokenKind::Minus
+            | TokenKind::Asterisk
+            | TokenKind::Slash
+            | TokenKind::Bang
+            | TokenKind::Percent
+            | TokenKind::At
+            | TokenKind::Caret => Some(OPERATOR),
+            _ => None,
+        };
+        span(styles, &t.loc(), &|s| s.color = color);
+    }
+
+    // カーソルの上か直前の括弧と対になる括弧
+    for (open, close) in bracket_pairs(&tokens) {
+        let at = |loc: &Location| {
+            let i = char_index(line, loc.start());
+            i == cursor || i + 1 == cursor
+        };
+        if at(&open) || at(&close) {
+            for loc in [&open, &close] {
+                span(styles, loc, &|s| {
+                    s.bold = true;
+                    s.color = Some(MATCH);
+                });
+            }
+            break;
+        }
+    }
+
+    if errors && !tokens.is_empty() {
+        if let Err(e) = parser.parse_cst(tokens, trailing) {
+            if let Some(loc) = Error::from(e).loc() {
+                span(styles, &loc, &underline);
+            }
+        }
+    }
+}
+
+fn underline(s: &mut Style) {
+    s.underline = true;
+    s.color = Some(ERROR);
+}
+
+fn bracket_pairs(tokens: &[CstToken]) -> Vec<(Location, Location)> {
+    let mut stack = Vec::new();
+    let mut pairs = Vec::new();
+    for t in tokens {
+        match t.kind() {
+            TokenKind::LParen | TokenKind::LBracket => stack.push(t),
+            TokenKind::RParen | TokenKind::RBracket => {
+                if let Some(open) = stack.pop() {
+                    pairs.push((open.loc(), t.loc()));
+                }
+            }
+            _ => {}
+        }
+    }
+    pairs
+}
+
+// byte より前にある文字の数。位置が文字の途中でもその文字を含める
+fn char_index(line: &str, byte: usize) -> usize {
+    line.char_indices().take_while(|(i, _)| *i < byte).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(s: &str) -> String {
+        let mut out = String::new();
+        let mut escape = false;
+        for c in s.chars() {
+            match c {
+                '\x1b' => escape = true,
+                'm' if escape => escape = false,
+                c if !escape => out.push(c),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_highlight_tokens_and_parens() {
+        let parser = Parser::default();
+
+        assert_eq!(
+            highlight("12+x", 0, &parser, true),
+            "\x1b[0;36m12\x1b[0;33m+\x1b[0mx"
+        );
+        // カーソルが `)` の直後にあれば対の `(` も強調する
+        assert_eq!(
+            highlight("(1)", 3, &parser, true),
+            "\x1b[0;1;32m(\x1b[0;36m1\x1b[0;1;32m)\x1b[0m"
+        );
+        let s = highlight(":trace 2 # two", 0, &parser, true);
+        assert!(s.starts_with("\x1b[0;1m:trace\x1b[0m \x1b[0;36m2"));
+        assert!(s.ends_with("\x1b[0;90m# two\x1b[0m"));
+        assert_eq!(plain(&s), ":trace 2 # two");
+
+        // 引数の後ろの空白は色付けの位置をずらさない
+        assert_eq!(
+            highlight(":trace 1 ", 0, &parser, true),
+            "\x1b[0;1m:trace\x1b[0m \x1b[0;36m1\x1b[0m "
+        );
+        assert_eq!(
+            highlight(":trace 1\u{3000}", 0, &parser, true),
+            "\x1b[0;1m:trace\x1b[0m \x1b[0;36m1\x1b[0m\u{3000}"
+        );
+    }
+
+    #[test]
+    fn test_highlight_errors() {
+        let parser = Parser::default();
+
+        assert_eq!(
+            highlight("1 + * 2", 0, &parser, true),
+            "\x1b[0;36m1\x1b[0m \x1b[0;33m+\x1b[0m \x1b[0;4;31m*\x1b[0m \x1b[0;36m2\x1b[0m"
+        );
+        assert_eq!(highlight("é+1", 0, &parser, true), "\x1b[0;4;31mé\x1b[0m+1");
+        assert_eq!(plain(&highlight("1 + * 2", 0, &parser, false)), "1 + * 2");
+        assert!(!highlight("1 + * 2", 0, &parser, false).contains("4;31"));
+    }
+}
@@ -0,0 +1,177 @@
+// 真の値を必ず含む閉区間。演算のたびに端点を外側へ 1 ulp 丸める
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Interval {
+    pub const ENTIRE: Interval = Interval {
+        lo: f64::NEG_INFINITY,
+        hi: f64::INFINITY,
+    };
+
+    pub fn new(lo: f64, hi: f64) -> Self {
+        Self { lo, hi }
+    }
+
+    pub fn point(x: f64) -> Self {
+        Self::new(x, x)
+    }
+
+    // 丸め誤差のある値 x を含む区間
+    pub fn around(x: f64) -> Self {
+        Self::new(x, x).widen()
+    }
+
+    pub fn contains(&self, x: f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.lo == 0.0 && self.hi == 0.0
+    }
+
+    pub fn width(&self) -> f64 {
+        self.hi - self.lo
+    }
+
+    fn widen(self) -> Self {
+        Self::new(self.lo.next_down(), self.hi.next_up())
+    }
+
+    pub fn add(&self, other: &Interval) -> Self {
+        Self::new(self.lo + other.lo, self.hi + other.hi).widen()
+    }
+
+    pub fn sub(&self, other: &Interval) -> Self {
+        Self::new(self.lo - other.hi, self.hi - other.lo).widen()
+    }
+
+    pub fn mul(&self, other: &Interval) -> Self {
+        let products = [
+            self.lo * other.lo,
+            self.lo * other.hi,
+            self.hi * other.lo,
+            self.hi * other.hi,
+        ];
+        let lo = products.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = products.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        Self::new(lo, hi).widen()
+    }
+
+    // 0 を含む区間で割ると結果は非有界になる。二つに分かれる場合はその全体を返す
+    pub fn div(&self, other: &Interval) -> Self {
+        if !other.contains(0.0) {
+            return self.mul(&Self::new(1.0 / other.hi, 1.0 / other.lo).widen());
+        }
+        if self.contains(0.0) || (other.lo < 0.0 && other.hi > 0.0) {
+            return Self::ENTIRE;
+        }
+
+        let (a, b, c, d) = (self.lo, self.hi, other.lo, other.hi);
+        match (b < 0.0, c == 0.0) {
+            (true, true) => Self::new(f64::NEG_INFINITY, b / d),
+            (false, true) => Self::new(a / d, f64::INFINITY),
+            (true, false) => Self::new(b / c, f64::INFINITY),
+            (false, false) => Self::new(f64::NEG_INFINITY, a / c),
+        }
+        .widen()
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::new(-self.hi, -self.lo)
+    }
+
+    pub fn abs(&self) -> Self {
+        if self.lo >= 0.0 {
+            *self
+        } else if self.hi <= 0.0 {
+            self.neg()
+        } else {
+            Self::new(0.0, self.hi.max(-self.lo))
+        }
+    }
+
+    // 整数乗以外は負の部分を含まないこと
+    pub fn powf(&self, y: f64) -> Option<Self> {
+        if y == 0.0 {
+            return Some(Self::point(1.0));
+        }
+        if y.fract() != 0.0 && self.lo < 0.0 {
+            return None;
+        }
+        if y < 0.0 {
+            return Some(Self::point(1.0).div(&self.powf(-y)?));
+        }
+        // 正の指数では単調増加。偶数乗は絶対値を取ってから
+        let x = if y % 2.0 == 0.0 { self.abs() } else { *self };
+        Some(
+            Self::new(x.lo.powf(y), x.hi.powf(y))
+                .widen()
+                .keep_zero(x.lo),
+        )
+    }
+
+    // 負の部分を含まないこと
+    pub fn sqrt(&self) -> Option<Self> {
+        (self.lo >= 0.0).then(|| {
+            Self::new(self.lo.sqrt(), self.hi.sqrt())
+                .widen()
+                .keep_zero(self.lo)
+        })
+    }
+
+    // 0 の冪や平方根は誤差なく 0 なので、広げた下端を 0 に戻す
+    fn keep_zero(self, lo: f64) -> Self {
+        if lo == 0.0 {
+            Self::new(0.0, self.hi)
+        } else {
+            self
+        }
+    }
+}
+
+impl std::fmt::Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_encloses() {
+        let a = Interval::around(1.9);
+        let b = Interval::new(2.9, 3.1);
+        let c = a.mul(&b);
+
+        assert!(c.contains(1.9 * 2.9) && c.contains(1.9 * 3.1));
+        assert!(c.lo < 5.51 && c.hi > 5.89 && c.width() < 0.38 + 1e-9);
+        assert!(Interval::new(0.1, 0.1)
+            .add(&Interval::new(0.2, 0.2))
+            .contains(0.3));
+        assert_eq!(Interval::new(-1.0, 2.0).neg(), Interval::new(-2.0, 1.0));
+
+        // 偶数乗や平方根の下端は 0 ちょうど
+        let r = Interval::new(-2.0, 1.0).powf(2.0).unwrap();
+        assert!(r.lo == 0.0 && r.hi >= 4.0);
+        assert_eq!(Interval::new(0.0, 4.0).sqrt().unwrap().lo, 0.0);
+        assert!(Interval::new(-2.0, -1.0).powf(3.0).unwrap().contains(-8.0));
+    }
+
+    #[test]
+    fn test_interval_div_by_zero() {
+        let one = Interval::point(1.0);
+
+        assert_eq!(one.div(&Interval::new(-1.0, 1.0)), Interval::ENTIRE);
+        let r = one.div(&Interval::new(0.0, 2.0));
+        assert!(r.lo <= 0.5 && r.hi == f64::INFINITY);
+        let r = one.div(&Interval::new(-2.0, 0.0));
+        assert!(r.lo == f64::NEG_INFINITY && r.hi >= -0.5);
+        let r = Interval::new(1.0, 2.0).div(&Interval::new(4.0, 8.0));
+        assert!(r.contains(0.125) && r.contains(0.5));
+    }
+}
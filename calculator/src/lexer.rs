@@ -1,31 +1,111 @@
+use crate::limits::Limits;
 use crate::token::*;
 
 pub fn lexer(input: &str) -> Result<Vec<Token>, LexError> {
+    let (tokens, _) = lexer_lossless(input)?;
+
+    Ok(tokens.into_iter().map(|t| t.token).collect())
+}
+
+// トリビアも含めてすべて保持する。末尾のトリビアは別に返す
+pub fn lexer_lossless(input: &str) -> Result<(Vec<CstToken>, Vec<Trivia>), LexError> {
+    lexer_lossless_at(input, &Location::new(0, input.len()))
+}
+
+// input のうち loc の範囲だけを字句解析する。位置は input 全体での位置になる
+pub fn lexer_lossless_at(
+    input: &str,
+    loc: &Location,
+) -> Result<(Vec<CstToken>, Vec<Trivia>), LexError> {
+    lexer_limited(input, loc, &Limits::default())
+}
+
+// 入力の長さと数値リテラルの大きさを limits で制限する
+pub fn lexer_limited(
+    input: &str,
+    loc: &Location,
+    limits: &Limits,
+) -> Result<(Vec<CstToken>, Vec<Trivia>), LexError> {
+    let len = loc.end() - loc.start();
+    if len > limits.max_input_bytes {
+        let start = loc.start() + limits.max_input_bytes;
+        return Err(LexError::new(
+            LexErrorKind::InputTooLong {
+                len,
+                max: limits.max_input_bytes,
+            },
+            Location::new(start, loc.end()),
+        ));
+    }
+
     let mut tokens = Vec::new();
+    let mut trivia = Vec::new();
 
-    let input = input.as_bytes();
-    let mut pos = 0;
+    let source = &input[..loc.end()];
+    let input = source.as_bytes();
+    let mut pos = loc.start();
 
     while pos < input.len() {
-        // 区切り文字は先に処理
-        if [b' ', b'\n', b'\t'].contains(&input[pos]) {
-            let p = lex_spaces(input, pos)?;
+        // 区切り文字とコメントは次のトークンの前置きとして先に処理
+        let lexed = match input[pos] {
+            b' ' | b'\t' | b'\r' => Some(lex_spaces(source, pos)?),
+            b'\n' => Some(lex_newline(input, pos)?),
+            b'#' => Some(lex_comment(source, pos)?),
+            _ => None,
+        };
+        if let Some((t, p)) = lexed {
+            trivia.push(t);
             pos = p;
 
             continue;
         }
 
-        //  数字か記号か
+        //  数字か識別子か記号か
         let (token, p) = match input[pos] {
-            b'0'..=b'9' => lex_number(input, pos)?,
-            _ => lex_symbol(input, pos)?,
+            b'0'..=b'9' => lex_number(input, pos, limits.max_magnitude)?,
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => lex_ident(source, pos)?,
+            b'$' => lex_history(input, pos)?,
+            _ => lex_symbol(source, pos)?,
         };
 
-        tokens.push(token);
+        let leading = std::mem::take(&mut trivia);
+        tokens.push(CstToken::new(token, &source[pos..p], leading));
         pos = p;
     }
 
-    Ok(tokens)
+    Ok((tokens, trivia))
+}
+
+// 括弧の外にある改行で文を区切る。空行やコメントだけの行も一つの文として返す
+pub fn split_statements(input: &str) -> Vec<Location> {
+    let bytes = input.as_bytes();
+    let mut statements = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth = depth.saturating_sub(1),
+            b'#' => {
+                pos = recognize_many(bytes, pos, |b| b != b'\n');
+                continue;
+            }
+            b'\n' if depth == 0 => {
+                statements.push(Location::new(start, pos));
+                start = pos + 1;
+            }
+            _ => {}
+        }
+        pos += 1;
+    }
+
+    if start < bytes.len() {
+        statements.push(Location::new(start, bytes.len()));
+    }
+
+    statements
 }
 
 // クロージャーに適応するcharの連続性を確認
@@ -52,26 +132,94 @@ fn consume_byte(input: &[u8], pos: usize, b: u8) -> Result<(u8, usize), LexError
     Ok((b, pos + 1))
 }
 
-fn lex_spaces(input: &[u8], pos: usize) -> Result<usize, LexError> {
+fn lex_spaces(source: &str, pos: usize) -> Result<(Trivia, usize), LexError> {
+    let start = pos;
+    let end = recognize_many(source.as_bytes(), start, |b| b" \t\r".contains(&b));
+
+    Ok((
+        Trivia::whitespace(&source[start..end], Location::new(start, end)),
+        end,
+    ))
+}
+
+fn lex_newline(input: &[u8], start: usize) -> Result<(Trivia, usize), LexError> {
+    consume_byte(input, start, b'\n')
+        .map(|(_, end)| (Trivia::newline(Location::new(start, end)), end))
+}
+
+// # から行末までをコメントとする。CRLF の行では \r を含めない
+fn lex_comment(source: &str, pos: usize) -> Result<(Trivia, usize), LexError> {
     let start = pos;
-    let end = recognize_many(input, start, |b| b" \n\t".contains(&b));
+    let end = recognize_many(source.as_bytes(), start, |b| b != b'\n' && b != b'\r');
 
-    Ok(end)
+    Ok((
+        Trivia::comment(&source[start..end], Location::new(start, end)),
+        end,
+    ))
 }
 
-fn lex_number(input: &[u8], pos: usize) -> Result<(Token, usize), LexError> {
+fn lex_number(input: &[u8], pos: usize, max: f64) -> Result<(Token, usize), LexError> {
     use std::str::from_utf8;
 
     let start = pos;
-    let end = recognize_many(input, start, |b| b"1234567890".contains(&b));
+    let mut end = recognize_many(input, start, |b| b.is_ascii_digit());
+    // 小数点の後には数字が一つ以上必要
+    if input.get(end) == Some(&b'.') && input.get(end + 1).is_some_and(u8::is_ascii_digit) {
+        end = recognize_many(input, end + 1, |b| b.is_ascii_digit());
+    }
+
+    let n: f64 = from_utf8(&input[start..end]).unwrap().parse().unwrap();
+    // 桁が多すぎると無限大になる
+    if !n.is_finite() || n > max {
+        return Err(LexError::new(
+            LexErrorKind::NumberTooLarge,
+            Location::new(start, end),
+        ));
+    }
 
-    let n = from_utf8(&input[start..end]).unwrap().parse().unwrap();
+    // 直後に識別子として続かない `i` は虚数単位の接尾辞
+    let suffix = input.get(end) == Some(&b'i')
+        && !input
+            .get(end + 1)
+            .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_');
+    if suffix {
+        return Ok((Token::imaginary(n, Location::new(start, end + 1)), end + 1));
+    }
 
     Ok((Token::number(n, Location::new(start, end)), end))
 }
 
-fn lex_symbol(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
+fn lex_ident(source: &str, pos: usize) -> Result<(Token, usize), LexError> {
+    let start = pos;
+    let end = recognize_many(source.as_bytes(), start, |b| {
+        b.is_ascii_alphanumeric() || b == b'_'
+    });
+
+    Ok((
+        Token::ident(&source[start..end], Location::new(start, end)),
+        end,
+    ))
+}
+
+// `$n` は n 番目の結果
+fn lex_history(input: &[u8], pos: usize) -> Result<(Token, usize), LexError> {
+    use std::str::from_utf8;
+
+    let start = pos;
+    let end = recognize_many(input, start + 1, |b| b.is_ascii_digit());
+    let n = from_utf8(&input[start + 1..end]).unwrap().parse().ok();
+
+    match n {
+        Some(n) => Ok((Token::history(n, Location::new(start, end)), end)),
+        None => Err(LexError::invalid_char('$', Location::new(start, start + 1))),
+    }
+}
+
+fn lex_symbol(source: &str, start: usize) -> Result<(Token, usize), LexError> {
+    let input = source.as_bytes();
     match input[start] {
+        b'=' => consume_byte(input, start, input[start])
+            .map(|(_, end)| (Token::equal(Location::new(start, end)), end)),
         b'+' => consume_byte(input, start, input[start])
             .map(|(_, end)| (Token::plus(Location::new(start, end)), end)),
         b'-' => consume_byte(input, start, input[start])
@@ -84,10 +232,28 @@ fn lex_symbol(input: &[u8], start: usize) -> Result<(Token, usize), LexError> {
             .map(|(_, end)| (Token::lparen(Location::new(start, end)), end)),
         b')' => consume_byte(input, start, input[start])
             .map(|(_, end)| (Token::rparen(Location::new(start, end)), end)),
-        b => Err(LexError::invalid_char(
-            b as char,
-            Location::new(start, start + 1),
-        )),
+        b'!' => consume_byte(input, start, input[start])
+            .map(|(_, end)| (Token::bang(Location::new(start, end)), end)),
+        b'%' => consume_byte(input, start, input[start])
+            .map(|(_, end)| (Token::percent(Location::new(start, end)), end)),
+        b'@' => consume_byte(input, start, input[start])
+            .map(|(_, end)| (Token::at(Location::new(start, end)), end)),
+        b'^' => consume_byte(input, start, input[start])
+            .map(|(_, end)| (Token::caret(Location::new(start, end)), end)),
+        b'[' => consume_byte(input, start, input[start])
+            .map(|(_, end)| (Token::lbracket(Location::new(start, end)), end)),
+        b']' => consume_byte(input, start, input[start])
+            .map(|(_, end)| (Token::rbracket(Location::new(start, end)), end)),
+        b',' => consume_byte(input, start, input[start])
+            .map(|(_, end)| (Token::comma(Location::new(start, end)), end)),
+        // ASCII 以外の文字はバイトではなく文字として報告する
+        _ => {
+            let c = source[start..].chars().next().unwrap();
+            Err(LexError::invalid_char(
+                c,
+                Location::new(start, start + c.len_utf8()),
+            ))
+        }
     }
 }
 
@@ -101,19 +267,140 @@ mod tests {
         assert!(result.is_ok());
 
         let test_tokens = vec![
-            Token::number(1, Location::new(0, 1)),
+            Token::number(1.0, Location::new(0, 1)),
             Token::plus(Location::new(2, 3)),
-            Token::number(2, Location::new(4, 5)),
+            Token::number(2.0, Location::new(4, 5)),
             Token::asterisk(Location::new(6, 7)),
-            Token::number(3, Location::new(8, 9)),
+            Token::number(3.0, Location::new(8, 9)),
             Token::minus(Location::new(10, 11)),
             Token::minus(Location::new(12, 13)),
-            Token::number(10, Location::new(14, 16)),
+            Token::number(10.0, Location::new(14, 16)),
+        ];
+
+        assert_eq!(result, Ok(test_tokens));
+
+        // CRLF の行末と ASCII 以外の誤った文字
+        assert_eq!(lexer("1 +\r\n2 # two\r\n").unwrap().len(), 3);
+        assert_eq!(
+            lexer("1 + é"),
+            Err(LexError::invalid_char('é', Location::new(4, 6)))
+        );
+    }
+
+    #[test]
+    fn test_lexer_ident() {
+        let result = lexer("x_1 = ab*2");
+
+        let test_tokens = vec![
+            Token::ident("x_1", Location::new(0, 3)),
+            Token::equal(Location::new(4, 5)),
+            Token::ident("ab", Location::new(6, 8)),
+            Token::asterisk(Location::new(8, 9)),
+            Token::number(2.0, Location::new(9, 10)),
+        ];
+
+        assert_eq!(result, Ok(test_tokens));
+    }
+
+    #[test]
+    fn test_lexer_imaginary() {
+        let result = lexer("4i 2in");
+
+        let test_tokens = vec![
+            Token::imaginary(4.0, Location::new(0, 2)),
+            Token::number(2.0, Location::new(3, 4)),
+            Token::ident("in", Location::new(4, 6)),
         ];
 
         assert_eq!(result, Ok(test_tokens));
     }
 
+    #[test]
+    fn test_lexer_history() {
+        assert_eq!(
+            lexer("$12+_"),
+            Ok(vec![
+                Token::history(12, Location::new(0, 3)),
+                Token::plus(Location::new(3, 4)),
+                Token::ident("_", Location::new(4, 5)),
+            ])
+        );
+        assert_eq!(
+            lexer("$x"),
+            Err(LexError::invalid_char('$', Location::new(0, 1)))
+        );
+    }
+
+    #[test]
+    fn test_lexer_lossless() {
+        let input = "1 +\t2 # sum\n";
+        let (tokens, trailing) = lexer_lossless(input).unwrap();
+
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(
+            tokens[2].leading,
+            vec![Trivia::whitespace("\t", Location::new(3, 4))]
+        );
+        assert_eq!(
+            trailing,
+            vec![
+                Trivia::whitespace(" ", Location::new(5, 6)),
+                Trivia::comment("# sum", Location::new(6, 11)),
+                Trivia::newline(Location::new(11, 12)),
+            ]
+        );
+
+        let restored: String = tokens.iter().map(|t| t.to_string()).collect::<String>()
+            + &trailing
+                .iter()
+                .map(|t| t.value.to_string())
+                .collect::<String>();
+        assert_eq!(restored, input);
+        assert_eq!(lexer("1 # one\n+ 2").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let input = "1 + 2\n\n(3 # (\n * 4)\n5";
+        let statements = split_statements(input);
+
+        assert_eq!(
+            statements,
+            vec![
+                Location::new(0, 5),
+                Location::new(6, 6),
+                Location::new(7, 19),
+                Location::new(20, 21),
+            ]
+        );
+
+        let (tokens, _) = lexer_lossless_at(input, &statements[2]).unwrap();
+        assert_eq!(tokens[0].token, Token::lparen(Location::new(7, 8)));
+        assert_eq!(tokens.len(), 5);
+    }
+
+    #[test]
+    fn test_lexer_limits() {
+        let limits = Limits {
+            max_input_bytes: 8,
+            max_magnitude: 1000.0,
+            ..Limits::default()
+        };
+        let lex =
+            |s: &str| lexer_limited(s, &Location::new(0, s.len()), &limits).map_err(|e| e.value);
+
+        assert!(lex("1 + 999").is_ok());
+        assert_eq!(
+            lex("1 + 2 + 3"),
+            Err(LexErrorKind::InputTooLong { len: 9, max: 8 })
+        );
+        assert_eq!(lex("1001"), Err(LexErrorKind::NumberTooLarge));
+        assert_eq!(
+            lexer(&"9".repeat(400)).map_err(|e| e.value),
+            Err(LexErrorKind::NumberTooLarge)
+        );
+    }
+
     #[test]
     fn test_debug() {
         if [b' ', b'\n', b'\t'].contains(&b'\t') {
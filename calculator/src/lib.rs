@@ -0,0 +1,128 @@
+//! Arithmetic expression calculator.
+//!
+//! The crate is organised as a pipeline: [`tokenize`] turns source text into
+//! tokens, [`parse`] builds an [`Ast`], and [`evaluate`] computes a [`Value`].
+//! An [`Engine`] runs the same pipeline while keeping session state such as
+//! variables between calls.
+//!
+//! ```
+//! use calculator::{evaluate, Engine, Value};
+//!
+//! assert_eq!(evaluate("1 + 2 * 3").unwrap(), Value::Number(7.0));
+//!
+//! let mut engine = Engine::new();
+//! engine.eval("rate = 3").unwrap();
+//! assert_eq!(engine.eval("rate * 2").unwrap(), Value::Number(6.0));
+//! ```
+
+pub(crate) mod batch;
+pub(crate) mod builtin;
+pub(crate) mod capi;
+pub(crate) mod compile;
+pub(crate) mod complete;
+pub(crate) mod complex;
+pub(crate) mod cst;
+pub(crate) mod editor;
+pub(crate) mod engine;
+pub(crate) mod error;
+pub(crate) mod eval;
+pub(crate) mod fix;
+pub(crate) mod format;
+pub(crate) mod highlight;
+pub(crate) mod interval;
+pub(crate) mod json;
+pub(crate) mod lexer;
+pub(crate) mod limits;
+pub(crate) mod lsp;
+pub(crate) mod numfmt;
+pub(crate) mod parser;
+pub(crate) mod repl;
+pub(crate) mod rpn;
+pub(crate) mod sheet;
+pub(crate) mod solve;
+pub(crate) mod token;
+pub(crate) mod trace;
+pub(crate) mod usage;
+pub(crate) mod value;
+
+// 内部のモジュールは公開せず、ライブラリとして使う型だけをここから公開する
+pub use crate::compile::Compiled;
+pub use crate::complex::Complex;
+pub use crate::engine::Engine;
+pub use crate::error::Error;
+pub use crate::eval::{Env, EvalError, EvalErrorKind, Mode, Value};
+pub use crate::interval::Interval;
+pub use crate::limits::Limits;
+pub use crate::parser::{
+    Assoc, Ast, AstKind, BinOp, BinOpKind, Expected, OperatorTable, Parser, ParserError, UniOp,
+    UniOpKind,
+};
+pub use crate::rpn::ReversePolishNotation;
+pub use crate::sheet::{CellRef, Sheet, SheetError};
+pub use crate::solve::{Root, Solution};
+pub use crate::token::{LexError, LexErrorKind, Location, Token, TokenKind};
+pub use crate::trace::{Step, Trace};
+pub use crate::value::{Array, Shape};
+
+// 同じパッケージの実行ファイル calculator, calcfmt, calc-lsp が使うもの。安定した API ではない
+#[doc(hidden)]
+pub mod cli {
+    pub use crate::batch::{run_batch, OutputFormat};
+    pub use crate::editor::{Editor, Highlighter, History, Input};
+    pub use crate::format::{format_source, FormatOptions, Formatter};
+    pub use crate::highlight::SyntaxHighlighter;
+    pub use crate::lsp::{read_message, write_message, Server};
+    pub use crate::numfmt::{NumberFormat, Precision};
+    pub use crate::repl::{split_command, Flow, Repl};
+    pub use crate::usage::args_or_exit;
+}
+
+/// Splits `input` into tokens, dropping whitespace and comments.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    Ok(lexer::lexer(input)?)
+}
+
+/// Parses a single statement with the standard operator table.
+pub fn parse(input: &str) -> Result<Ast, Error> {
+    input.parse()
+}
+
+/// Evaluates a single statement in a fresh session.
+pub fn evaluate(input: &str) -> Result<Value, Error> {
+    Engine::new().eval(input)
+}
+
+/// Compiles `input` into a function of `params` for fast repeated evaluation.
+///
+/// Variable names are resolved to argument slots once, so
+/// [`Compiled::call`] takes the values in the same order as `params`.
+///
+/// ```
+/// let f = calculator::compile("a * x + b", &["a", "x", "b"]).unwrap();
+/// assert_eq!(f.call(&[2.0, 3.0, 1.0]).unwrap(), 7.0);
+/// ```
+pub fn compile(input: &str, params: &[&str]) -> Result<Compiled, Error> {
+    Engine::new().compile(input, params)
+}
+
+/// Returns `true` when `input` ends inside an unclosed parenthesis or bracket, i.e. more
+/// lines are needed before it can be parsed.
+pub fn is_incomplete(input: &str) -> bool {
+    match parse(input) {
+        Err(Error::Parser(ParserError::UnclosedOpenParen(_))) => true,
+        Err(Error::Parser(ParserError::Eof { .. })) => open_parens(input) > 0,
+        _ => false,
+    }
+}
+
+fn open_parens(input: &str) -> usize {
+    let Ok(tokens) = tokenize(input) else {
+        return 0;
+    };
+
+    tokens.iter().fold(0, |depth, t| match t.value {
+        TokenKind::LParen | TokenKind::LBracket => depth + 1,
+        TokenKind::RParen | TokenKind::RBracket => depth.saturating_sub(1),
+        _ => depth,
+    })
+}
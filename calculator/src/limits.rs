@@ -0,0 +1,29 @@
+// 信頼できない入力を扱うための上限。字句解析・構文解析・評価のそれぞれで確かめる
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    // 一つの文の長さ(バイト)
+    pub max_input_bytes: usize,
+    // 括弧や前置演算子の入れ子の深さ。既定値は 2 MiB のスタックでも最適化なしで収まる
+    pub max_depth: usize,
+    // 構文木の深さ。`1 + 2 + 3` のような左結合の連なりも一段ずつ数える。
+    // 評価や整形は木を再帰でたどるので、既定値は 2 MiB のスタックでも最適化なしで収まる
+    pub max_tree_depth: usize,
+    pub max_nodes: usize,
+    // 評価する節の数
+    pub max_steps: usize,
+    // リテラルと計算結果の絶対値
+    pub max_magnitude: f64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_input_bytes: 1 << 20,
+            max_depth: 64,
+            max_tree_depth: 256,
+            max_nodes: 1 << 20,
+            max_steps: 10_000_000,
+            max_magnitude: f64::INFINITY,
+        }
+    }
+}
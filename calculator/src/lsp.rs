@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::builtin::builtins;
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::eval::{Value, SOLVE};
+use crate::fix::suggest;
+use crate::format::{FormatOptions, Formatter};
+use crate::json::Json;
+use crate::lexer::split_statements;
+use crate::token::Location;
+
+// JSON-RPC のエラーコード
+const PARSE_ERROR: f64 = -32700.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+// `Content-Length` ヘッダーで区切られたメッセージを一つ読む。入力の終わりなら None
+pub fn read_message(r: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no Content-Length"))?;
+    let mut body = vec![0; len];
+    r.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(w: &mut dyn Write, msg: &Json) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+// 文の位置とその評価結果
+type Results = Vec<(Location, Result<Value, Error>)>;
+
+// 開いている文書を全文で持ち、変更のたびに頭から評価し直す
+#[derive(Debug, Clone, Default)]
+pub struct Server {
+    engine: Engine,
+    formatter: Formatter,
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exit: Option<i32>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 文書の評価は engine の複製から始める
+    pub fn with_engine(engine: Engine) -> Self {
+        Self {
+            formatter: Formatter::new(engine.parser().clone(), FormatOptions::default()),
+            engine,
+            ..Self::default()
+        }
+    }
+
+    // exit を受け取った後の終了コード。shutdown の前に exit が来たら 1
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    // 送り返すメッセージ(応答と通知)を返す
+    pub fn handle_message(&mut self, body: &str) -> Vec<Json> {
+        match body.parse::<Json>() {
+            Ok(msg) => self.handle(&msg),
+            Err(e) => vec![error_response(Json::Null, PARSE_ERROR, &e)],
+        }
+    }
+
+    pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+        let method = msg.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = msg.get("params").unwrap_or(&Json::Null);
+        let id = msg.get("id").cloned();
+
+        let result = match method {
+            "initialize" => Some(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Some(Json::Null)
+            }
+            "exit" => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                None
+            }
+            "textDocument/didOpen" => {
+                let doc = params.get("textDocument");
+                let text = doc.and_then(|d| d.get("text")).and_then(Json::as_str);
+                return match (uri(params), text) {
+                    (Some(uri), Some(text)) => self.update(uri, text.to_string()),
+                    _ => vec![],
+                };
+            }
+            "textDocument/didChange" => {
+                // 全文同期なので最後の変更が文書全体になる
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|c| c.last())
+                    .and_then(|c| c.get("text"))
+                    .and_then(Json::as_str);
+                return match (uri(params), text) {
+                    (Some(uri), Some(text)) => self.update(uri, text.to_string()),
+                    _ => vec![],
+                };
+            }
+            "textDocument/didClose" => {
+                return match uri(params) {
+                    Some(uri) => {
+                        self.documents.remove(uri);
+                        vec![diagnostics(uri, vec![])]
+                    }
+                    None => vec![],
+                };
+            }
+            "textDocument/hover" => Some(self.hover(params).unwrap_or(Json::Null)),
+            "textDocument/completion" => Some(self.completion(params)),
+            "textDocument/formatting" => Some(self.formatting(params).unwrap_or(Json::Null)),
+            "textDocument/codeAction" => Some(self.code_actions(params).unwrap_or(Json::Null)),
+            _ => None,
+        };
+
+        // 通知には応答しない
+        match (id, result) {
+            (Some(id), Some(result)) => vec![Json::object([
+                ("jsonrpc", Json::string("2.0")),
+                ("id", id),
+                ("result", result),
+            ])],
+            (Some(id), None) if method != "exit" => vec![error_response(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("unknown method: {}", method),
+            )],
+            _ => vec![],
+        }
+    }
+
+    fn update(&mut self, uri: &str, text: String) -> Vec<Json> {
+        let (results, _) = self.evaluate(&text);
+        let items = results
+            .iter()
+            .filter_map(|(loc, r)| r.as_ref().err().map(|e| (loc, e)))
+            .map(|(loc, e)| {
+                let at = e.loc().unwrap_or(Location::new(loc.end(), loc.end()));
+                Json::object([
+                    ("range", range(&text, &at)),
+                    ("severity", Json::Number(1.0)),
+                    ("source", Json::string("calculator")),
+                    ("message", Json::string(e.to_string())),
+                ])
+            })
+            .collect();
+
+        self.documents.insert(uri.to_string(), text);
+        vec![diagnostics(uri, items)]
+    }
+
+    // 文ごとの結果と、すべて評価した後のエンジン
+    fn evaluate(&self, text: &str) -> (Results, Engine) {
+        let mut engine = self.engine.clone();
+        let mut results = Vec::new();
+        for loc in split_statements(text) {
+            match engine.eval_statement(text, &loc) {
+                Ok(None) => {}
+                Ok(Some(v)) => results.push((loc, Ok(v))),
+                Err(e) => results.push((loc, Err(e))),
+            }
+        }
+        (results, engine)
+    }
+
+    // カーソルのある文の値を表示する
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let text = self.documents.get(uri(params)?)?;
+        let at = offset(text, params.get("position")?)?;
+        let (results, _) = self.evaluate(text);
+        let (loc, v) = results
+            .into_iter()
+            .find(|(loc, _)| loc.start() <= at && at <= loc.end())?;
+
+        Some(Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", Json::string("plaintext")),
+                    ("value", Json::string(format!("= {}", v.ok()?))),
+                ]),
+            ),
+            ("range", range(text, &loc)),
+        ]))
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let vars = match uri(params).and_then(|uri| self.documents.get(uri)) {
+            Some(text) => {
+                let (_, engine) = self.evaluate(text);
+                let vars = engine.env().vars();
+                vars.into_iter()
+                    .map(|(name, v)| (name.to_string(), v.to_string()))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        // CompletionItemKind: Function = 3, Variable = 6
+        let solve = (SOLVE, "solve(equation, name[, guess]) for the unknown");
+        let functions = builtins()
+            .iter()
+            .map(|b| (b.name, b.help))
+            .chain([solve])
+            .map(|(name, help)| {
+                Json::object([
+                    ("label", Json::string(name)),
+                    ("kind", Json::Number(3.0)),
+                    ("detail", Json::string(help)),
+                ])
+            });
+        let vars = vars.into_iter().map(|(name, v)| {
+            Json::object([
+                ("label", Json::string(name)),
+                ("kind", Json::Number(6.0)),
+                ("detail", Json::string(v)),
+            ])
+        });
+        Json::Array(functions.chain(vars).collect())
+    }
+
+    // 範囲に掛かる誤りの直し方を quickfix として返す
+    fn code_actions(&self, params: &Json) -> Option<Json> {
+        let uri = uri(params)?;
+        let text = self.documents.get(uri)?;
+        let start = offset(text, params.get("range")?.get("start")?)?;
+        let end = offset(text, params.get("range")?.get("end")?)?;
+
+        let (results, _) = self.evaluate(text);
+        let actions = results
+            .iter()
+            .filter_map(|(loc, r)| r.as_ref().err().map(|e| (loc, e)))
+            .filter(|(loc, e)| {
+                let at = e.loc().unwrap_or(Location::new(loc.end(), loc.end()));
+                at.start() <= end && start <= at.end()
+            })
+            .flat_map(|(loc, e)| suggest(text, loc, e))
+            .map(|fix| {
+                let edit = Json::object([
+                    ("range", range(text, &fix.span)),
+                    ("newText", Json::string(fix.replacement)),
+                ]);
+                Json::object([
+                    ("title", Json::string(fix.title)),
+                    ("kind", Json::string("quickfix")),
+                    (
+                        "edit",
+                        Json::object([("changes", Json::object([(uri, Json::Array(vec![edit]))]))]),
+                    ),
+                ])
+            })
+            .collect();
+        Some(Json::Array(actions))
+    }
+
+    // 文書全体を置き換える編集を一つ返す。構文エラーがあれば整形しない
+    fn formatting(&self, params: &Json) -> Option<Json> {
+        let text = self.documents.get(uri(params)?)?;
+        let formatted = self.formatter.format(text).ok()?;
+        if &formatted == text {
+            return Some(Json::Array(vec![]));
+        }
+
+        Some(Json::Array(vec![Json::object([
+            ("range", range(text, &Location::new(0, text.len()))),
+            ("newText", Json::string(formatted)),
+        ])]))
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                // 全文同期
+                ("textDocumentSync", Json::Number(1.0)),
+                ("hoverProvider", Json::Bool(true)),
+                ("completionProvider", Json::object::<&str>([])),
+                ("documentFormattingProvider", Json::Bool(true)),
+                ("codeActionProvider", Json::Bool(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([("name", Json::string("calc-lsp"))]),
+        ),
+    ])
+}
+
+fn uri(params: &Json) -> Option<&str> {
+    params.get("textDocument")?.get("uri")?.as_str()
+}
+
+fn diagnostics(uri: &str, items: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", Json::string("2.0")),
+        ("method", Json::string("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object([
+                ("uri", Json::string(uri)),
+                ("diagnostics", Json::Array(items)),
+            ]),
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: f64, message: &str) -> Json {
+    Json::object([
+        ("jsonrpc", Json::string("2.0")),
+        ("id", id),
+        (
+            "error",
+            Json::object([
+                ("code", Json::Number(code)),
+                ("message", Json::string(message)),
+            ]),
+        ),
+    ])
+}
+
+// LSP の位置は 0 始まりの行と、行内の UTF-16 単位での文字位置。
+// 字句解析のエラー位置は文字の途中を指すことがあるので文字の頭に寄せる
+fn position(text: &str, offset: usize) -> Json {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Json::object([
+        ("line", Json::Number(before.matches('\n').count() as f64)),
+        ("character", Json::Number(character as f64)),
+    ])
+}
+
+fn range(text: &str, loc: &Location) -> Json {
+    Json::object([
+        ("start", position(text, loc.start())),
+        ("end", position(text, loc.end())),
+    ])
+}
+
+// 行や文字位置が行末を越えていれば行末に寄せる
+fn offset(text: &str, position: &Json) -> Option<usize> {
+    let line = position.get("line")?.as_f64()? as usize;
+    let character = position.get("character")?.as_f64()? as usize;
+
+    let start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if c == '\n' || units >= character {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(server: &mut Server, text: &str) -> Vec<Json> {
+        let msg = Json::object([
+            ("jsonrpc", Json::string("2.0")),
+            ("method", Json::string("textDocument/didOpen")),
+            (
+                "params",
+                Json::object([(
+                    "textDocument",
+                    Json::object([
+                        ("uri", Json::string("file:///a.calc")),
+                        ("text", Json::string(text)),
+                    ]),
+                )]),
+            ),
+        ]);
+        server.handle(&msg)
+    }
+
+    fn request(server: &mut Server, method: &str, params: &str) -> Json {
+        let body = format!(
+            r#"{{"jsonrpc": "2.0", "id": 7, "method": "{}", "params": {}}}"#,
+            method, params
+        );
+        let mut replies = server.handle_message(&body);
+        assert_eq!(replies.len(), 1);
+        replies.pop().unwrap()
+    }
+
+    #[test]
+    fn test_lsp_diagnostics_and_hover() {
+        let mut server = Server::new();
+
+        let replies = open(&mut server, "x = 2\ny = x * 3\n1 +* 2\n");
+        let diags = replies[0]
+            .get("params")
+            .and_then(|p| p.get("diagnostics"))
+            .and_then(Json::as_array)
+            .unwrap();
+        assert_eq!(diags.len(), 1);
+        assert_eq!(
+            diags[0].get("range").unwrap().to_string(),
+            r#"{"start":{"line":2,"character":3},"end":{"line":2,"character":4}}"#
+        );
+
+        let doc = r#"{"textDocument": {"uri": "file:///a.calc"}, "position": {"line": 1, "character": 2}}"#;
+        let hover = request(&mut server, "textDocument/hover", doc);
+        assert_eq!(
+            hover
+                .get("result")
+                .and_then(|r| r.get("contents"))
+                .unwrap()
+                .to_string(),
+            r#"{"kind":"plaintext","value":"= 6"}"#
+        );
+
+        let completion = request(&mut server, "textDocument/completion", doc);
+        let labels: Vec<&str> = completion
+            .get("result")
+            .and_then(Json::as_array)
+            .unwrap()
+            .iter()
+            .filter_map(|c| c.get("label").and_then(Json::as_str))
+            .collect();
+        assert!(labels.contains(&"det") && labels.contains(&"x") && labels.contains(&"y"));
+
+        let range = r#"{"start": {"line": 2, "character": 0}, "end": {"line": 2, "character": 6}}"#;
+        let params = format!(
+            r#"{{"textDocument": {{"uri": "file:///a.calc"}}, "range": {}}}"#,
+            range
+        );
+        let actions = request(&mut server, "textDocument/codeAction", &params);
+        assert_eq!(
+            actions.get("result").unwrap().to_string(),
+            concat!(
+                r#"[{"title":"remove '*'","kind":"quickfix","edit":{"changes":{"file:///a.calc":"#,
+                r#"[{"range":{"start":{"line":2,"character":3},"end":{"line":2,"character":5}},"newText":""}]}}}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_lsp_formatting_and_lifecycle() {
+        let mut server = Server::new();
+        let init = request(&mut server, "initialize", "{}");
+        assert!(init
+            .get("result")
+            .and_then(|r| r.get("capabilities"))
+            .is_some());
+
+        open(&mut server, "1+2*é\n");
+        let doc = r#"{"textDocument": {"uri": "file:///a.calc"}}"#;
+        // 字句解析のエラーがあれば整形しない
+        let edits = request(&mut server, "textDocument/formatting", doc);
+        assert_eq!(edits.get("result"), Some(&Json::Null));
+
+        open(&mut server, "1+2*x\n");
+        let edits = request(&mut server, "textDocument/formatting", doc);
+        assert_eq!(
+            edits.get("result").unwrap().to_string(),
+            r#"[{"range":{"start":{"line":0,"character":0},"end":{"line":1,"character":0}},"newText":"1 + 2 * x\n"}]"#
+        );
+
+        let unknown = request(&mut server, "textDocument/rename", doc);
+        assert!(unknown.get("error").is_some());
+        assert!(server.handle_message("{").len() == 1);
+
+        request(&mut server, "shutdown", "null");
+        server.handle_message(r#"{"jsonrpc": "2.0", "method": "exit"}"#);
+        assert_eq!(server.exit_code(), Some(0));
+    }
+
+    #[test]
+    fn test_lsp_framing() {
+        let mut out = Vec::new();
+        write_message(&mut out, &Json::object([("id", Json::Number(1.0))])).unwrap();
+        assert_eq!(out, b"Content-Length: 8\r\n\r\n{\"id\":1}");
+
+        let mut input = &out[..];
+        assert_eq!(
+            read_message(&mut input).unwrap(),
+            Some("{\"id\":1}".to_string())
+        );
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+}
@@ -1,40 +1,225 @@
-pub mod error;
-pub mod lexer;
-pub mod parser;
-pub mod token;
+use calculator::cli::{
+    args_or_exit, run_batch, split_command, Editor, Flow, Highlighter, History, Input,
+    NumberFormat, OutputFormat, Precision, Repl, SyntaxHighlighter,
+};
+use calculator::{is_incomplete, Engine, Mode};
 
-use crate::parser::Ast;
+use std::io::{stderr, stdin, stdout, IsTerminal, Read, Result};
+use std::process::ExitCode;
 
-use std::io::{stdin, stdout, BufRead, BufReader, Result, Write};
+const HISTORY_SIZE: usize = 1000;
 
-fn main() -> Result<()> {
-    let stdin = stdin();
-    let stdin = stdin.lock();
-    let stdin = BufReader::new(stdin);
-    let mut lines = stdin.lines();
+const USAGE: &str = "\
+usage: calculator [-e EXPR]... [--format text|json] [--mode real|complex|interval]
+                  [--implicit-mul] [--digits N | --fixed N] [--notation plain|sci|eng]
+                  [--radix dec|hex|bin|oct] [--group] [--locale en|de|fr|ch]
+                  [FILE|-]...
 
-    loop {
-        prompt("> ")?;
-        let Some(Ok(line)) = lines.next() else {
-            break;
-        };
+With no EXPR or FILE, starts the REPL when stdin is a terminal and
+otherwise evaluates stdin. Prints one result per statement, or `error`
+with the details on stderr, and exits with status 1 if any statement
+fails. Variables carry over from one EXPR or FILE to the next.";
 
-        let ast = match line.parse::<Ast>() {
-            Ok(ast) => Some(ast),
+enum Source {
+    Expr(String),
+    File(String),
+    Stdin,
+}
+
+struct Args {
+    sources: Vec<Source>,
+    format: OutputFormat,
+    numbers: NumberFormat,
+    engine: Engine,
+}
+
+fn main() -> ExitCode {
+    let args = match args_or_exit("calculator", USAGE, parse_args(std::env::args().skip(1))) {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
+
+    let mut sources = args.sources;
+    if sources.is_empty() {
+        if stdin().is_terminal() {
+            return match run_repl(args.engine, args.numbers) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("calculator: {}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        sources.push(Source::Stdin);
+    }
+
+    let mut engine = args.engine;
+    let mut code = ExitCode::SUCCESS;
+    for source in sources {
+        let (name, input) = match read_source(source) {
+            Ok(read) => read,
             Err(e) => {
-                eprintln!("Error: {e:?}");
-                None
+                eprintln!("calculator: {}", e);
+                code = ExitCode::from(2);
+                continue;
             }
         };
-        println!("{:?}", ast);
+
+        match run_batch(
+            &name,
+            &input,
+            args.format,
+            &args.numbers,
+            &mut engine,
+            &mut stdout(),
+            &mut stderr(),
+        ) {
+            Ok(true) => {}
+            Ok(false) => code = ExitCode::FAILURE,
+            Err(e) => {
+                eprintln!("calculator: {}", e);
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    code
+}
+
+// --help なら None
+fn parse_args(mut args: impl Iterator<Item = String>) -> std::result::Result<Option<Args>, String> {
+    let mut ret = Args {
+        sources: Vec::new(),
+        format: OutputFormat::Text,
+        numbers: NumberFormat::default(),
+        engine: Engine::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--eval" => {
+                let expr = args.next().ok_or("-e requires an expression")?;
+                ret.sources.push(Source::Expr(expr));
+            }
+            "--format" => {
+                let format = args.next().ok_or("--format requires text or json")?;
+                ret.format = format.parse()?;
+            }
+            "--mode" => {
+                let mode = args
+                    .next()
+                    .ok_or("--mode requires real, complex or interval")?;
+                ret.engine.set_mode(mode.parse::<Mode>()?);
+            }
+            "--implicit-mul" => ret.engine.parser_mut().set_implicit_mul(true),
+            "--digits" | "--fixed" => {
+                let n = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or(format!("{} requires a number of digits", arg))?;
+                ret.numbers.precision = match arg.as_str() {
+                    "--digits" => Precision::significant(n),
+                    _ => Precision::fixed(n),
+                }
+                .map_err(|e| format!("{}: {}", arg, e))?;
+            }
+            "--notation" => {
+                let notation = args.next().ok_or("--notation requires plain, sci or eng")?;
+                ret.numbers.notation = notation.parse()?;
+            }
+            "--radix" => {
+                let radix = args.next().ok_or("--radix requires dec, hex, bin or oct")?;
+                ret.numbers.radix = radix.parse()?;
+            }
+            "--group" => ret.numbers.grouping = true,
+            "--locale" => {
+                let locale = args.next().ok_or("--locale requires en, de, fr or ch")?;
+                ret.numbers.locale = locale.parse()?;
+            }
+            "-h" | "--help" => return Ok(None),
+            "-" => ret.sources.push(Source::Stdin),
+            s if s.starts_with('-') => return Err(format!("unknown option: {}", s)),
+            _ => ret.sources.push(Source::File(arg)),
+        }
+    }
+
+    Ok(Some(ret))
+}
+
+fn read_source(source: Source) -> std::result::Result<(String, String), String> {
+    match source {
+        Source::Expr(expr) => Ok(("<expr>".to_string(), expr)),
+        Source::File(path) => std::fs::read_to_string(&path)
+            .map(|input| (path.clone(), input))
+            .map_err(|e| format!("{}: {}", path, e)),
+        Source::Stdin => {
+            let mut input = String::new();
+            stdin()
+                .read_to_string(&mut input)
+                .map(|_| ("<stdin>".to_string(), input))
+                .map_err(|e| format!("<stdin>: {}", e))
+        }
+    }
+}
+
+fn run_repl(engine: Engine, numbers: NumberFormat) -> Result<()> {
+    let history = match History::default_path() {
+        Some(path) => History::load(path, HISTORY_SIZE).unwrap_or_else(|e| {
+            eprintln!("Warning: failed to load history: {e}");
+            History::new(HISTORY_SIZE)
+        }),
+        None => History::new(HISTORY_SIZE),
+    };
+    let mut editor = Editor::new(history);
+    let mut repl = Repl::with_engine(engine);
+    *repl.number_format_mut() = numbers;
+
+    // NO_COLOR が設定されていれば色を付けない
+    let color = std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+    while let Some(line) = read_statement(&mut editor, &repl, color)? {
+        if repl.handle(&line, &mut stdout())? == Flow::Quit {
+            break;
+        }
     }
 
     Ok(())
 }
 
-fn prompt(s: &str) -> Result<()> {
-    let stdout = stdout();
-    let mut stdout = stdout.lock();
-    stdout.write_all(s.as_bytes())?;
-    stdout.flush()
+// 括弧が閉じられていなければ続きの行を読んで一つの文にする
+fn read_statement(editor: &mut Editor, repl: &Repl, color: bool) -> Result<Option<String>> {
+    let mut statement = String::new();
+
+    loop {
+        let prompt = if statement.is_empty() { "> " } else { "... " };
+        let highlighter = SyntaxHighlighter {
+            parser: repl.engine().parser(),
+            errors: statement.is_empty(),
+        };
+        let highlighter = color.then_some(&highlighter as &dyn Highlighter);
+        match editor.read_line_with(prompt, highlighter, Some(repl))? {
+            Input::Line(line) => {
+                if !statement.is_empty() {
+                    statement.push('\n');
+                }
+                statement.push_str(&line);
+            }
+            Input::Interrupt => {
+                statement.clear();
+                continue;
+            }
+            Input::Eof => return Ok(None),
+        }
+
+        if !needs_continuation(&statement) {
+            return Ok(Some(statement));
+        }
+    }
+}
+
+// コマンドは引数の式だけを見る
+fn needs_continuation(input: &str) -> bool {
+    match input.trim_start().strip_prefix(':') {
+        Some(command) => is_incomplete(split_command(command).1),
+        None => is_incomplete(input),
+    }
 }
@@ -0,0 +1,603 @@
+use std::str::FromStr;
+
+use crate::complex::ComplexFormat;
+use crate::value::Value;
+
+// 桁数。Shortest は元の値に戻せる最短の表記
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Precision {
+    #[default]
+    Shortest,
+    // 有効数字。末尾の 0 は省く
+    Significant(usize),
+    // 小数点以下(指数表記では仮数部)の桁数
+    Fixed(usize),
+}
+
+// f64 は有効数字 767 桁、小数点以下 1074 桁あれば正確に書けるので、それより多い桁数は受け付けない
+pub const MAX_SIGNIFICANT: usize = 767;
+pub const MAX_DECIMALS: usize = 1074;
+
+impl Precision {
+    pub fn significant(n: usize) -> Result<Self, String> {
+        match n {
+            0..=MAX_SIGNIFICANT => Ok(Precision::Significant(n)),
+            _ => Err(format!("at most {} significant digits", MAX_SIGNIFICANT)),
+        }
+    }
+
+    pub fn fixed(n: usize) -> Result<Self, String> {
+        match n {
+            0..=MAX_DECIMALS => Ok(Precision::Fixed(n)),
+            _ => Err(format!("at most {} decimals", MAX_DECIMALS)),
+        }
+    }
+
+    // フィールドを直接書き換えた場合でも format! の桁数が溢れないように上限で抑える
+    fn clamped(self) -> Self {
+        match self {
+            Precision::Shortest => self,
+            Precision::Significant(n) => Precision::Significant(n.min(MAX_SIGNIFICANT)),
+            Precision::Fixed(n) => Precision::Fixed(n.min(MAX_DECIMALS)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Notation {
+    #[default]
+    Plain,
+    Scientific,
+    // 指数を 3 の倍数にそろえる
+    Engineering,
+}
+
+impl FromStr for Notation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Notation::Plain),
+            "sci" | "scientific" => Ok(Notation::Scientific),
+            "eng" | "engineering" => Ok(Notation::Engineering),
+            _ => Err(format!("unknown notation: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Notation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Notation::Plain => write!(f, "plain"),
+            Notation::Scientific => write!(f, "sci"),
+            Notation::Engineering => write!(f, "eng"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Radix {
+    #[default]
+    Dec,
+    Hex,
+    Bin,
+    Oct,
+}
+
+impl Radix {
+    fn base(self) -> u64 {
+        match self {
+            Radix::Dec => 10,
+            Radix::Hex => 16,
+            Radix::Bin => 2,
+            Radix::Oct => 8,
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Radix::Dec => "",
+            Radix::Hex => "0x",
+            Radix::Bin => "0b",
+            Radix::Oct => "0o",
+        }
+    }
+}
+
+impl FromStr for Radix {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dec" | "10" => Ok(Radix::Dec),
+            "hex" | "16" => Ok(Radix::Hex),
+            "bin" | "2" => Ok(Radix::Bin),
+            "oct" | "8" => Ok(Radix::Oct),
+            _ => Err(format!("unknown radix: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Radix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Radix::Dec => write!(f, "dec"),
+            Radix::Hex => write!(f, "hex"),
+            Radix::Bin => write!(f, "bin"),
+            Radix::Oct => write!(f, "oct"),
+        }
+    }
+}
+
+// 桁区切りと小数点の文字
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Locale {
+    pub name: &'static str,
+    pub group: char,
+    pub decimal: char,
+}
+
+static LOCALES: &[Locale] = &[
+    Locale {
+        name: "en",
+        group: ',',
+        decimal: '.',
+    },
+    Locale {
+        name: "de",
+        group: '.',
+        decimal: ',',
+    },
+    Locale {
+        name: "fr",
+        group: '\u{202f}',
+        decimal: ',',
+    },
+    Locale {
+        name: "ch",
+        group: '\'',
+        decimal: '.',
+    },
+];
+
+impl Default for Locale {
+    fn default() -> Self {
+        LOCALES[0]
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LOCALES
+            .iter()
+            .find(|l| l.name == s)
+            .copied()
+            .ok_or_else(|| format!("unknown locale: {}", s))
+    }
+}
+
+// 表示する桁で切れない値の丸め方。区間の端は外向きに丸めて元の区間を含むようにする
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Rounding {
+    #[default]
+    Nearest,
+    // -∞ 向き
+    Down,
+    // +∞ 向き
+    Up,
+}
+
+// 結果の表示方法。既定値では f64 の Display と同じ表記になる。
+// JSON 出力には使わない
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct NumberFormat {
+    pub precision: Precision,
+    pub notation: Notation,
+    pub radix: Radix,
+    // 整数部を 3 桁ごとに区切る
+    pub grouping: bool,
+    pub locale: Locale,
+    pub complex: ComplexFormat,
+}
+
+impl NumberFormat {
+    pub fn value(&self, v: &Value) -> String {
+        v.format_with(self.complex, self.separator(), &|x, round| {
+            self.number_rounded(x, round)
+        })
+    }
+
+    // 配列の要素や区間の端の区切り。小数点が `,` なら紛れないように `;` にする
+    fn separator(&self) -> char {
+        if self.locale.decimal == ',' {
+            ';'
+        } else {
+            ','
+        }
+    }
+
+    pub fn number(&self, n: f64) -> String {
+        self.number_rounded(n, Rounding::Nearest)
+    }
+
+    pub fn number_rounded(&self, n: f64, round: Rounding) -> String {
+        if !n.is_finite() {
+            return n.to_string();
+        }
+
+        // ここから先は絶対値を扱うので、Up は 0 から離れる向き、Down は 0 に近づく向きになる
+        let (sign, round) = match (n.is_sign_negative(), round) {
+            (true, Rounding::Down) => ("-", Rounding::Up),
+            (true, Rounding::Up) => ("-", Rounding::Down),
+            (true, _) => ("-", round),
+            (false, _) => ("", round),
+        };
+        let n = n.abs();
+        let f = NumberFormat {
+            precision: self.precision.clamped(),
+            ..*self
+        };
+        if self.radix != Radix::Dec {
+            return format!(
+                "{}{}{}",
+                sign,
+                self.radix.prefix(),
+                f.radix_digits(n, round)
+            );
+        }
+
+        let body = match self.notation {
+            Notation::Plain => f.plain(n, round),
+            Notation::Scientific => f.exponential(n, 1, round),
+            Notation::Engineering => f.exponential(n, 3, round),
+        };
+        format!("{}{}", sign, body)
+    }
+
+    // n は非負
+    fn plain(&self, n: f64, round: Rounding) -> String {
+        let s = match self.precision {
+            Precision::Shortest => n.to_string(),
+            Precision::Fixed(d) if round == Rounding::Nearest => format!("{:.*}", d, n),
+            Precision::Fixed(d) => {
+                // 小数点以下 1074 桁あれば f64 は正確に書ける
+                let exact = format!("{:.1074}", n);
+                let (int, frac) = exact.split_once('.').unwrap();
+                let digits = format!("{}{}", int, frac);
+                let (digits, carry) = round_digits(&digits, int.len() + d, round);
+                place_point(&digits, (int.len() + carry as usize) as i32)
+            }
+            Precision::Significant(d) => {
+                let (digits, exp) = mantissa(n, d.max(1) - 1, round);
+                trim_zeros(&place_point(&digits, exp + 1))
+            }
+        };
+
+        let (int, frac) = s.split_once('.').unwrap_or((&s, ""));
+        self.join(int, frac)
+    }
+
+    // 指数を step の倍数にする。step が 1 なら通常の指数表記
+    fn exponential(&self, n: f64, step: i32, round: Rounding) -> String {
+        let (digits, exp) = match self.precision {
+            Precision::Shortest => mantissa(n, usize::MAX, round),
+            Precision::Significant(d) => mantissa(n, d.max(1) - 1, round),
+            Precision::Fixed(d) => {
+                // 小数点の前に来る桁数は指数で決まるので先に求める
+                let (_, exp) = mantissa(n, d, round);
+                mantissa(n, d + exp.rem_euclid(step) as usize, round)
+            }
+        };
+
+        let shifted = exp.rem_euclid(step);
+        let mut m = place_point(&digits, shifted + 1);
+        if !matches!(self.precision, Precision::Fixed(_)) {
+            m = trim_zeros(&m);
+        }
+
+        let (int, frac) = m.split_once('.').unwrap_or((&m, ""));
+        format!("{}e{}", self.join(int, frac), exp - shifted)
+    }
+
+    fn join(&self, int: &str, frac: &str) -> String {
+        let mut s = if self.grouping {
+            group(int, self.locale.group)
+        } else {
+            int.to_string()
+        };
+        if !frac.is_empty() {
+            s.push(self.locale.decimal);
+            s.push_str(frac);
+        }
+        s
+    }
+
+    // 小数部は割り切れるまで、ただし f64 の精度を超えない桁数で打ち切り、
+    // 残りは 10 進と同じく最後の桁に丸める
+    fn radix_digits(&self, n: f64, round: Rounding) -> String {
+        let base = self.radix.base() as u32;
+        let max = match self.precision {
+            Precision::Fixed(d) | Precision::Significant(d) => d,
+            Precision::Shortest => (53.0 / (base as f64).log2()).ceil() as usize,
+        };
+
+        let mut int = n.trunc();
+        let mut frac = n.fract();
+        let mut digits = Vec::new();
+        while frac > 0.0 && digits.len() < max {
+            frac *= base as f64;
+            digits.push(frac.trunc() as u32);
+            frac = frac.fract();
+        }
+        // 基数が 2 の冪なので frac は最後の桁より下の残りを誤差なく表す
+        let odd = digits.last().map_or(int % 2.0 == 1.0, |d| d % 2 == 1);
+        let up = match round {
+            Rounding::Nearest => frac > 0.5 || (frac == 0.5 && odd),
+            Rounding::Up => frac > 0.0,
+            Rounding::Down => false,
+        };
+        if up {
+            match digits.iter().rposition(|&d| d + 1 < base) {
+                Some(i) => {
+                    digits[i] += 1;
+                    digits[i + 1..].fill(0);
+                }
+                None => {
+                    int += 1.0;
+                    digits.fill(0);
+                }
+            }
+        }
+        match self.precision {
+            Precision::Fixed(d) => digits.resize(d, 0),
+            _ => {
+                while digits.last() == Some(&0) {
+                    digits.pop();
+                }
+            }
+        }
+
+        let mut s = self.radix_int(int);
+        if !digits.is_empty() {
+            s.push('.');
+            s.extend(
+                digits
+                    .iter()
+                    .map(|&d| std::char::from_digit(d, base).unwrap()),
+            );
+        }
+        s
+    }
+
+    // n は非負の整数。u64 に収まらなくても、基数が 2 の冪なので仮数部のビット列から正確に書ける
+    fn radix_int(&self, n: f64) -> String {
+        if n < u64::MAX as f64 {
+            let int = n as u64;
+            return match self.radix {
+                Radix::Hex => format!("{:x}", int),
+                Radix::Bin => format!("{:b}", int),
+                Radix::Oct => format!("{:o}", int),
+                Radix::Dec => int.to_string(),
+            };
+        }
+
+        let bits = n.to_bits();
+        let exp = (bits >> 52) as usize - 1075;
+        let m = bits & ((1 << 52) - 1) | (1 << 52);
+        let width = self.radix.base().trailing_zeros() as usize;
+        let len = 53 + exp;
+        let pad = (width - len % width) % width;
+        let bin = format!("{}{:b}{}", "0".repeat(pad), m, "0".repeat(exp));
+        bin.as_bytes()
+            .chunks(width)
+            .map(|c| {
+                let d = c.iter().fold(0, |d, b| d * 2 + (b - b'0') as u32);
+                std::char::from_digit(d, 1 << width).unwrap()
+            })
+            .collect()
+    }
+}
+
+// n を d.ddd × 10^exp の形にし、小数点を除いた仮数部の数字と exp を返す。
+// decimals が usize::MAX なら最短の表記。最短の表記は元の値に戻せるので丸め方によらない
+fn mantissa(n: f64, decimals: usize, round: Rounding) -> (String, i32) {
+    if decimals == usize::MAX || round == Rounding::Nearest {
+        let s = if decimals == usize::MAX {
+            format!("{:e}", n)
+        } else {
+            format!("{:.*e}", decimals, n)
+        };
+        let (m, exp) = s.split_once('e').unwrap();
+        return (m.replace('.', ""), exp.parse().unwrap());
+    }
+
+    // f64 の 10 進の有効数字は 767 桁に収まる
+    let s = format!("{:.767e}", n);
+    let (m, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let (digits, carry) = round_digits(&m.replace('.', ""), decimals + 1, round);
+    if carry {
+        (digits[..decimals + 1].to_string(), exp + 1)
+    } else {
+        (digits, exp)
+    }
+}
+
+// 正確な値を表す数字の並びを先頭の keep 桁に切り捨てるか切り上げる。
+// 足りない桁は 0 で補い、繰り上がりで桁が増えたら真を返す
+fn round_digits(digits: &str, keep: usize, round: Rounding) -> (String, bool) {
+    let mut kept: Vec<u8> = digits
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(keep)
+        .collect();
+    let rest = digits.get(keep..).unwrap_or("");
+    if round != Rounding::Up || rest.bytes().all(|b| b == b'0') {
+        return (String::from_utf8(kept).unwrap(), false);
+    }
+    let carry = match kept.iter().rposition(|&b| b != b'9') {
+        Some(i) => {
+            kept[i] += 1;
+            kept[i + 1..].fill(b'0');
+            false
+        }
+        None => {
+            kept.fill(b'0');
+            kept.insert(0, b'1');
+            true
+        }
+    };
+    (String::from_utf8(kept).unwrap(), carry)
+}
+
+// 数字の並びの先頭から point 桁目の後に小数点を置く。足りない桁は 0 で補う
+fn place_point(digits: &str, point: i32) -> String {
+    if point <= 0 {
+        return format!("0.{}{}", "0".repeat(-point as usize), digits);
+    }
+    let point = point as usize;
+    if digits.len() <= point {
+        return format!("{}{}", digits, "0".repeat(point - digits.len()));
+    }
+    format!("{}.{}", &digits[..point], &digits[point..])
+}
+
+fn trim_zeros(s: &str) -> String {
+    if !s.contains('.') {
+        return s.to_string();
+    }
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn group(int: &str, sep: char) -> String {
+    let mut s = String::new();
+    for (i, c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i).is_multiple_of(3) {
+            s.push(sep);
+        }
+        s.push(c);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interval::Interval;
+    use crate::value::Array;
+
+    #[test]
+    fn test_number_precision_and_notation() {
+        let mut f = NumberFormat::default();
+        assert!(Precision::fixed(70000).is_err());
+        assert_eq!(Precision::significant(3), Ok(Precision::Significant(3)));
+        // 上限を超える桁数は上限まで
+        f.precision = Precision::Fixed(70000);
+        assert_eq!(f.number(0.5).len(), 2 + MAX_DECIMALS);
+        f.precision = Precision::Significant(70000);
+        f.notation = Notation::Scientific;
+        assert_eq!(f.number(0.5), "5e-1");
+        f = NumberFormat::default();
+
+        assert_eq!(f.number(1234.5), "1234.5");
+        assert_eq!(f.number(0.1 + 0.2), (0.1 + 0.2).to_string());
+
+        f.precision = Precision::Significant(3);
+        assert_eq!(f.number(1234.5), "1230");
+        assert_eq!(f.number(-0.00012345), "-0.000123");
+        assert_eq!(f.number(2.0), "2");
+
+        f.precision = Precision::Fixed(2);
+        assert_eq!(f.number(2.0 / 3.0), "0.67");
+
+        f.notation = Notation::Scientific;
+        assert_eq!(f.number(123456.0), "1.23e5");
+        f.notation = Notation::Engineering;
+        assert_eq!(f.number(123456.0), "123.46e3");
+        assert_eq!(f.number(0.0012), "1.20e-3");
+
+        f.precision = Precision::Shortest;
+        assert_eq!(f.number(4.7e-5), "47e-6");
+        f.notation = Notation::Scientific;
+        assert_eq!(f.number(4.7e-5), "4.7e-5");
+    }
+
+    #[test]
+    fn test_number_grouping_and_radix() {
+        let mut f = NumberFormat {
+            grouping: true,
+            ..NumberFormat::default()
+        };
+        assert_eq!(f.number(-1234567.25), "-1,234,567.25");
+        assert_eq!(f.number(999.0), "999");
+        f.locale = "de".parse().unwrap();
+        assert_eq!(f.number(1234567.25), "1.234.567,25");
+
+        let mut f = NumberFormat {
+            radix: Radix::Hex,
+            ..NumberFormat::default()
+        };
+        assert_eq!(f.number(255.0), "0xff");
+        assert_eq!(f.number(-10.5), "-0xa.8");
+        f.radix = Radix::Bin;
+        assert_eq!(f.number(5.0), "0b101");
+        f.radix = Radix::Oct;
+        assert_eq!(f.number(64.0), "0o100");
+
+        // u64 に収まらない整数も 10 進に戻さない
+        f.radix = Radix::Hex;
+        assert_eq!(f.number(2f64.powi(70)), format!("0x4{}", "0".repeat(17)));
+        assert_eq!(f.number(-1e30), "-0xc9f2c9cd04675000000000000");
+        f.radix = Radix::Oct;
+        assert_eq!(f.number(2f64.powi(64)), "0o2000000000000000000000");
+
+        // 桁数を指定すると最後の桁を丸める
+        f.radix = Radix::Hex;
+        f.precision = Precision::Fixed(2);
+        assert_eq!(f.number(0.1), "0x0.1a");
+        assert_eq!(f.number(15.999), "0x10.00");
+        f.precision = Precision::Fixed(0);
+        assert_eq!(f.number(2.5), "0x2");
+        assert_eq!(f.number(3.5), "0x4");
+    }
+
+    #[test]
+    fn test_value_separator() {
+        let mut f = NumberFormat::default();
+        let v = Value::Array(Array::vector(vec![1.5, 2.5]));
+        assert_eq!(f.value(&v), "[1.5, 2.5]");
+        f.locale = "de".parse().unwrap();
+        assert_eq!(f.value(&v), "[1,5; 2,5]");
+        assert_eq!(
+            f.value(&Value::Interval(Interval::new(1.5, 2.0))),
+            "[1,5; 2]"
+        );
+    }
+
+    #[test]
+    fn test_interval_rounding() {
+        let mut f = NumberFormat {
+            precision: Precision::Fixed(2),
+            ..NumberFormat::default()
+        };
+        let x = Value::Interval(Interval::new(2.0 / 3.0, 1.0));
+        assert_eq!(f.value(&x), "[0.66, 1.00]");
+        let y = Value::Interval(Interval::new(-2.0 / 3.0, 0.999));
+        assert_eq!(f.value(&y), "[-0.67, 1.00]");
+        assert_eq!(f.number(2.0 / 3.0), "0.67");
+
+        f.precision = Precision::Significant(2);
+        f.notation = Notation::Scientific;
+        assert_eq!(
+            f.value(&Value::Interval(Interval::new(0.0995, 9.91))),
+            "[9.9e-2, 1e1]"
+        );
+
+        f.notation = Notation::Plain;
+        f.radix = Radix::Hex;
+        f.precision = Precision::Fixed(2);
+        assert_eq!(
+            f.value(&Value::Interval(Interval::point(0.1))),
+            "[0x0.19, 0x0.1a]"
+        );
+    }
+}
@@ -1,17 +1,32 @@
 use std::iter::Peekable;
 use std::str::FromStr;
 
+use crate::cst::{Cst, CstKind, CstNode};
 use crate::error::Error;
 use crate::lexer::lexer;
+use crate::limits::Limits;
 use crate::token::*;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UniOpKind {
     Plus,
     Minus,
+    Factorial,
+    Percent,
 }
 
-type UniOp = Annotation<UniOpKind>;
+impl std::fmt::Display for UniOpKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UniOpKind::Plus => write!(f, "+"),
+            UniOpKind::Minus => write!(f, "-"),
+            UniOpKind::Factorial => write!(f, "!"),
+            UniOpKind::Percent => write!(f, "%"),
+        }
+    }
+}
+
+pub type UniOp = Annotation<UniOpKind>;
 
 impl UniOp {
     pub fn plus(loc: Location) -> Self {
@@ -21,6 +36,14 @@ impl UniOp {
     pub fn minus(loc: Location) -> Self {
         Self::new(UniOpKind::Minus, loc)
     }
+
+    pub fn factorial(loc: Location) -> Self {
+        Self::new(UniOpKind::Factorial, loc)
+    }
+
+    pub fn percent(loc: Location) -> Self {
+        Self::new(UniOpKind::Percent, loc)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -29,9 +52,24 @@ pub enum BinOpKind {
     Sub,
     Mul,
     Div,
+    MatMul,
+    Pow,
 }
 
-type BinOp = Annotation<BinOpKind>;
+impl std::fmt::Display for BinOpKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BinOpKind::Add => write!(f, "+"),
+            BinOpKind::Sub => write!(f, "-"),
+            BinOpKind::Mul => write!(f, "*"),
+            BinOpKind::Div => write!(f, "/"),
+            BinOpKind::MatMul => write!(f, "@"),
+            BinOpKind::Pow => write!(f, "^"),
+        }
+    }
+}
+
+pub type BinOp = Annotation<BinOpKind>;
 
 impl BinOp {
     pub fn add(loc: Location) -> Self {
@@ -49,22 +87,70 @@ impl BinOp {
     pub fn div(loc: Location) -> Self {
         Self::new(BinOpKind::Div, loc)
     }
+
+    pub fn matmul(loc: Location) -> Self {
+        Self::new(BinOpKind::MatMul, loc)
+    }
+
+    pub fn pow(loc: Location) -> Self {
+        Self::new(BinOpKind::Pow, loc)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AstKind {
-    Num(u64),
+    Num(f64),
+    Imag(f64),
+    Var(String),
+    History(usize),
+    Assign { name: String, e: Box<Ast> },
+    // 両辺が等しくなる未知数の値を表す
+    Equation { l: Box<Ast>, r: Box<Ast> },
     UniOp { op: UniOp, e: Box<Ast> },
     BinOp { op: BinOp, l: Box<Ast>, r: Box<Ast> },
+    Vector(Vec<Ast>),
+    Call { name: String, args: Vec<Ast> },
 }
 
 pub type Ast = Annotation<AstKind>;
 
 impl Ast {
-    pub fn num(n: u64, loc: Location) -> Self {
+    pub fn num(n: f64, loc: Location) -> Self {
         Self::new(AstKind::Num(n), loc)
     }
 
+    pub fn imag(n: f64, loc: Location) -> Self {
+        Self::new(AstKind::Imag(n), loc)
+    }
+
+    pub fn var(name: &str, loc: Location) -> Self {
+        Self::new(AstKind::Var(name.to_string()), loc)
+    }
+
+    pub fn history(n: usize, loc: Location) -> Self {
+        Self::new(AstKind::History(n), loc)
+    }
+
+    pub fn assign(name: &str, e: Ast, loc: Location) -> Self {
+        Self::new(
+            AstKind::Assign {
+                name: name.to_string(),
+                e: Box::new(e),
+            },
+            loc,
+        )
+    }
+
+    pub fn equation(l: Ast, r: Ast, loc: Location) -> Self {
+        Self::new(
+            AstKind::Equation {
+                l: Box::new(l),
+                r: Box::new(r),
+            },
+            loc,
+        )
+    }
+
     pub fn uniop(op: UniOp, e: Ast, loc: Location) -> Self {
         Self::new(AstKind::UniOp { op, e: Box::new(e) }, loc)
     }
@@ -79,6 +165,59 @@ impl Ast {
             loc,
         )
     }
+
+    pub fn vector(items: Vec<Ast>, loc: Location) -> Self {
+        Self::new(AstKind::Vector(items), loc)
+    }
+
+    pub fn call(name: &str, args: Vec<Ast>, loc: Location) -> Self {
+        Self::new(
+            AstKind::Call {
+                name: name.to_string(),
+                args,
+            },
+            loc,
+        )
+    }
+}
+
+impl Ast {
+    // 罫線で枝を描いた木の表示
+    pub fn to_tree(&self) -> String {
+        let mut ret = String::new();
+        self.write_tree(&mut ret, "", "");
+        ret
+    }
+
+    fn write_tree(&self, out: &mut String, head: &str, indent: &str) {
+        let (label, children): (String, Vec<&Ast>) = match &self.value {
+            AstKind::Num(n) => (format!("Num({})", n), vec![]),
+            AstKind::Imag(n) => (format!("Imag({})", n), vec![]),
+            AstKind::Var(name) => (format!("Var({})", name), vec![]),
+            AstKind::History(n) => (format!("History({})", n), vec![]),
+            AstKind::Assign { name, e } => (format!("Assign({})", name), vec![e]),
+            AstKind::Equation { l, r } => ("Equation".to_string(), vec![l, r]),
+            AstKind::UniOp { op, e } => (format!("UniOp({})", op.value), vec![e]),
+            AstKind::BinOp { op, l, r } => (format!("BinOp({})", op.value), vec![l, r]),
+            AstKind::Vector(items) => ("Vector".to_string(), items.iter().collect()),
+            AstKind::Call { name, args } => (format!("Call({})", name), args.iter().collect()),
+        };
+        out.push_str(&format!("{}{}{} {}\n", indent, head, label, self.loc()));
+
+        let indent = match head {
+            "" => indent.to_string(),
+            "├── " => format!("{}│   ", indent),
+            _ => format!("{}    ", indent),
+        };
+        for (i, child) in children.iter().enumerate() {
+            let head = if i + 1 == children.len() {
+                "└── "
+            } else {
+                "├── "
+            };
+            child.write_tree(out, head, &indent);
+        }
+    }
 }
 
 impl FromStr for Ast {
@@ -90,125 +229,853 @@ impl FromStr for Ast {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+// 構文エラーの位置で受け付けられたトークンの種類
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    // 虚数も含む
+    Number,
+    Name,
+    History,
+    Token(TokenKind),
+    End,
+}
+
+impl std::fmt::Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expected::Number => write!(f, "number"),
+            Expected::Name => write!(f, "name"),
+            Expected::History => write!(f, "history reference"),
+            Expected::Token(t) => write!(f, "'{}'", t),
+            Expected::End => write!(f, "end of input"),
+        }
+    }
+}
+
+// `a, b or c` の形に並べる
+pub fn expected_list(expected: &[Expected]) -> String {
+    let items: Vec<String> = expected.iter().map(|e| e.to_string()).collect();
+    match items.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        Some((last, _)) => last.clone(),
+        None => String::new(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ParserError {
-    UnexpectedToken(Token),
-    NotExpression(Token),
-    NotOperator(Token),
+    UnexpectedToken {
+        found: Token,
+        expected: Vec<Expected>,
+    },
     UnclosedOpenParen(Token),
-    RedundantExpression(Token),
-    Eof,
+    TooDeep(Token),
+    TooManyNodes(Token),
+    // loc は入力の末尾の空の範囲
+    Eof {
+        loc: Location,
+        expected: Vec<Expected>,
+    },
+}
+
+impl ParserError {
+    pub fn expected(&self) -> &[Expected] {
+        match self {
+            ParserError::UnexpectedToken { expected, .. } | ParserError::Eof { expected, .. } => {
+                expected
+            }
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixOp {
+    pub token: TokenKind,
+    pub bp: u8,
+    pub op: UniOpKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfixOp {
+    pub token: TokenKind,
+    pub bp: u8,
+    pub assoc: Assoc,
+    pub op: BinOpKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostfixOp {
+    pub token: TokenKind,
+    pub bp: u8,
+    pub op: UniOpKind,
+}
+
+impl InfixOp {
+    // 左右の結合力。左結合なら右側を一段強くして同じ優先度の演算子を右に取り込まない。
+    // 優先度は u8 の全域を使えるので、倍にしても溢れないように u16 で計算する
+    fn binding_power(&self) -> (u16, u16) {
+        let bp = self.bp as u16 * 2;
+        match self.assoc {
+            Assoc::Left => (bp, bp + 1),
+            Assoc::Right => (bp + 1, bp),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OperatorTable {
+    prefix: Vec<PrefixOp>,
+    infix: Vec<InfixOp>,
+    postfix: Vec<PostfixOp>,
+    implicit: Option<InfixOp>,
+}
+
+impl OperatorTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 同じトークンが既に登録されていれば置き換える
+    pub fn prefix(&mut self, token: TokenKind, bp: u8, op: UniOpKind) -> &mut Self {
+        self.prefix.retain(|p| p.token != token);
+        self.prefix.push(PrefixOp { token, bp, op });
+        self
+    }
+
+    pub fn infix(&mut self, token: TokenKind, bp: u8, assoc: Assoc, op: BinOpKind) -> &mut Self {
+        self.infix.retain(|p| p.token != token);
+        self.infix.push(InfixOp {
+            token,
+            bp,
+            assoc,
+            op,
+        });
+        self
+    }
+
+    pub fn postfix(&mut self, token: TokenKind, bp: u8, op: UniOpKind) -> &mut Self {
+        self.postfix.retain(|p| p.token != token);
+        self.postfix.push(PostfixOp { token, bp, op });
+        self
+    }
+
+    // 暗黙の乗算(並置)。有効にすると登録済みの `*` と同じ優先度・結合性で掛け算になる。
+    // 右側が名前か `(` で始まるときだけ並置とみなすので `2 3` はエラーのまま。
+    // 優先度は `*` と同じなので `1/2x` は `(1/2)*x`、`-2x` は `(-2)*x` になる。
+    // `x(1)` は関数呼び出しであり `x*(1)` ではない
+    pub fn implicit_mul(&mut self, on: bool) -> &mut Self {
+        self.implicit = match on {
+            true => self.find_infix_op(&BinOpKind::Mul).cloned(),
+            false => None,
+        };
+        self
+    }
+
+    pub(crate) fn find_implicit(&self) -> Option<&InfixOp> {
+        self.implicit.as_ref()
+    }
+
+    pub(crate) fn find_prefix(&self, token: &TokenKind) -> Option<&PrefixOp> {
+        self.prefix.iter().find(|p| &p.token == token)
+    }
+
+    pub(crate) fn find_infix(&self, token: &TokenKind) -> Option<&InfixOp> {
+        self.infix.iter().find(|p| &p.token == token)
+    }
+
+    pub(crate) fn find_infix_op(&self, op: &BinOpKind) -> Option<&InfixOp> {
+        self.infix.iter().find(|p| &p.op == op)
+    }
+
+    pub(crate) fn find_postfix(&self, token: &TokenKind) -> Option<&PostfixOp> {
+        self.postfix.iter().find(|p| &p.token == token)
+    }
+
+    pub(crate) fn find_prefix_op(&self, op: &UniOpKind) -> Option<&PrefixOp> {
+        self.prefix.iter().find(|p| &p.op == op)
+    }
+
+    pub(crate) fn find_postfix_op(&self, op: &UniOpKind) -> Option<&PostfixOp> {
+        self.postfix.iter().find(|p| &p.op == op)
+    }
+
+    // 標準の文法: 後置 !% > 二項 ^ (右結合) > 前置 +- > 二項 */@ > 二項 +-。
+    // `-2^2` は `-(2^2)`、`2^3!` は `2^(3!)` になる
+    pub fn standard() -> Self {
+        let mut table = Self::new();
+        table
+            .infix(TokenKind::Plus, 1, Assoc::Left, BinOpKind::Add)
+            .infix(TokenKind::Minus, 1, Assoc::Left, BinOpKind::Sub)
+            .infix(TokenKind::Asterisk, 2, Assoc::Left, BinOpKind::Mul)
+            .infix(TokenKind::Slash, 2, Assoc::Left, BinOpKind::Div)
+            .infix(TokenKind::At, 2, Assoc::Left, BinOpKind::MatMul)
+            .prefix(TokenKind::Plus, 3, UniOpKind::Plus)
+            .prefix(TokenKind::Minus, 3, UniOpKind::Minus)
+            .infix(TokenKind::Caret, 4, Assoc::Right, BinOpKind::Pow)
+            .postfix(TokenKind::Bang, 5, UniOpKind::Factorial)
+            .postfix(TokenKind::Percent, 5, UniOpKind::Percent);
+        table
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parser {
+    table: OperatorTable,
+    limits: Limits,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new(OperatorTable::standard())
+    }
+}
+
+impl Parser {
+    pub fn new(table: OperatorTable) -> Self {
+        Self {
+            table,
+            limits: Limits::default(),
+        }
+    }
+
+    pub fn table(&self) -> &OperatorTable {
+        &self.table
+    }
+
+    pub fn table_mut(&mut self) -> &mut OperatorTable {
+        &mut self.table
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn implicit_mul(&self) -> bool {
+        self.table.find_implicit().is_some()
+    }
+
+    // 規則は OperatorTable::implicit_mul を参照
+    pub fn set_implicit_mul(&mut self, on: bool) {
+        self.table.implicit_mul(on);
+    }
+
+    pub fn parse(&self, tokens: Vec<Token>) -> Result<Ast, ParserError> {
+        let tokens = tokens.into_iter().map(CstToken::bare).collect();
+        let cst = self.parse_cst(tokens, Vec::new())?;
+        Ok(cst.to_ast())
+    }
+
+    pub(crate) fn parse_cst(
+        &self,
+        tokens: Vec<CstToken>,
+        trailing: Vec<Trivia>,
+    ) -> Result<Cst, ParserError> {
+        // 先頭が `名前 =` なら代入文。`x = 2` は方程式ではない
+        let assign = matches!(tokens.first().map(|t| t.kind()), Some(TokenKind::Ident(_)))
+            && tokens.get(1).map(|t| t.kind()) == Some(TokenKind::Equal);
+        let end = trailing
+            .last()
+            .map(|t| t.loc().end())
+            .or(tokens.last().map(|t| t.loc().end()))
+            .unwrap_or(0);
+        let mut tokens = tokens.into_iter().peekable();
+        let mut state = State {
+            table: &self.table,
+            limits: &self.limits,
+            depth: 0,
+            height: 0,
+            nodes: 0,
+            end,
+            equation: false,
+        };
+
+        let ret = if assign {
+            let name = tokens.next().unwrap();
+            let eq = tokens.next().unwrap();
+            let e = parse_entry(&mut tokens, &mut state)?;
+            CstNode::assign(name, eq, e)
+        } else {
+            parse_entry(&mut tokens, &mut state)?
+        };
+
+        match tokens.next() {
+            Some(t) => Err(state.unexpected(t, &[Expected::End])),
+            None => Ok(Cst::new(ret, trailing)),
+        }
+    }
 }
 
 pub fn parse(tokens: Vec<Token>) -> Result<Ast, ParserError> {
-    let mut tokens = tokens.into_iter().peekable();
-
-    let ret = parse_entry(&mut tokens)?;
-
-    match tokens.next() {
-        Some(t) => Err(ParserError::RedundantExpression(t)),
-        None => Ok(ret),
-    }
-}
-
-fn parse_entry<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Result<Ast, ParserError> {
-    parse_expr3(tokens)
-}
-
-fn parse_expr3<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Result<Ast, ParserError> {
-    parse_left_binop(tokens, parse_expr2, |tokens: &mut Peekable<I>| {
-        let op = tokens
-            .peek()
-            .ok_or(ParserError::Eof)
-            .and_then(|t| match t.value() {
-                TokenKind::Plus => Ok(BinOp::add(t.loc())),
-                TokenKind::Minus => Ok(BinOp::sub(t.loc())),
-                _ => Err(ParserError::NotOperator(t.clone())),
-            })?;
-        tokens.next();
-        Ok(op)
-    })
-}
-
-fn parse_expr2<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Result<Ast, ParserError> {
-    parse_left_binop(tokens, parse_expr1, |tokens: &mut Peekable<I>| {
-        let op = tokens
-            .peek()
-            .ok_or(ParserError::Eof)
-            .and_then(|t| match t.value() {
-                TokenKind::Asterisk => Ok(BinOp::mul(t.loc())),
-                TokenKind::Slash => Ok(BinOp::div(t.loc())),
-                _ => Err(ParserError::NotOperator(t.clone())),
-            })?;
-        tokens.next();
-        Ok(op)
-    })
-}
-
-fn parse_expr1<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Result<Ast, ParserError> {
-    match tokens.peek().map(|t| t.value()) {
-        Some(TokenKind::Plus) => {
-            let loc = tokens.next().unwrap().loc();
-            let op = UniOp::plus(loc);
-            let e = parse_atom(tokens)?;
-            let loc = op.loc().merge(&e.loc());
-            Ok(Ast::uniop(op, e, loc))
-        }
-
-        Some(TokenKind::Minus) => {
-            let loc = tokens.next().unwrap().loc();
-            let op = UniOp::minus(loc);
-            let e = parse_atom(tokens)?;
-            let loc = op.loc().merge(&e.loc());
-            Ok(Ast::uniop(op, e, loc))
-        }
-        _ => parse_atom(tokens),
-    }
-}
-
-fn parse_atom<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Result<Ast, ParserError> {
-    tokens
-        .next()
-        .ok_or(ParserError::Eof)
-        .and_then(|t| match t.value() {
-            TokenKind::Number(n) => Ok(Ast::new(AstKind::Num(n), t.loc())),
-            TokenKind::LParen => {
-                let e = parse_entry(tokens)?;
-                match tokens.next() {
-                    Some(Token {
-                        value: TokenKind::RParen,
-                        ..
-                    }) => Ok(e),
-                    Some(t) => Err(ParserError::RedundantExpression(t)),
-                    _ => Err(ParserError::UnclosedOpenParen(t)),
+    Parser::default().parse(tokens)
+}
+
+// 解析中の入れ子の深さと作った節の数を数え、上限を超えたらその位置のトークンで失敗する
+struct State<'a> {
+    table: &'a OperatorTable,
+    limits: &'a Limits,
+    depth: usize,
+    // 作りかけの木の深さの上限の見積もり。入れ子と演算子の連なりの両方で増える
+    height: usize,
+    nodes: usize,
+    // 入力の末尾の位置
+    end: usize,
+    // 直前に読み終えた式が方程式だった
+    equation: bool,
+}
+
+impl State<'_> {
+    fn enter(&mut self, t: &CstToken) -> Result<(), ParserError> {
+        self.depth += 1;
+        if self.depth > self.limits.max_depth {
+            return Err(ParserError::TooDeep(t.token.clone()));
+        }
+        self.grow(t)
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+        self.height -= 1;
+    }
+
+    // 左の被演算子を子に持つ節を一つ作る。再帰しないので depth は増えないが木は深くなる
+    fn grow(&mut self, t: &CstToken) -> Result<(), ParserError> {
+        self.height += 1;
+        if self.height > self.limits.max_tree_depth {
+            return Err(ParserError::TooDeep(t.token.clone()));
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, t: &CstToken) -> Result<(), ParserError> {
+        self.nodes += 1;
+        if self.nodes > self.limits.max_nodes {
+            return Err(ParserError::TooManyNodes(t.token.clone()));
+        }
+        Ok(())
+    }
+
+    // 式の先頭に来られるトークン
+    fn operands(&self) -> Vec<Expected> {
+        let mut ret = vec![
+            Expected::Number,
+            Expected::Name,
+            Expected::History,
+            Expected::Token(TokenKind::LParen),
+            Expected::Token(TokenKind::LBracket),
+        ];
+        ret.extend(
+            self.table
+                .prefix
+                .iter()
+                .map(|p| Expected::Token(p.token.clone())),
+        );
+        ret
+    }
+
+    // 式の後に続けられるトークンと、その後に more を並べる
+    fn operators(&self, more: &[Expected]) -> Vec<Expected> {
+        let mut ret: Vec<Expected> = self
+            .table
+            .infix
+            .iter()
+            .map(|p| p.token.clone())
+            .chain(self.table.postfix.iter().map(|p| p.token.clone()))
+            .map(Expected::Token)
+            .collect();
+        if self.table.implicit.is_some() {
+            ret.extend([Expected::Name, Expected::Token(TokenKind::LParen)]);
+        }
+        if !self.equation {
+            ret.push(Expected::Token(TokenKind::Equal));
+        }
+        ret.extend_from_slice(more);
+        ret
+    }
+
+    // 式の後に found が来た
+    fn unexpected(&self, found: CstToken, more: &[Expected]) -> ParserError {
+        ParserError::UnexpectedToken {
+            found: found.token,
+            expected: self.operators(more),
+        }
+    }
+}
+
+// 式か、`=` で二つの式を結んだ方程式。方程式は続けて書けない
+fn parse_entry<I: Iterator<Item = CstToken>>(
+    tokens: &mut Peekable<I>,
+    state: &mut State,
+) -> Result<CstNode, ParserError> {
+    let l = parse_expr(tokens, state, 0)?;
+    let ret = match tokens.next_if(|t| t.kind() == TokenKind::Equal) {
+        Some(eq) => {
+            state.node(&eq)?;
+            let r = parse_expr(tokens, state, 0)?;
+            CstNode::equation(l, eq, r)
+        }
+        None => l,
+    };
+    state.equation = matches!(ret.value, CstKind::Equation { .. });
+    Ok(ret)
+}
+
+fn parse_expr<I: Iterator<Item = CstToken>>(
+    tokens: &mut Peekable<I>,
+    state: &mut State,
+    min_bp: u16,
+) -> Result<CstNode, ParserError> {
+    if let Some(t) = tokens.peek() {
+        state.enter(t)?;
+    }
+    let l = parse_prefix(tokens, state)?;
+    let e = parse_trailing(tokens, state, l, min_bp)?;
+    state.leave();
+    Ok(e)
+}
+
+fn parse_prefix<I: Iterator<Item = CstToken>>(
+    tokens: &mut Peekable<I>,
+    state: &mut State,
+) -> Result<CstNode, ParserError> {
+    let prefix = tokens
+        .peek()
+        .and_then(|t| state.table.find_prefix(&t.token.value))
+        .cloned();
+
+    match prefix {
+        Some(prefix) => {
+            let token = tokens.next().unwrap();
+            state.enter(&token)?;
+            state.node(&token)?;
+            let op = UniOp::new(prefix.op, token.loc());
+            // 前置演算子は入れ子にでき、自分より強い演算子までを被演算子に取る
+            let e = parse_prefix(tokens, state)?;
+            let e = parse_trailing(tokens, state, e, prefix.bp as u16 * 2 + 1)?;
+            state.leave();
+            Ok(CstNode::prefix(op, token, e))
+        }
+        None => parse_atom(tokens, state),
+    }
+}
+
+fn parse_trailing<I: Iterator<Item = CstToken>>(
+    tokens: &mut Peekable<I>,
+    state: &mut State,
+    mut l: CstNode,
+    min_bp: u16,
+) -> Result<CstNode, ParserError> {
+    let height = state.height;
+    while let Some(t) = tokens.peek() {
+        if let Some(postfix) = state.table.find_postfix(&t.token.value) {
+            if (postfix.bp as u16) * 2 < min_bp {
+                break;
+            }
+            let op = UniOp::new(postfix.op.clone(), t.loc());
+            let token = tokens.next().unwrap();
+            state.node(&token)?;
+            state.grow(&token)?;
+            l = CstNode::postfix(op, token, l);
+            continue;
+        }
+
+        if let Some(infix) = state.table.find_infix(&t.token.value) {
+            let (l_bp, r_bp) = infix.binding_power();
+            if l_bp < min_bp {
+                break;
+            }
+            let op = BinOp::new(infix.op.clone(), t.loc());
+            let token = tokens.next().unwrap();
+            state.node(&token)?;
+            state.grow(&token)?;
+            let r = parse_expr(tokens, state, r_bp)?;
+            l = CstNode::binop(op, token, l, r);
+            continue;
+        }
+
+        // 次の項がそのまま続いていれば暗黙の乗算
+        if let Some(implicit) = state.table.find_implicit() {
+            if matches!(t.kind(), TokenKind::Ident(_) | TokenKind::LParen) {
+                let (l_bp, r_bp) = implicit.binding_power();
+                if l_bp < min_bp {
+                    break;
                 }
+                let op = implicit.op.clone();
+                state.node(t)?;
+                state.grow(t)?;
+                let r = parse_expr(tokens, state, r_bp)?;
+                let loc = l.loc().merge(&r.loc());
+                l = CstNode::implicit(BinOp::new(op, loc), l, r);
+                continue;
+            }
+        }
+
+        break;
+    }
+
+    state.height = height;
+    Ok(l)
+}
+
+fn parse_atom<I: Iterator<Item = CstToken>>(
+    tokens: &mut Peekable<I>,
+    state: &mut State,
+) -> Result<CstNode, ParserError> {
+    let Some(t) = tokens.next() else {
+        return Err(ParserError::Eof {
+            loc: Location::new(state.end, state.end),
+            expected: state.operands(),
+        });
+    };
+    state.node(&t)?;
+
+    match t.kind() {
+        TokenKind::Number(_) | TokenKind::Imaginary(_) => Ok(CstNode::num(t)),
+        TokenKind::Ident(_) => match tokens.peek().map(|t| t.kind()) {
+            Some(TokenKind::LParen) => {
+                let open = tokens.next().unwrap();
+                let (args, commas, close) = parse_list(tokens, state, &open, TokenKind::RParen)?;
+                Ok(CstNode::call(t, open, args, commas, close))
+            }
+            _ => Ok(CstNode::var(t)),
+        },
+        TokenKind::History(_) => Ok(CstNode::var(t)),
+        TokenKind::LBracket => {
+            let (items, commas, close) = parse_list(tokens, state, &t, TokenKind::RBracket)?;
+            Ok(CstNode::vector(t, items, commas, close))
+        }
+        TokenKind::LParen => {
+            let e = parse_entry(tokens, state)?;
+            match tokens.next() {
+                Some(close) if close.kind() == TokenKind::RParen => Ok(CstNode::paren(t, e, close)),
+                Some(close) => Err(state.unexpected(close, &[Expected::Token(TokenKind::RParen)])),
+                _ => Err(ParserError::UnclosedOpenParen(t.token)),
             }
-            _ => Err(ParserError::NotExpression(t)),
-        })
+        }
+        _ => Err(ParserError::UnexpectedToken {
+            found: t.token,
+            expected: state.operands(),
+        }),
+    }
 }
 
-fn parse_left_binop<I: Iterator<Item = Token>>(
+// open の後ろからカンマ区切りの式を close まで読む。空の並びも許す
+fn parse_list<I: Iterator<Item = CstToken>>(
     tokens: &mut Peekable<I>,
-    subexpr_parser: fn(&mut Peekable<I>) -> Result<Ast, ParserError>,
-    or_parser: fn(&mut Peekable<I>) -> Result<BinOp, ParserError>,
-) -> Result<Ast, ParserError> {
-    let mut l = subexpr_parser(tokens)?;
+    state: &mut State,
+    open: &CstToken,
+    close: TokenKind,
+) -> Result<(Vec<CstNode>, Vec<CstToken>, CstToken), ParserError> {
+    let mut items = Vec::new();
+    let mut commas = Vec::new();
+
+    if let Some(t) = tokens.next_if(|t| t.kind() == close) {
+        return Ok((items, commas, t));
+    }
 
     loop {
-        match tokens.peek() {
-            Some(_) => {
-                let op = match or_parser(tokens) {
-                    Ok(op) => op,
-                    Err(_) => break,
-                };
-
-                let r = subexpr_parser(tokens)?;
-                let loc = l.loc().merge(&r.loc());
-                l = Ast::binop(op, l, r, loc)
+        items.push(parse_entry(tokens, state)?);
+        match tokens.next() {
+            Some(t) if t.kind() == TokenKind::Comma => commas.push(t),
+            Some(t) if t.kind() == close => return Ok((items, commas, t)),
+            Some(t) => {
+                let more = [Expected::Token(TokenKind::Comma), Expected::Token(close)];
+                return Err(state.unexpected(t, &more));
             }
-            _ => break,
+            None => return Err(ParserError::UnclosedOpenParen(open.token.clone())),
         }
     }
+}
 
-    Ok(l)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn num(n: f64, start: usize) -> Ast {
+        Ast::num(n, Location::new(start, start + 1))
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let ast = "1 + 2 * 3 - 4".parse::<Ast>().unwrap();
+
+        let mul = Ast::binop(
+            BinOp::mul(Location::new(6, 7)),
+            num(2.0, 4),
+            num(3.0, 8),
+            Location::new(4, 9),
+        );
+        let add = Ast::binop(
+            BinOp::add(Location::new(2, 3)),
+            num(1.0, 0),
+            mul,
+            Location::new(0, 9),
+        );
+        let sub = Ast::binop(
+            BinOp::sub(Location::new(10, 11)),
+            add,
+            num(4.0, 12),
+            Location::new(0, 13),
+        );
+
+        assert_eq!(ast, sub);
+    }
+
+    #[test]
+    fn test_parse_unary() {
+        let ast = "-2*3".parse::<Ast>().unwrap();
+
+        let neg = Ast::uniop(
+            UniOp::minus(Location::new(0, 1)),
+            num(2.0, 1),
+            Location::new(0, 2),
+        );
+        let mul = Ast::binop(
+            BinOp::mul(Location::new(2, 3)),
+            neg,
+            num(3.0, 3),
+            Location::new(0, 4),
+        );
+        assert_eq!(ast, mul);
+
+        let expected = "\
+UniOp(-) 0-3
+└── UniOp(-) 1-3
+    └── Num(5) 2-3
+";
+        assert_eq!("--5".parse::<Ast>().unwrap().to_tree(), expected);
+
+        // 後置演算子は前置より強い
+        let expected = "\
+BinOp(+) 0-10
+├── UniOp(-) 0-3
+│   └── UniOp(!) 1-3
+│       └── Num(3) 1-2
+└── UniOp(+) 6-10
+    └── UniOp(-) 7-10
+        └── UniOp(%) 8-10
+            └── Num(5) 8-9
+";
+        assert_eq!("-3! + +-5%".parse::<Ast>().unwrap().to_tree(), expected);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let tokens = lexer("(1 + 2").unwrap();
+        assert_eq!(
+            parse(tokens),
+            Err(ParserError::UnclosedOpenParen(Token::lparen(
+                Location::new(0, 1)
+            )))
+        );
+
+        let e = parse(lexer("1 2").unwrap()).unwrap_err();
+        assert!(matches!(
+            &e,
+            ParserError::UnexpectedToken { found, .. } if found == &Token::number(2.0, Location::new(2, 3))
+        ));
+        assert_eq!(
+            expected_list(e.expected()),
+            "'+', '-', '*', '/', '@', '^', '!', '%', '=' or end of input"
+        );
+
+        // 方程式は続けて書けない
+        let e = parse(lexer("1 = 2 = 3").unwrap()).unwrap_err();
+        assert_eq!(
+            expected_list(e.expected()),
+            "'+', '-', '*', '/', '@', '^', '!', '%' or end of input"
+        );
+
+        let e = parse(lexer("1 +").unwrap()).unwrap_err();
+        assert_eq!(
+            e,
+            ParserError::Eof {
+                loc: Location::new(3, 3),
+                expected: vec![
+                    Expected::Number,
+                    Expected::Name,
+                    Expected::History,
+                    Expected::Token(TokenKind::LParen),
+                    Expected::Token(TokenKind::LBracket),
+                    Expected::Token(TokenKind::Plus),
+                    Expected::Token(TokenKind::Minus),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        // 上限がなければスタックを使い果たす深さ
+        let deep = "(".repeat(100_000) + "1" + &")".repeat(100_000);
+        assert!(matches!(
+            parse(lexer(&deep).unwrap()),
+            Err(ParserError::TooDeep(_))
+        ));
+        assert!(matches!(
+            parse(lexer(&"-".repeat(100_000)).unwrap()),
+            Err(ParserError::TooDeep(_))
+        ));
+
+        // 左結合の連なりは再帰せずに作るが、木は同じだけ深くなる
+        let flat = vec!["1"; 30_000].join(" + ");
+        assert!(matches!(
+            parse(lexer(&flat).unwrap()),
+            Err(ParserError::TooDeep(_))
+        ));
+        assert!(matches!(
+            parse(lexer(&format!("2{}", "!".repeat(30_000))).unwrap()),
+            Err(ParserError::TooDeep(_))
+        ));
+        assert!(parse(lexer(&vec!["1"; 200].join(" + ")).unwrap()).is_ok());
+
+        let mut parser = Parser::default();
+        parser.set_limits(Limits {
+            max_depth: 3,
+            max_nodes: 6,
+            ..Limits::default()
+        });
+        assert!(parser.parse(lexer("((1)) + -2").unwrap()).is_ok());
+        assert_eq!(
+            parser.parse(lexer("(((1)))").unwrap()),
+            Err(ParserError::TooDeep(Token::number(
+                1.0,
+                Location::new(3, 4)
+            )))
+        );
+        assert_eq!(
+            parser.parse(lexer("1 + 2 + 3 + 4").unwrap()),
+            Err(ParserError::TooManyNodes(Token::number(
+                4.0,
+                Location::new(12, 13)
+            )))
+        );
+    }
+
+    #[test]
+    fn test_parse_registered_operator() {
+        // / を右結合・最弱に登録し直す
+        let mut parser = Parser::default();
+        parser
+            .table_mut()
+            .infix(TokenKind::Slash, 0, Assoc::Right, BinOpKind::Div);
+
+        let ast = parser.parse(lexer("8 / 4 / 2 + 1").unwrap()).unwrap();
+        match ast.value {
+            AstKind::BinOp { op, r, .. } => {
+                assert_eq!(op.value, BinOpKind::Div);
+                assert!(matches!(r.value, AstKind::BinOp { .. }));
+            }
+            _ => panic!("expected binop"),
+        }
+
+        // u8 の上限近くの優先度でも結合力の計算が溢れない
+        let mut parser = Parser::default();
+        parser
+            .table_mut()
+            .infix(TokenKind::Asterisk, 255, Assoc::Left, BinOpKind::Mul)
+            .prefix(TokenKind::Minus, 200, UniOpKind::Minus)
+            .postfix(TokenKind::Bang, 255, UniOpKind::Factorial);
+        let ast = parser.parse(lexer("-2 * 3! * 4").unwrap()).unwrap();
+        assert!(matches!(ast.value, AstKind::UniOp { .. }));
+    }
+
+    #[test]
+    fn test_parse_vector_and_call() {
+        let ast = "[[1, 2], [3, 4]] @ inv(m)".parse::<Ast>().unwrap();
+
+        let expected = "\
+BinOp(@) 0-25
+├── Vector 0-16
+│   ├── Vector 1-7
+│   │   ├── Num(1) 2-3
+│   │   └── Num(2) 5-6
+│   └── Vector 9-15
+│       ├── Num(3) 10-11
+│       └── Num(4) 13-14
+└── Call(inv) 19-25
+    └── Var(m) 23-24
+";
+        assert_eq!(ast.to_tree(), expected);
+
+        assert_eq!(
+            parse(lexer("[1, 2").unwrap()),
+            Err(ParserError::UnclosedOpenParen(Token::lbracket(
+                Location::new(0, 1)
+            )))
+        );
+        let e = parse(lexer("f(1 2)").unwrap()).unwrap_err();
+        assert!(matches!(
+            &e,
+            ParserError::UnexpectedToken { found, .. } if found == &Token::number(2.0, Location::new(4, 5))
+        ));
+        assert!(expected_list(e.expected()).ends_with("'%', '=', ',' or ')'"));
+    }
+
+    #[test]
+    fn test_parse_implicit_mul() {
+        let mut parser = Parser::default();
+        let parse = |parser: &Parser, s: &str| parser.parse(lexer(s).unwrap());
+
+        assert!(matches!(
+            parse(&parser, "2(3 + 4)"),
+            Err(ParserError::UnexpectedToken { found, .. }) if found == Token::lparen(Location::new(1, 2))
+        ));
+
+        parser.set_implicit_mul(true);
+        let ast = parse(&parser, "(1 + 2)(3 + 4)").unwrap();
+        match ast.value {
+            AstKind::BinOp { op, .. } => assert_eq!(op, BinOp::mul(Location::new(0, 14))),
+            _ => panic!("expected binop"),
+        }
+
+        // `*` と同じ優先度で左結合
+        let expected = "\
+BinOp(*) 0-4
+├── BinOp(/) 0-3
+│   ├── Num(1) 0-1
+│   └── Num(2) 2-3
+└── Var(x) 3-4
+";
+        assert_eq!(parse(&parser, "1/2x").unwrap().to_tree(), expected);
+
+        let expected = "\
+BinOp(+) 0-6
+├── BinOp(*) 0-2
+│   ├── Num(3) 0-1
+│   └── Var(x) 1-2
+└── Num(1) 5-6
+";
+        assert_eq!(parse(&parser, "3x + 1").unwrap().to_tree(), expected);
+        assert!(parse(&parser, "2 3").is_err());
+        assert!(matches!(
+            parse(&parser, "f(2)").unwrap().value,
+            AstKind::Call { .. }
+        ));
+    }
+
+    #[test]
+    fn test_to_tree() {
+        let ast = "1 + -2 * 3".parse::<Ast>().unwrap();
+
+        let expected = "\
+BinOp(+) 0-10
+├── Num(1) 0-1
+└── BinOp(*) 4-10
+    ├── UniOp(-) 4-6
+    │   └── Num(2) 5-6
+    └── Num(3) 9-10
+";
+        assert_eq!(ast.to_tree(), expected);
+    }
 }
@@ -0,0 +1,738 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::complete::{complete, Completion};
+use crate::complex::ComplexFormat;
+use crate::editor::Completer;
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::eval::Value;
+use crate::fix::suggest;
+use crate::lexer::lexer_limited;
+use crate::numfmt::{NumberFormat, Precision};
+use crate::rpn::to_rpn;
+use crate::token::Location;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+pub type CommandFn = fn(&mut Repl, &str, &mut dyn Write) -> io::Result<Flow>;
+
+// `:name args` の形で呼び出すコマンド
+#[derive(Clone)]
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: CommandFn,
+}
+
+pub struct Repl {
+    engine: Engine,
+    commands: Vec<Command>,
+    numbers: NumberFormat,
+    // 直前に失敗した入力を直したもの。`:fix` で評価する
+    fix: Option<String>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::with_engine(Engine::new())
+    }
+
+    pub fn with_engine(engine: Engine) -> Self {
+        Self {
+            engine,
+            commands: standard_commands(),
+            numbers: NumberFormat::default(),
+            fix: None,
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    pub fn number_format(&self) -> &NumberFormat {
+        &self.numbers
+    }
+
+    pub fn number_format_mut(&mut self) -> &mut NumberFormat {
+        &mut self.numbers
+    }
+
+    // 同じ名前のコマンドがあれば置き換える
+    pub fn register(&mut self, command: Command) {
+        self.commands.retain(|c| c.name != command.name);
+        self.commands.push(command);
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    // 完全一致がなければ一意な前方一致を探す
+    pub fn find_command(&self, name: &str) -> Option<&Command> {
+        if let Some(c) = self.commands.iter().find(|c| c.name == name) {
+            return Some(c);
+        }
+
+        let mut candidates = self.commands.iter().filter(|c| c.name.starts_with(name));
+        match (candidates.next(), candidates.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    }
+
+    // 登録済みのコマンド名と、関数・変数の名前を補う。cursor はバイト単位
+    pub fn complete(&self, line: &str, cursor: usize) -> Completion {
+        let names: Vec<&str> = self.commands.iter().map(|c| c.name).collect();
+        complete(&self.engine, &names, line, cursor)
+    }
+
+    pub fn handle(&mut self, line: &str, out: &mut dyn Write) -> io::Result<Flow> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(Flow::Continue);
+        }
+
+        let Some(command) = line.strip_prefix(':') else {
+            match self.engine.eval(line) {
+                Ok(v) => {
+                    writeln!(out, "{}", self.numbers.value(&v))?;
+                    self.engine.env_mut().push_history(v);
+                    self.fix = None;
+                }
+                Err(e) => {
+                    writeln!(out, "Error: {}", e.describe(line))?;
+                    let fix = suggest(line, &Location::new(0, line.len()), &e)
+                        .into_iter()
+                        .next();
+                    self.fix = fix.as_ref().map(|fix| fix.apply(line));
+                    if let (Some(fix), Some(fixed)) = (fix, &self.fix) {
+                        writeln!(out, "Hint: {}: {} (:fix to apply)", fix.title, fixed)?;
+                    }
+                }
+            }
+            return Ok(Flow::Continue);
+        };
+
+        let (name, args) = split_command(command);
+        match self.find_command(name) {
+            Some(c) => (c.run)(self, args, out),
+            None => {
+                writeln!(out, "Error: unknown command :{} (try :help)", name)?;
+                Ok(Flow::Continue)
+            }
+        }
+    }
+}
+
+impl Completer for Repl {
+    fn complete(&self, line: &str, cursor: usize) -> (usize, Vec<String>) {
+        let byte = line
+            .char_indices()
+            .nth(cursor)
+            .map_or(line.len(), |(i, _)| i);
+        let completion = Repl::complete(self, line, byte);
+        let start = line[..completion.start].chars().count();
+        let texts = completion.candidates.into_iter().map(|c| c.text);
+        (start, texts.collect())
+    }
+}
+
+pub fn split_command(command: &str) -> (&str, &str) {
+    match command.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (command, ""),
+    }
+}
+
+fn standard_commands() -> Vec<Command> {
+    vec![
+        Command {
+            name: "tokens",
+            usage: ":tokens <expr>",
+            help: "show the tokens produced by the lexer",
+            run: cmd_tokens,
+        },
+        Command {
+            name: "ast",
+            usage: ":ast <expr>",
+            help: "show the syntax tree",
+            run: cmd_ast,
+        },
+        Command {
+            name: "rpn",
+            usage: ":rpn <expr>",
+            help: "show the expression in postfix form",
+            run: cmd_rpn,
+        },
+        Command {
+            name: "type",
+            usage: ":type <expr>",
+            help: "show the type of the result",
+            run: cmd_type,
+        },
+        Command {
+            name: "trace",
+            usage: ":trace <expr>",
+            help: "evaluate one step at a time, marking each reduced part",
+            run: cmd_trace,
+        },
+        Command {
+            name: "time",
+            usage: ":time <expr>",
+            help: "evaluate and show lex/parse/eval durations",
+            run: cmd_time,
+        },
+        Command {
+            name: "solve",
+            usage: ":solve <equation>",
+            help: "solve for the unknown and show the error bound of the root",
+            run: cmd_solve,
+        },
+        Command {
+            name: "fix",
+            usage: ":fix",
+            help: "evaluate the suggested correction of the last failed input",
+            run: cmd_fix,
+        },
+        Command {
+            name: "vars",
+            usage: ":vars",
+            help: "list session variables",
+            run: cmd_vars,
+        },
+        Command {
+            name: "history",
+            usage: ":history",
+            help: "list previous results ($1, $2, ...; ans is the last)",
+            run: cmd_history,
+        },
+        Command {
+            name: "mode",
+            usage: ":mode [real|complex|interval]",
+            help: "show or set the evaluation mode",
+            run: cmd_mode,
+        },
+        Command {
+            name: "implicit",
+            usage: ":implicit [on|off]",
+            help: "treat adjacent terms such as 2x or 2(3 + 4) as multiplication",
+            run: cmd_implicit,
+        },
+        Command {
+            name: "polar",
+            usage: ":polar [on|off]",
+            help: "show complex results in polar form",
+            run: cmd_polar,
+        },
+        Command {
+            name: "digits",
+            usage: ":digits [N|auto]",
+            help: "show results with N significant digits",
+            run: cmd_digits,
+        },
+        Command {
+            name: "fixed",
+            usage: ":fixed [N|auto]",
+            help: "show results with N digits after the decimal point",
+            run: cmd_fixed,
+        },
+        Command {
+            name: "notation",
+            usage: ":notation [plain|sci|eng]",
+            help: "show results as plain, scientific or engineering notation",
+            run: cmd_notation,
+        },
+        Command {
+            name: "radix",
+            usage: ":radix [dec|hex|bin|oct]",
+            help: "show results in another base",
+            run: cmd_radix,
+        },
+        Command {
+            name: "group",
+            usage: ":group [on|off]",
+            help: "separate thousands in results",
+            run: cmd_group,
+        },
+        Command {
+            name: "locale",
+            usage: ":locale [en|de|fr|ch]",
+            help: "choose the thousands and decimal separators",
+            run: cmd_locale,
+        },
+        Command {
+            name: "help",
+            usage: ":help",
+            help: "list commands",
+            run: cmd_help,
+        },
+        Command {
+            name: "quit",
+            usage: ":quit",
+            help: "exit the REPL",
+            run: cmd_quit,
+        },
+    ]
+}
+
+fn cmd_tokens(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    let loc = Location::new(0, args.len());
+    match lexer_limited(args, &loc, repl.engine.limits()) {
+        Ok((tokens, _)) => {
+            for t in tokens.into_iter().map(|t| t.token) {
+                writeln!(out, "{:<8} {:?}", t.loc().to_string(), t.value)?;
+            }
+        }
+        Err(e) => writeln!(out, "Error: {}", Error::from(e).describe(args))?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_ast(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    match repl.engine.parse(args) {
+        Ok(ast) => write!(out, "{}", ast.to_tree())?,
+        Err(e) => writeln!(out, "Error: {}", e.describe(args))?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_rpn(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    match repl.engine.parse(args) {
+        Ok(ast) => writeln!(out, "{}", to_rpn(&ast))?,
+        Err(e) => writeln!(out, "Error: {}", e.describe(args))?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_type(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    match repl.engine.eval(args) {
+        Ok(v) => writeln!(out, "{}", v.type_name())?,
+        Err(e) => writeln!(out, "Error: {}", e.describe(args))?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_trace(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    let trace = match repl.engine.trace(args) {
+        Ok(trace) => trace,
+        Err(e) => {
+            write_error_at(out, args, &e)?;
+            return Ok(Flow::Continue);
+        }
+    };
+
+    // 簡約した部分の下に ^ を引く
+    writeln!(out, "  {}", trace.start)?;
+    for step in &trace.steps {
+        let pad = step.expr[..step.span.start()].chars().count();
+        let width = step.expr[step.span.start()..step.span.end()]
+            .chars()
+            .count();
+        writeln!(out, "= {}", step.expr)?;
+        writeln!(out, "  {}{}", " ".repeat(pad), "^".repeat(width))?;
+    }
+    if let Err(e) = trace.result {
+        write_error_at(out, args, &Error::from(e))?;
+    }
+    Ok(Flow::Continue)
+}
+
+// 入力を表示してエラーの位置の下に ^ を引き、その後にメッセージを書く
+fn write_error_at(out: &mut dyn Write, source: &str, e: &Error) -> io::Result<()> {
+    if let Some(loc) = e.loc() {
+        let start = loc.start().min(source.len());
+        let end = loc.end().clamp(start, source.len());
+        let pad = source[..start].chars().count();
+        let width = source[start..end].chars().count().max(1);
+        writeln!(out, "  {}", source)?;
+        writeln!(out, "  {}{}", " ".repeat(pad), "^".repeat(width))?;
+    }
+    writeln!(out, "Error: {}", e.describe(source))
+}
+
+fn cmd_time(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    let mut timings: Vec<(&str, Duration)> = Vec::new();
+
+    let result = (|| -> Result<Value, Error> {
+        let start = Instant::now();
+        let loc = Location::new(0, args.len());
+        let (tokens, trailing) = lexer_limited(args, &loc, repl.engine.limits())?;
+        timings.push(("lex", start.elapsed()));
+
+        let start = Instant::now();
+        let ast = repl.engine.parser().parse_cst(tokens, trailing)?.to_ast();
+        timings.push(("parse", start.elapsed()));
+
+        let start = Instant::now();
+        let v = repl.engine.eval_ast(&ast)?;
+        timings.push(("eval", start.elapsed()));
+
+        Ok(v)
+    })();
+
+    match result {
+        Ok(v) => writeln!(out, "{}", v)?,
+        Err(e) => writeln!(out, "Error: {}", e.describe(args))?,
+    }
+    for (stage, d) in timings {
+        writeln!(out, "{:<6} {:?}", stage, d)?;
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_solve(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    match repl.engine.solve(args) {
+        Ok(s) => writeln!(
+            out,
+            "{} = {} ± {:.1e} ({} iterations)",
+            s.name,
+            repl.numbers.value(&Value::Number(s.root.x)),
+            s.root.tolerance,
+            s.root.iterations
+        )?,
+        Err(e) => writeln!(out, "Error: {}", e.describe(args))?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_fix(repl: &mut Repl, _: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    match repl.fix.take() {
+        Some(line) => {
+            writeln!(out, "{}", line)?;
+            repl.handle(&line, out)
+        }
+        None => {
+            writeln!(out, "Error: nothing to fix")?;
+            Ok(Flow::Continue)
+        }
+    }
+}
+
+fn cmd_vars(repl: &mut Repl, _: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    for (name, v) in repl.engine.env().vars() {
+        writeln!(out, "{} = {}", name, v)?;
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_history(repl: &mut Repl, _: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    for (i, v) in repl.engine.env().history().iter().enumerate() {
+        writeln!(out, "${} = {}", i + 1, repl.numbers.value(v))?;
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_mode(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    if args.is_empty() {
+        writeln!(out, "{}", repl.engine.mode())?;
+        return Ok(Flow::Continue);
+    }
+    match args.parse() {
+        Ok(mode) => repl.engine.set_mode(mode),
+        Err(e) => writeln!(out, "Error: {}", e)?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_implicit(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    let on = match args {
+        "" => {
+            let on = repl.engine.parser().implicit_mul();
+            writeln!(out, "{}", if on { "on" } else { "off" })?;
+            return Ok(Flow::Continue);
+        }
+        "on" => true,
+        "off" => false,
+        _ => {
+            writeln!(out, "Error: expected on or off")?;
+            return Ok(Flow::Continue);
+        }
+    };
+    repl.engine.parser_mut().set_implicit_mul(on);
+    Ok(Flow::Continue)
+}
+
+fn cmd_polar(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    repl.numbers.complex = match args {
+        "" | "on" => ComplexFormat::Polar,
+        "off" => ComplexFormat::Rect,
+        _ => {
+            writeln!(out, "Error: expected on or off")?;
+            return Ok(Flow::Continue);
+        }
+    };
+    Ok(Flow::Continue)
+}
+
+fn cmd_digits(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    set_precision(repl, args, Precision::significant, out)
+}
+
+fn cmd_fixed(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    set_precision(repl, args, Precision::fixed, out)
+}
+
+fn set_precision(
+    repl: &mut Repl,
+    args: &str,
+    precision: fn(usize) -> Result<Precision, String>,
+    out: &mut dyn Write,
+) -> io::Result<Flow> {
+    repl.numbers.precision = match args {
+        "" => {
+            match repl.numbers.precision {
+                Precision::Shortest => writeln!(out, "auto")?,
+                Precision::Significant(n) => writeln!(out, "{} significant digits", n)?,
+                Precision::Fixed(n) => writeln!(out, "{} decimals", n)?,
+            }
+            return Ok(Flow::Continue);
+        }
+        "auto" => Precision::Shortest,
+        _ => match args.parse().map(precision) {
+            Ok(Ok(p)) => p,
+            Ok(Err(e)) => {
+                writeln!(out, "Error: {}", e)?;
+                return Ok(Flow::Continue);
+            }
+            Err(_) => {
+                writeln!(out, "Error: expected a number of digits or auto")?;
+                return Ok(Flow::Continue);
+            }
+        },
+    };
+    Ok(Flow::Continue)
+}
+
+fn cmd_notation(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    if args.is_empty() {
+        writeln!(out, "{}", repl.numbers.notation)?;
+        return Ok(Flow::Continue);
+    }
+    match args.parse() {
+        Ok(notation) => repl.numbers.notation = notation,
+        Err(e) => writeln!(out, "Error: {}", e)?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_radix(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    if args.is_empty() {
+        writeln!(out, "{}", repl.numbers.radix)?;
+        return Ok(Flow::Continue);
+    }
+    match args.parse() {
+        Ok(radix) => repl.numbers.radix = radix,
+        Err(e) => writeln!(out, "Error: {}", e)?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_group(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    repl.numbers.grouping = match args {
+        "" | "on" => true,
+        "off" => false,
+        _ => {
+            writeln!(out, "Error: expected on or off")?;
+            return Ok(Flow::Continue);
+        }
+    };
+    Ok(Flow::Continue)
+}
+
+fn cmd_locale(repl: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    if args.is_empty() {
+        writeln!(out, "{}", repl.numbers.locale.name)?;
+        return Ok(Flow::Continue);
+    }
+    match args.parse() {
+        Ok(locale) => repl.numbers.locale = locale,
+        Err(e) => writeln!(out, "Error: {}", e)?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_help(repl: &mut Repl, _: &str, out: &mut dyn Write) -> io::Result<Flow> {
+    for c in repl.commands() {
+        writeln!(out, "{:<16} {}", c.usage, c.help)?;
+    }
+    Ok(Flow::Continue)
+}
+
+fn cmd_quit(_: &mut Repl, _: &str, _: &mut dyn Write) -> io::Result<Flow> {
+    Ok(Flow::Quit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+
+    fn run(repl: &mut Repl, line: &str) -> (Flow, String) {
+        let mut out = Vec::new();
+        let flow = repl.handle(line, &mut out).unwrap();
+        (flow, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_repl_commands() {
+        let mut repl = Repl::new();
+
+        assert_eq!(run(&mut repl, "1 + 2 * 3").1, "7\n");
+        assert_eq!(run(&mut repl, ":rpn 1 + 2 * 3").1, "1 2 3 * +\n");
+        assert_eq!(run(&mut repl, ":type 1").1, "number\n");
+        assert_eq!(run(&mut repl, "b = 2").1, "2\n");
+        assert_eq!(run(&mut repl, "a = b * 2").1, "4\n");
+        assert_eq!(run(&mut repl, ":vars").1, "a = 4\nb = 2\n");
+        assert_eq!(
+            run(&mut repl, "1 + * 2").1,
+            concat!(
+                "Error: expected number, name, history reference, '(', '[', '+' or '-' at 1:5, found '*'\n",
+                "Hint: remove '*': 1 + 2 (:fix to apply)\n",
+            )
+        );
+        assert_eq!(run(&mut repl, ":fix").1, "1 + 2\n3\n");
+        assert_eq!(
+            run(&mut repl, ":solve x^2 = 2").1,
+            "x = 1.4142135623730951 ± 3.3e-16 (7 iterations)\n"
+        );
+        assert_eq!(run(&mut repl, ":fix").1, "Error: nothing to fix\n");
+        assert_eq!(run(&mut repl, ":tokens 1+2").1.lines().count(), 3);
+        assert!(run(&mut repl, ":time 1 + 2").1.starts_with("3\nlex"));
+        assert_eq!(
+            run(&mut repl, ":type 1 / 0").1,
+            "Error: division by zero at 1:3\n"
+        );
+        assert_eq!(
+            run(&mut repl, ":tokens 1 $ 2").1,
+            "Error: invalid character '$' at 1:3\n"
+        );
+        // 診断用のコマンドも通常の評価と同じ入力の制限を受ける
+        repl.engine.set_limits(Limits {
+            max_input_bytes: 4,
+            ..Limits::default()
+        });
+        assert_eq!(
+            run(&mut repl, ":time 1 + 2").1,
+            "Error: input is 5 bytes long, the limit is 4 at 1:5\n"
+        );
+        repl.engine.set_limits(Limits::default());
+        assert!(run(&mut repl, ":nope")
+            .1
+            .starts_with("Error: unknown command"));
+        assert_eq!(run(&mut repl, ":q").0, Flow::Quit);
+    }
+
+    #[test]
+    fn test_repl_trace() {
+        let mut repl = Repl::new();
+
+        assert_eq!(
+            run(&mut repl, ":trace (1 + 2) * 3 - 4 / 2").1,
+            "  (1 + 2) * 3 - 4 / 2\n\
+             = 3 * 3 - 4 / 2\n  ^\n\
+             = 9 - 4 / 2\n  ^\n\
+             = 9 - 2\n      ^\n\
+             = 7\n  ^\n"
+        );
+        assert_eq!(
+            run(&mut repl, ":trace 1 / (2 - 2)").1,
+            "  1 / (2 - 2)\n\
+             = 1 / 0\n      ^\n  \
+             1 / (2 - 2)\n    ^\n\
+             Error: division by zero at 1:3\n"
+        );
+        assert_eq!(
+            run(&mut repl, ":trace 1 + * 2").1,
+            "  1 + * 2\n      ^\n\
+             Error: expected number, name, history reference, '(', '[', '+' or '-' at 1:5, found '*'\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_number_format() {
+        let mut repl = Repl::new();
+
+        run(&mut repl, ":digits 3");
+        assert_eq!(run(&mut repl, "2 / 3").1, "0.667\n");
+        run(&mut repl, ":fixed 2");
+        run(&mut repl, ":group");
+        run(&mut repl, ":locale de");
+        assert_eq!(run(&mut repl, "1234567 / 2").1, "617.283,50\n");
+        run(&mut repl, ":notation eng");
+        assert_eq!(run(&mut repl, "[1500, 0.02]").1, "[1,50e3; 20,00e-3]\n");
+        run(&mut repl, ":radix hex");
+        assert_eq!(run(&mut repl, "255").1, "0xff.00\n");
+        assert_eq!(run(&mut repl, ":radix").1, "hex\n");
+        assert!(run(&mut repl, ":radix 7").1.starts_with("Error:"));
+        assert!(run(&mut repl, ":fixed many").1.starts_with("Error:"));
+        assert_eq!(
+            run(&mut repl, ":fixed 70000").1,
+            "Error: at most 1074 decimals\n"
+        );
+        assert_eq!(run(&mut repl, ":fixed").1, "2 decimals\n");
+    }
+
+    #[test]
+    fn test_repl_history() {
+        let mut repl = Repl::new();
+
+        run(&mut repl, "1 + 2");
+        run(&mut repl, "ans * 2");
+        assert_eq!(run(&mut repl, "$1 + _").1, "9\n");
+        assert_eq!(run(&mut repl, ":history").1, "$1 = 3\n$2 = 6\n$3 = 9\n");
+        assert!(run(&mut repl, "$7").1.starts_with("Error:"));
+        assert_eq!(run(&mut repl, ":history").1.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_repl_complex_mode() {
+        let mut repl = Repl::new();
+
+        assert!(run(&mut repl, "sqrt(-1)").1.starts_with("Error:"));
+        run(&mut repl, ":mode complex");
+        assert_eq!(run(&mut repl, ":mode").1, "complex\n");
+        assert_eq!(run(&mut repl, "sqrt(-1)").1, "1i\n");
+        assert_eq!(run(&mut repl, "(3 + 4i) * (1 - 2i)").1, "11 - 2i\n");
+        run(&mut repl, ":polar");
+        assert_eq!(
+            run(&mut repl, "-2i").1,
+            format!("2∠{}\n", -std::f64::consts::FRAC_PI_2)
+        );
+    }
+
+    #[test]
+    fn test_repl_register() {
+        fn cmd_hello(_: &mut Repl, args: &str, out: &mut dyn Write) -> io::Result<Flow> {
+            writeln!(out, "hello {}", args)?;
+            Ok(Flow::Continue)
+        }
+
+        let mut repl = Repl::new();
+        repl.register(Command {
+            name: "hello",
+            usage: ":hello <name>",
+            help: "greet",
+            run: cmd_hello,
+        });
+
+        assert_eq!(run(&mut repl, ":hello world").1, "hello world\n");
+        assert!(run(&mut repl, ":help").1.contains(":hello <name>"));
+    }
+}
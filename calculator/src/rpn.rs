@@ -1,10 +1,13 @@
-use anyhow::{Result, bail};
+use anyhow::{bail, Result};
+
+use crate::parser::{Ast, AstKind, BinOpKind, UniOpKind};
+use crate::value::factorial;
 
 pub trait ReversePolishNotation {
     fn calculate_rpn(&self) -> Result<f64>;
 }
 
-impl ReversePolishNotation for Vec<> {
+impl ReversePolishNotation for str {
     fn calculate_rpn(&self) -> Result<f64> {
         let mut stack = Vec::new();
 
@@ -17,12 +20,28 @@ impl ReversePolishNotation for Vec<> {
                     "-" => apply(&mut stack, |x, y| x - y)?,
                     "*" => apply(&mut stack, |x, y| x * y)?,
                     "/" => apply(&mut stack, |x, y| x / y)?,
-                    _ => panic!("Unknow operator: {}", token),
+                    "^" => apply(&mut stack, f64::powf)?,
+                    "neg" => match stack.pop() {
+                        Some(x) => stack.push(-x),
+                        None => bail!("Cant aaply notaion"),
+                    },
+                    "%" => match stack.pop() {
+                        Some(x) => stack.push(x / 100.0),
+                        None => bail!("Cant aaply notaion"),
+                    },
+                    "!" => match stack.pop().and_then(factorial) {
+                        Some(x) => stack.push(x),
+                        None => bail!("Cant aaply notaion"),
+                    },
+                    _ => bail!("Unknow operator: {}", token),
                 }
             }
         }
-    
-        Ok(stack.pop().unwrap())
+
+        match stack.pop() {
+            Some(x) if stack.is_empty() => Ok(x),
+            _ => bail!("Cant aaply notaion"),
+        }
     }
 }
 
@@ -37,24 +56,92 @@ fn apply(stack: &mut Vec<f64>, f: impl Fn(f64, f64) -> f64) -> Result<()> {
     Ok(())
 }
 
+// 後置記法に変換する。単項マイナスは neg、n 要素のベクトルは [n]、関数は名前/引数の数、
+// 方程式の両辺は == で結ぶ
+pub fn to_rpn(ast: &Ast) -> String {
+    let mut tokens = Vec::new();
+    push_rpn(ast, &mut tokens);
+    tokens.join(" ")
+}
+
+fn push_rpn(ast: &Ast, tokens: &mut Vec<String>) {
+    match &ast.value {
+        AstKind::Num(n) => tokens.push(n.to_string()),
+        AstKind::Imag(n) => tokens.push(format!("{}i", n)),
+        AstKind::Var(name) => tokens.push(name.clone()),
+        AstKind::History(n) => tokens.push(format!("${}", n)),
+        AstKind::Assign { name, e } => {
+            push_rpn(e, tokens);
+            tokens.push(name.clone());
+            tokens.push("=".to_string());
+        }
+        AstKind::Equation { l, r } => {
+            push_rpn(l, tokens);
+            push_rpn(r, tokens);
+            tokens.push("==".to_string());
+        }
+        AstKind::UniOp { op, e } => {
+            push_rpn(e, tokens);
+            match op.value {
+                UniOpKind::Plus => {}
+                UniOpKind::Minus => tokens.push("neg".to_string()),
+                UniOpKind::Factorial => tokens.push("!".to_string()),
+                UniOpKind::Percent => tokens.push("%".to_string()),
+            }
+        }
+        AstKind::BinOp { op, l, r } => {
+            push_rpn(l, tokens);
+            push_rpn(r, tokens);
+            let op = match op.value {
+                BinOpKind::Add => "+",
+                BinOpKind::Sub => "-",
+                BinOpKind::Mul => "*",
+                BinOpKind::Div => "/",
+                BinOpKind::MatMul => "@",
+                BinOpKind::Pow => "^",
+            };
+            tokens.push(op.to_string());
+        }
+        AstKind::Vector(items) => {
+            items.iter().for_each(|e| push_rpn(e, tokens));
+            tokens.push(format!("[{}]", items.len()));
+        }
+        AstKind::Call { name, args } => {
+            args.iter().for_each(|e| push_rpn(e, tokens));
+            tokens.push(format!("{}/{}", name, args.len()));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_rpn() {
-        let exp = "6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -"; 
+        let exp = "6.1 5.2 4.3 * + 3.4 2.5 / 1.6 * -";
 
         let result = exp.calculate_rpn();
 
         assert!(result.is_ok());
         assert_eq!(26.284000000000002, result.unwrap());
 
-        let exp = "6.1 5.2 * + 3.4 2.5 / 1.6 * -"; 
+        let exp = "6.1 5.2 * + 3.4 2.5 / 1.6 * -";
 
         let result = exp.calculate_rpn();
 
         assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_to_rpn() {
+        let ast = "(1 + 2) * -3".parse::<Ast>().unwrap();
+        let rpn = to_rpn(&ast);
 
+        assert_eq!(rpn, "1 2 + 3 neg *");
+        assert_eq!(rpn.calculate_rpn().unwrap(), -9.0);
+
+        let ast = "det([1, 2] @ m)".parse::<Ast>().unwrap();
+        assert_eq!(to_rpn(&ast), "1 2 [2] m @ det/1");
     }
-}
\ No newline at end of file
+}
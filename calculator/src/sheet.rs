@@ -0,0 +1,397 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::eval::Value;
+use crate::lexer::lexer;
+use crate::parser::{Ast, AstKind};
+use crate::token::*;
+
+// `A1` や `AB12` の形のセル番地。列は 0 始まり、行は 1 始まり
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellRef {
+    pub col: u32,
+    pub row: u32,
+}
+
+impl FromStr for CellRef {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("not a cell reference: {}", s);
+        let digits = s.find(|c: char| c.is_ascii_digit()).ok_or_else(err)?;
+        let (letters, row) = s.split_at(digits);
+        if letters.is_empty()
+            || letters.len() > 3
+            || !letters.bytes().all(|b| b.is_ascii_uppercase())
+            || row.starts_with('0')
+            || !row.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(err());
+        }
+
+        // A..Z, AA..ZZ, ... と続く 26 進
+        let col = letters
+            .bytes()
+            .fold(0, |n, b| n * 26 + (b - b'A') as u32 + 1);
+        Ok(Self {
+            col: col - 1,
+            row: row.parse().map_err(|_| err())?,
+        })
+    }
+}
+
+impl std::fmt::Display for CellRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut letters = Vec::new();
+        let mut n = self.col + 1;
+        while n > 0 {
+            letters.push((b'A' + ((n - 1) % 26) as u8) as char);
+            n = (n - 1) / 26;
+        }
+        let letters: String = letters.into_iter().rev().collect();
+        write!(f, "{}{}", letters, self.row)
+    }
+}
+
+// 名前のトークンのうちセル番地になっているもの。関数呼び出しの名前は除く
+pub fn cell_refs(tokens: &[Token]) -> Vec<Annotation<CellRef>> {
+    tokens
+        .iter()
+        .enumerate()
+        .filter_map(|(i, t)| match &t.value {
+            TokenKind::Ident(name)
+                if tokens.get(i + 1).map(|t| &t.value) != Some(&TokenKind::LParen) =>
+            {
+                let cell = name.parse().ok()?;
+                Some(Annotation::new(cell, t.loc()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SheetError {
+    Syntax(Error),
+    // 代入の形でないか、左辺がセル番地でない
+    NotACell(String),
+    // 循環している参照。最初と最後は同じセル
+    Cycle(Vec<CellRef>),
+}
+
+impl From<Error> for SheetError {
+    fn from(e: Error) -> Self {
+        SheetError::Syntax(e)
+    }
+}
+
+impl std::fmt::Display for SheetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SheetError::Syntax(e) => e.fmt(f),
+            SheetError::NotACell(s) => write!(
+                f,
+                "expected a cell assignment such as A1 = 1, found '{}'",
+                s
+            ),
+            SheetError::Cycle(cells) => {
+                let path: Vec<String> = cells.iter().map(|c| c.to_string()).collect();
+                write!(f, "circular reference: {}", path.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SheetError {}
+
+#[derive(Debug, Clone)]
+struct Cell {
+    formula: String,
+    ast: Ast,
+    deps: BTreeSet<CellRef>,
+    value: Result<Value, Error>,
+}
+
+// セルごとの式と値。式を変えると、そのセルに依存するセルだけを依存の順に計算し直す。
+// セルの値は engine の変数として見えるので、式から普通の変数も参照できる
+#[derive(Debug, Clone, Default)]
+pub struct Sheet {
+    engine: Engine,
+    cells: BTreeMap<CellRef, Cell>,
+    // セルからそのセルを参照しているセルへの辺
+    dependents: BTreeMap<CellRef, BTreeSet<CellRef>>,
+}
+
+impl Sheet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_engine(engine: Engine) -> Self {
+        Self {
+            engine,
+            ..Self::default()
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn value(&self, cell: CellRef) -> Option<&Result<Value, Error>> {
+        self.cells.get(&cell).map(|c| &c.value)
+    }
+
+    pub fn formula(&self, cell: CellRef) -> Option<&str> {
+        self.cells.get(&cell).map(|c| c.formula.as_str())
+    }
+
+    // 式の入っているセルを番地順に返す
+    pub fn cells(&self) -> impl Iterator<Item = CellRef> + '_ {
+        self.cells.keys().copied()
+    }
+
+    // `A1 = 10` の形の文を受け取る。計算し直したセルを計算した順に返す
+    pub fn define(&mut self, input: &str) -> Result<Vec<CellRef>, SheetError> {
+        let ast = self.engine.parse(input)?;
+        let AstKind::Assign { name, e } = &ast.value else {
+            return Err(SheetError::NotACell(input.trim().to_string()));
+        };
+        let cell = name
+            .parse()
+            .map_err(|_| SheetError::NotACell(name.clone()))?;
+        self.set(cell, &input[e.loc().start()..e.loc().end()])
+    }
+
+    // 循環する参照になる式は受け付けず、シートは変わらない
+    pub fn set(&mut self, cell: CellRef, formula: &str) -> Result<Vec<CellRef>, SheetError> {
+        let ast = self.engine.parse(formula)?;
+        let deps: BTreeSet<CellRef> = cell_refs(&lexer(formula).map_err(Error::from)?)
+            .into_iter()
+            .map(|c| c.value)
+            .collect();
+        if let Some(cycle) = self.find_cycle(cell, &deps) {
+            return Err(SheetError::Cycle(cycle));
+        }
+
+        self.unlink(cell);
+        for dep in &deps {
+            self.dependents.entry(*dep).or_default().insert(cell);
+        }
+        self.cells.insert(
+            cell,
+            Cell {
+                formula: formula.trim().to_string(),
+                ast,
+                deps,
+                value: Ok(Value::Number(0.0)),
+            },
+        );
+        Ok(self.recalculate(cell))
+    }
+
+    // セルを空にする。参照していたセルは未定義の変数の誤りになる
+    pub fn clear(&mut self, cell: CellRef) -> Vec<CellRef> {
+        self.unlink(cell);
+        self.cells.remove(&cell);
+        self.recalculate(cell)
+    }
+
+    fn unlink(&mut self, cell: CellRef) {
+        let Some(old) = self.cells.get(&cell) else {
+            return;
+        };
+        for dep in &old.deps {
+            if let Some(set) = self.dependents.get_mut(dep) {
+                set.remove(&cell);
+            }
+        }
+    }
+
+    // deps のどれかから参照をたどって cell に戻れるなら、その経路を返す
+    fn find_cycle(&self, cell: CellRef, deps: &BTreeSet<CellRef>) -> Option<Vec<CellRef>> {
+        let mut path = vec![cell];
+        let mut seen = BTreeSet::new();
+        for dep in deps {
+            if self.reaches(*dep, cell, &mut path, &mut seen) {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    fn reaches(
+        &self,
+        from: CellRef,
+        target: CellRef,
+        path: &mut Vec<CellRef>,
+        seen: &mut BTreeSet<CellRef>,
+    ) -> bool {
+        path.push(from);
+        if from == target {
+            return true;
+        }
+        if seen.insert(from) {
+            let deps = self.cells.get(&from).map(|c| &c.deps);
+            for dep in deps.into_iter().flatten() {
+                if self.reaches(*dep, target, path, seen) {
+                    return true;
+                }
+            }
+        }
+        path.pop();
+        false
+    }
+
+    // cell とそれに依存するセルを、参照されるものが先になる順に計算する
+    fn recalculate(&mut self, cell: CellRef) -> Vec<CellRef> {
+        let mut affected = BTreeSet::from([cell]);
+        let mut stack = vec![cell];
+        while let Some(c) = stack.pop() {
+            for d in self.dependents.get(&c).into_iter().flatten() {
+                if affected.insert(*d) {
+                    stack.push(*d);
+                }
+            }
+        }
+
+        // 影響を受けるセルの中だけで数えた、未計算の参照の数
+        let mut pending: BTreeMap<CellRef, usize> = affected
+            .iter()
+            .map(|c| {
+                let deps = self.cells.get(c).map(|cell| &cell.deps);
+                let n = deps
+                    .into_iter()
+                    .flatten()
+                    .filter(|d| affected.contains(d))
+                    .count();
+                (*c, n)
+            })
+            .collect();
+        let mut ready: Vec<CellRef> = pending
+            .iter()
+            .filter(|(_, n)| **n == 0)
+            .map(|(c, _)| *c)
+            .collect();
+
+        let mut order = Vec::new();
+        while let Some(c) = ready.pop() {
+            if self.cells.contains_key(&c) {
+                self.evaluate(c);
+                order.push(c);
+            } else {
+                self.engine.env_mut().remove(&c.to_string());
+            }
+            for d in self.dependents.get(&c).into_iter().flatten() {
+                let n = pending.get_mut(d).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    ready.push(*d);
+                }
+            }
+        }
+        order
+    }
+
+    fn evaluate(&mut self, cell: CellRef) {
+        let name = cell.to_string();
+        let ast = self.cells[&cell].ast.clone();
+        let value = self.engine.eval_ast(&ast);
+        match &value {
+            Ok(v) => self.engine.set(&name, v.clone()),
+            Err(_) => {
+                self.engine.env_mut().remove(&name);
+            }
+        }
+        self.cells.get_mut(&cell).unwrap().value = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(s: &str) -> CellRef {
+        s.parse().unwrap()
+    }
+
+    fn number(sheet: &Sheet, s: &str) -> Option<f64> {
+        match sheet.value(cell(s)) {
+            Some(Ok(Value::Number(n))) => Some(*n),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_cell_refs() {
+        assert_eq!(cell("A1"), CellRef { col: 0, row: 1 });
+        assert_eq!(cell("AB12"), CellRef { col: 27, row: 12 });
+        assert_eq!(cell("AB12").to_string(), "AB12");
+        assert_eq!(CellRef { col: 25, row: 3 }.to_string(), "Z3");
+        for s in ["a1", "A0", "A", "1", "A1B", "ABCD1"] {
+            assert!(s.parse::<CellRef>().is_err(), "{}", s);
+        }
+
+        let refs = cell_refs(&lexer("A1 + abs(B2) * x - C3(1)").unwrap());
+        let refs: Vec<_> = refs
+            .iter()
+            .map(|r| (r.value.to_string(), r.loc()))
+            .collect();
+        assert_eq!(
+            refs,
+            vec![
+                ("A1".to_string(), Location::new(0, 2)),
+                ("B2".to_string(), Location::new(9, 11)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sheet_recalculates_dependents() {
+        let mut sheet = Sheet::new();
+
+        sheet.define("A1 = 10").unwrap();
+        sheet.define("B1 = A1 * 2").unwrap();
+        sheet.define("C1 = A1 + B1").unwrap();
+        sheet.define("D1 = 5").unwrap();
+        assert_eq!(number(&sheet, "C1"), Some(30.0));
+
+        // 依存しない D1 は計算し直さない
+        let order = sheet.define("A1 = 1").unwrap();
+        assert_eq!(order, vec![cell("A1"), cell("B1"), cell("C1")]);
+        assert_eq!(number(&sheet, "C1"), Some(3.0));
+        assert_eq!(sheet.formula(cell("B1")), Some("A1 * 2"));
+
+        // 空のセルを参照すると誤りになり、埋めれば直る
+        sheet.define("E1 = F1 + 1").unwrap();
+        assert!(matches!(sheet.value(cell("E1")), Some(Err(_))));
+        sheet.define("F1 = D1").unwrap();
+        assert_eq!(number(&sheet, "E1"), Some(6.0));
+        sheet.clear(cell("F1"));
+        assert!(matches!(sheet.value(cell("E1")), Some(Err(_))));
+
+        assert!(matches!(
+            sheet.define("x = 1"),
+            Err(SheetError::NotACell(_))
+        ));
+    }
+
+    #[test]
+    fn test_sheet_rejects_cycles() {
+        let mut sheet = Sheet::new();
+        sheet.define("A1 = B1 + 1").unwrap();
+        sheet.define("B1 = C1 * 2").unwrap();
+
+        let e = sheet.define("C1 = A1").unwrap_err();
+        assert_eq!(
+            e,
+            SheetError::Cycle(vec![cell("C1"), cell("A1"), cell("B1"), cell("C1")])
+        );
+        assert_eq!(e.to_string(), "circular reference: C1 -> A1 -> B1 -> C1");
+        assert_eq!(sheet.formula(cell("C1")), None);
+
+        let e = sheet.define("D1 = D1 + 1").unwrap_err();
+        assert_eq!(e.to_string(), "circular reference: D1 -> D1");
+    }
+}
@@ -0,0 +1,224 @@
+// 一変数の方程式 f(x) = 0 の数値解法
+
+// 解を絞る反復の上限
+pub const MAX_ITERATIONS: usize = 100;
+// 符号の変わる区間を探すときに初期値から左右へ広げる回数。幅は毎回倍にする
+const MAX_EXPANSIONS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Root {
+    pub x: f64,
+    // 真の解との差の上限の見積もり。符号の変わる区間で挟めた場合はその半分の幅
+    pub tolerance: f64,
+    // f(x)
+    pub residual: f64,
+    pub iterations: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SolveError<E> {
+    Eval(E),
+    // 反復の回数
+    NoConvergence(usize),
+}
+
+// 名前の付いた未知数の解
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    pub name: String,
+    pub root: Root,
+}
+
+// guess から左右に広げて符号の変わる区間を探し、その中で割線法と二分法を組み合わせて解を絞る。
+// 区間が見つからなければ guess から割線法だけで探す。
+// 区間を探す途中で f が失敗した点や有限でない点は飛ばすが、guess と解を絞る途中での失敗はそのまま返す
+pub fn find_root<E>(
+    mut f: impl FnMut(f64) -> Result<f64, E>,
+    guess: f64,
+) -> Result<Root, SolveError<E>> {
+    let f0 = f(guess).map_err(SolveError::Eval)?;
+    if f0 == 0.0 {
+        return Ok(Root {
+            x: guess,
+            tolerance: 0.0,
+            residual: 0.0,
+            iterations: 0,
+        });
+    }
+    if !f0.is_finite() {
+        return Err(SolveError::NoConvergence(0));
+    }
+
+    match bracket(&mut f, guess, f0) {
+        Some((a, b)) => refine(&mut f, a, b),
+        None => secant(&mut f, guess, f0),
+    }
+}
+
+fn bracket<E>(
+    f: &mut impl FnMut(f64) -> Result<f64, E>,
+    x0: f64,
+    f0: f64,
+) -> Option<((f64, f64), (f64, f64))> {
+    let mut h = 0.01 * x0.abs().max(1.0);
+    // 左右それぞれで最後に値の得られた点
+    let mut sides = [(x0, f0), (x0, f0)];
+
+    for _ in 0..MAX_EXPANSIONS {
+        for (side, dir) in sides.iter_mut().zip([1.0, -1.0]) {
+            let x = x0 + dir * h;
+            let Some(fx) = f(x).ok().filter(|y| y.is_finite()) else {
+                continue;
+            };
+            if fx == 0.0 || (fx < 0.0) != (side.1 < 0.0) {
+                return Some((*side, (x, fx)));
+            }
+            *side = (x, fx);
+        }
+        h *= 2.0;
+    }
+    None
+}
+
+// a と b で符号の異なる区間を縮める。割線法の一歩が区間の中点側に収まらないときや、
+// 区間が二回続けて半分にならなかったときは二分法にする
+fn refine<E>(
+    f: &mut impl FnMut(f64) -> Result<f64, E>,
+    (mut a, mut fa): (f64, f64),
+    (mut b, mut fb): (f64, f64),
+) -> Result<Root, SolveError<E>> {
+    // 端より大きな値に収束したら解ではなく不連続点
+    let limit = fa.abs().max(fb.abs());
+    // b が最良の近似、a は f の符号が b と逆の点、c は一つ前の b
+    let (mut c, mut fc) = (a, fa);
+    let mut slow = 0;
+
+    for i in 0..MAX_ITERATIONS {
+        if fa.abs() < fb.abs() {
+            std::mem::swap(&mut a, &mut b);
+            std::mem::swap(&mut fa, &mut fb);
+        }
+
+        let tol = 2.0 * f64::EPSILON * b.abs().max(1.0);
+        let m = (a - b) / 2.0;
+        if m.abs() <= tol || fb == 0.0 {
+            if fb.abs() > limit {
+                return Err(SolveError::NoConvergence(i));
+            }
+            return Ok(Root {
+                x: b,
+                tolerance: if fb == 0.0 { 0.0 } else { m.abs() },
+                residual: fb,
+                iterations: i,
+            });
+        }
+
+        let mut s = if fb != fc {
+            b - fb * (b - c) / (fb - fc)
+        } else {
+            b + m
+        };
+        if slow >= 2 || (s - b) * (s - (b + m)) >= 0.0 {
+            s = b + m;
+            slow = 0;
+        }
+        // 許容誤差より小さな歩みでは区間が縮まらない
+        if (s - b).abs() < tol {
+            s = b + tol.copysign(m);
+        }
+
+        let fs = f(s).map_err(SolveError::Eval)?;
+        if !fs.is_finite() {
+            return Err(SolveError::NoConvergence(i + 1));
+        }
+
+        let width = (a - b).abs();
+        (c, fc) = (b, fb);
+        (b, fb) = (s, fs);
+        if fb != 0.0 && (fb < 0.0) == (fa < 0.0) {
+            (a, fa) = (c, fc);
+        }
+        slow = if (a - b).abs() > width / 2.0 {
+            slow + 1
+        } else {
+            0
+        };
+    }
+
+    Err(SolveError::NoConvergence(MAX_ITERATIONS))
+}
+
+// 符号の変わる区間がないとき(重解など)。残差が初期値より大きいままなら解とみなさない
+fn secant<E>(
+    f: &mut impl FnMut(f64) -> Result<f64, E>,
+    x0: f64,
+    f0: f64,
+) -> Result<Root, SolveError<E>> {
+    let (mut a, mut fa) = (x0, f0);
+    let mut b = x0 + 0.01 * x0.abs().max(1.0);
+    let mut fb = f(b).map_err(SolveError::Eval)?;
+
+    for i in 1..=MAX_ITERATIONS {
+        if !fb.is_finite() || fb == fa {
+            return Err(SolveError::NoConvergence(i));
+        }
+        let step = fb * (b - a) / (fb - fa);
+        (a, fa) = (b, fb);
+        b -= step;
+        fb = f(b).map_err(SolveError::Eval)?;
+
+        let tol = 2.0 * f64::EPSILON * b.abs().max(1.0);
+        if fb == 0.0 || (step.abs() <= tol && fb.abs() <= f0.abs()) {
+            return Ok(Root {
+                x: b,
+                tolerance: step.abs(),
+                residual: fb,
+                iterations: i,
+            });
+        }
+    }
+
+    Err(SolveError::NoConvergence(MAX_ITERATIONS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(f: impl Fn(f64) -> f64, guess: f64) -> Result<Root, SolveError<()>> {
+        find_root(|x| Ok(f(x)), guess)
+    }
+
+    #[test]
+    fn test_find_root() {
+        let root = solve(|x| x * x - 2.0, 1.0).unwrap();
+        assert!((root.x - 2f64.sqrt()).abs() <= root.tolerance.max(f64::EPSILON));
+        assert!(root.tolerance < 1e-15);
+
+        // 区間を大きく広げてから絞る
+        let root = solve(|x| x * 1.07f64.powi(5) - 1000.0, 1.0).unwrap();
+        assert!((root.x - 1000.0 / 1.07f64.powi(5)).abs() < 1e-9);
+        assert!(root.iterations < 20);
+
+        // 符号の変わらない重解は割線法で探す
+        let root = solve(|x| (x - 3.0) * (x - 3.0), 0.0).unwrap();
+        assert!((root.x - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_find_root_failures() {
+        assert_eq!(
+            solve(|x| x * x + 1.0, 1.0),
+            Err(SolveError::NoConvergence(MAX_ITERATIONS))
+        );
+        // 符号は変わるが解ではない
+        assert!(matches!(
+            solve(|x| 1.0 / x, 1.0),
+            Err(SolveError::NoConvergence(_))
+        ));
+        assert_eq!(
+            find_root(|x| if x < 0.0 { Err("neg") } else { Ok(x - 1.0) }, -1.0),
+            Err(SolveError::Eval("neg"))
+        );
+    }
+}
@@ -5,6 +5,22 @@ impl Location {
     pub fn new(start: usize, end: usize) -> Self {
         Self(start, end)
     }
+    pub fn start(&self) -> usize {
+        self.0
+    }
+
+    pub fn end(&self) -> usize {
+        self.1
+    }
+
+    // 開始位置の行と列(どちらも 1 始まり、列は文字単位)
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.0.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, col)
+    }
+
     pub fn merge(&self, other: &Location) -> Location {
         use std::cmp::{max, min};
         Location(min(self.0, other.0), max(self.1, other.1))
@@ -37,15 +53,26 @@ impl<T: Clone> Annotation<T> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
-    Number(u64),
+    Number(f64),
+    Imaginary(f64),
+    Ident(String),
+    History(usize),
+    Equal,
     Plus,
     Minus,
     Asterisk,
     Slash,
+    Bang,
+    Percent,
+    At,
+    Caret,
     LParen,
     RParen,
+    LBracket,
+    RBracket,
+    Comma,
 }
 
 impl std::fmt::Display for TokenKind {
@@ -53,12 +80,23 @@ impl std::fmt::Display for TokenKind {
         use self::TokenKind::*;
         match self {
             Number(n) => n.fmt(f),
+            Imaginary(n) => write!(f, "{}i", n),
+            Ident(s) => s.fmt(f),
+            History(n) => write!(f, "${}", n),
+            Equal => write!(f, "="),
             Plus => write!(f, "+"),
             Minus => write!(f, "-"),
             Asterisk => write!(f, "*"),
             Slash => write!(f, "/"),
+            Bang => write!(f, "!"),
+            Percent => write!(f, "%"),
+            At => write!(f, "@"),
+            Caret => write!(f, "^"),
             LParen => write!(f, "("),
             RParen => write!(f, ")"),
+            LBracket => write!(f, "["),
+            RBracket => write!(f, "]"),
+            Comma => write!(f, ","),
         }
     }
 }
@@ -66,10 +104,26 @@ impl std::fmt::Display for TokenKind {
 pub type Token = Annotation<TokenKind>;
 
 impl Token {
-    pub fn number(n: u64, loc: Location) -> Self {
+    pub fn number(n: f64, loc: Location) -> Self {
         Self::new(TokenKind::Number(n), loc)
     }
 
+    pub fn imaginary(n: f64, loc: Location) -> Self {
+        Self::new(TokenKind::Imaginary(n), loc)
+    }
+
+    pub fn ident(s: &str, loc: Location) -> Self {
+        Self::new(TokenKind::Ident(s.to_string()), loc)
+    }
+
+    pub fn history(n: usize, loc: Location) -> Self {
+        Self::new(TokenKind::History(n), loc)
+    }
+
+    pub fn equal(loc: Location) -> Self {
+        Self::new(TokenKind::Equal, loc)
+    }
+
     pub fn plus(loc: Location) -> Self {
         Self::new(TokenKind::Plus, loc)
     }
@@ -93,12 +147,122 @@ impl Token {
     pub fn rparen(loc: Location) -> Self {
         Self::new(TokenKind::RParen, loc)
     }
+
+    pub fn bang(loc: Location) -> Self {
+        Self::new(TokenKind::Bang, loc)
+    }
+
+    pub fn percent(loc: Location) -> Self {
+        Self::new(TokenKind::Percent, loc)
+    }
+
+    pub fn at(loc: Location) -> Self {
+        Self::new(TokenKind::At, loc)
+    }
+
+    pub fn caret(loc: Location) -> Self {
+        Self::new(TokenKind::Caret, loc)
+    }
+
+    pub fn lbracket(loc: Location) -> Self {
+        Self::new(TokenKind::LBracket, loc)
+    }
+
+    pub fn rbracket(loc: Location) -> Self {
+        Self::new(TokenKind::RBracket, loc)
+    }
+
+    pub fn comma(loc: Location) -> Self {
+        Self::new(TokenKind::Comma, loc)
+    }
+}
+
+// 空白・改行・コメントなど、構文には関係しないがソースの復元に必要な部分
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TriviaKind {
+    Whitespace(String),
+    Newline,
+    Comment(String),
+}
+
+impl std::fmt::Display for TriviaKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use self::TriviaKind::*;
+        match self {
+            Whitespace(s) => write!(f, "{}", s),
+            Newline => writeln!(f),
+            Comment(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+pub type Trivia = Annotation<TriviaKind>;
+
+impl Trivia {
+    pub fn whitespace(s: &str, loc: Location) -> Self {
+        Self::new(TriviaKind::Whitespace(s.to_string()), loc)
+    }
+
+    pub fn newline(loc: Location) -> Self {
+        Self::new(TriviaKind::Newline, loc)
+    }
+
+    pub fn comment(s: &str, loc: Location) -> Self {
+        Self::new(TriviaKind::Comment(s.to_string()), loc)
+    }
+}
+
+// 元の綴りと直前のトリビアを保持したトークン
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstToken {
+    pub token: Token,
+    pub text: String,
+    pub leading: Vec<Trivia>,
+}
+
+impl CstToken {
+    pub fn new(token: Token, text: &str, leading: Vec<Trivia>) -> Self {
+        Self {
+            token,
+            text: text.to_string(),
+            leading,
+        }
+    }
+
+    // ソースを持たないトークンから作る場合は表示形を綴りとする
+    pub fn bare(token: Token) -> Self {
+        let text = token.value.to_string();
+        Self {
+            token,
+            text,
+            leading: Vec::new(),
+        }
+    }
+
+    pub fn kind(&self) -> TokenKind {
+        self.token.value()
+    }
+
+    pub fn loc(&self) -> Location {
+        self.token.loc()
+    }
+}
+
+impl std::fmt::Display for CstToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for t in &self.leading {
+            t.value.fmt(f)?;
+        }
+        write!(f, "{}", self.text)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum LexErrorKind {
     InvalidChar(char),
     Eof,
+    InputTooLong { len: usize, max: usize },
+    NumberTooLarge,
 }
 
 pub type LexError = Annotation<LexErrorKind>;
@@ -133,6 +297,15 @@ mod tests {
         assert_eq!(merged_loc, Location(1, 6))
     }
 
+    #[test]
+    fn test_location_line_col() {
+        let source = "1 +\n  2 *\n3";
+
+        assert_eq!(Location(0, 1).line_col(source), (1, 1));
+        assert_eq!(Location(6, 7).line_col(source), (2, 3));
+        assert_eq!(Location(12, 12).line_col(source), (3, 2));
+    }
+
     #[test]
     fn test_annotation_new() {
         let loc = Location(0, 1);
@@ -0,0 +1,496 @@
+use crate::eval::{
+    apply_binop, apply_uniop, eval_in, find_function, make_vector, Env, EvalError, Value, SOLVE,
+};
+use crate::parser::{Assoc, Ast, AstKind, BinOp, BinOpKind, OperatorTable, UniOp, UniOpKind};
+use crate::token::Location;
+
+// 一回の簡約の後の式と、簡約してできた値の expr 中の位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub expr: String,
+    pub span: Location,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub start: String,
+    pub steps: Vec<Step>,
+    pub result: Result<Value, EvalError>,
+}
+
+// 最も左の最も内側の節を一つずつ値に置き換えながら評価する。
+// 見た目の変わらない簡約(ベクトルが値になるなど)は記録しない。
+// 括弧は table の優先度に従って必要なところにだけ付ける
+pub fn trace(ast: &Ast, env: &mut Env, table: &OperatorTable) -> Trace {
+    let mut term = Term::from_ast(ast);
+    let start = term.render(table).0;
+    let mut steps = Vec::new();
+
+    let result = loop {
+        if let TermKind::Value(v) = &term.kind {
+            break Ok(v.clone());
+        }
+
+        term.settle();
+        if let Err(e) = term.reduce(env) {
+            break Err(e);
+        }
+        let (expr, span) = term.render(table);
+        if steps.last().map_or(&start, |s: &Step| &s.expr) != &expr {
+            steps.push(Step {
+                expr,
+                span: span.unwrap(),
+            });
+        }
+    };
+
+    Trace {
+        start,
+        steps,
+        result,
+    }
+}
+
+// 途中の式。評価し終えた部分は Value になっている
+struct Term {
+    kind: TermKind,
+    loc: Location,
+    // 直前の簡約でできた値
+    fresh: bool,
+}
+
+enum TermKind {
+    // 数値リテラル、変数、履歴の参照
+    Leaf(Ast),
+    Value(Value),
+    Assign {
+        name: String,
+        e: Box<Term>,
+    },
+    // 表示のためだけに持つ。簡約は Solve が一度に行う
+    Equation {
+        l: Box<Term>,
+        r: Box<Term>,
+    },
+    // 方程式と solve の呼び出し。途中の値は見せずに解いた値に置き換える
+    Solve {
+        ast: Ast,
+        shown: Box<Term>,
+    },
+    UniOp {
+        op: UniOp,
+        e: Box<Term>,
+    },
+    BinOp {
+        op: BinOp,
+        l: Box<Term>,
+        r: Box<Term>,
+    },
+    Vector(Vec<Term>),
+    Call {
+        name: String,
+        args: Vec<Term>,
+    },
+}
+
+impl Term {
+    fn from_ast(ast: &Ast) -> Self {
+        let solves = match &ast.value {
+            AstKind::Equation { .. } => true,
+            AstKind::Call { name, .. } => name == SOLVE,
+            _ => false,
+        };
+        let term = Self::structure(ast);
+        if !solves {
+            return term;
+        }
+
+        Self {
+            kind: TermKind::Solve {
+                ast: ast.clone(),
+                shown: Box::new(term),
+            },
+            loc: ast.loc(),
+            fresh: false,
+        }
+    }
+
+    fn structure(ast: &Ast) -> Self {
+        let kind = match &ast.value {
+            AstKind::Num(_) | AstKind::Imag(_) | AstKind::Var(_) | AstKind::History(_) => {
+                TermKind::Leaf(ast.clone())
+            }
+            AstKind::Assign { name, e } => TermKind::Assign {
+                name: name.clone(),
+                e: Box::new(Self::from_ast(e)),
+            },
+            AstKind::Equation { l, r } => TermKind::Equation {
+                l: Box::new(Self::from_ast(l)),
+                r: Box::new(Self::from_ast(r)),
+            },
+            AstKind::UniOp { op, e } => TermKind::UniOp {
+                op: op.clone(),
+                e: Box::new(Self::from_ast(e)),
+            },
+            AstKind::BinOp { op, l, r } => TermKind::BinOp {
+                op: op.clone(),
+                l: Box::new(Self::from_ast(l)),
+                r: Box::new(Self::from_ast(r)),
+            },
+            AstKind::Vector(items) => TermKind::Vector(items.iter().map(Self::from_ast).collect()),
+            AstKind::Call { name, args } => TermKind::Call {
+                name: name.clone(),
+                args: args.iter().map(Self::from_ast).collect(),
+            },
+        };
+
+        Self {
+            kind,
+            loc: ast.loc(),
+            fresh: false,
+        }
+    }
+
+    // 数値リテラルは書かれたまま残し、親を簡約するときに値にする
+    fn is_ready(&self) -> bool {
+        match &self.kind {
+            TermKind::Value(_) => true,
+            TermKind::Leaf(ast) => matches!(ast.value, AstKind::Num(_) | AstKind::Imag(_)),
+            _ => false,
+        }
+    }
+
+    fn children_mut(&mut self) -> Vec<&mut Term> {
+        match &mut self.kind {
+            TermKind::Leaf(_)
+            | TermKind::Value(_)
+            | TermKind::Equation { .. }
+            | TermKind::Solve { .. } => vec![],
+            TermKind::Assign { e, .. } | TermKind::UniOp { e, .. } => vec![e],
+            TermKind::BinOp { l, r, .. } => vec![l, r],
+            TermKind::Vector(items) | TermKind::Call { args: items, .. } => {
+                items.iter_mut().collect()
+            }
+        }
+    }
+
+    fn settle(&mut self) {
+        self.fresh = false;
+        for c in self.children_mut() {
+            c.settle();
+        }
+    }
+
+    fn value(&self, env: &mut Env) -> Result<Value, EvalError> {
+        match &self.kind {
+            TermKind::Value(v) => Ok(v.clone()),
+            TermKind::Leaf(ast) => eval_in(ast, env),
+            _ => unreachable!("not reduced yet"),
+        }
+    }
+
+    fn reduce(&mut self, env: &mut Env) -> Result<(), EvalError> {
+        if let Some(c) = self.children_mut().into_iter().find(|c| !c.is_ready()) {
+            return c.reduce(env);
+        }
+
+        let loc = self.loc.clone();
+        let v = match &self.kind {
+            TermKind::Leaf(ast) | TermKind::Solve { ast, .. } => eval_in(ast, env)?,
+            TermKind::Value(v) => v.clone(),
+            TermKind::Equation { .. } => unreachable!("equations are reduced by Solve"),
+            TermKind::Assign { name, e } => {
+                let v = e.value(env)?;
+                env.set(name, v.clone());
+                v
+            }
+            TermKind::UniOp { op, e } => apply_uniop(op, e.value(env)?)?,
+            TermKind::BinOp { op, l, r } => apply_binop(op, &l.value(env)?, &r.value(env)?)?,
+            TermKind::Vector(items) => {
+                let items = items
+                    .iter()
+                    .map(|e| e.value(env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                make_vector(items, env.mode(), loc.clone())?
+            }
+            TermKind::Call { name, args } => {
+                let builtin = find_function(name, args.len(), loc.clone())?;
+                let args = args
+                    .iter()
+                    .map(|e| e.value(env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (builtin.f)(&args, env.mode()).map_err(|kind| EvalError::new(kind, loc.clone()))?
+            }
+        };
+        env.check_magnitude(&v)
+            .map_err(|kind| EvalError::new(kind, loc))?;
+
+        self.kind = TermKind::Value(v);
+        self.fresh = true;
+        Ok(())
+    }
+
+    fn render(&self, table: &OperatorTable) -> (String, Option<Location>) {
+        let mut out = String::new();
+        let mut span = None;
+        self.write(table, &mut out, &mut span);
+        (out, span)
+    }
+
+    fn write(&self, table: &OperatorTable, out: &mut String, span: &mut Option<Location>) {
+        let start = out.len();
+        match &self.kind {
+            TermKind::Leaf(ast) => match &ast.value {
+                AstKind::Num(n) => out.push_str(&n.to_string()),
+                AstKind::Imag(n) => out.push_str(&format!("{}i", n)),
+                AstKind::Var(name) => out.push_str(name),
+                AstKind::History(n) => out.push_str(&format!("${}", n)),
+                _ => unreachable!(),
+            },
+            TermKind::Value(v) => out.push_str(&v.to_string()),
+            TermKind::Assign { name, e } => {
+                out.push_str(name);
+                out.push_str(" = ");
+                e.write(table, out, span);
+            }
+            TermKind::Equation { l, r } => {
+                l.write_operand(table, out, span, l.precedence(table) == 0);
+                out.push_str(" = ");
+                r.write_operand(table, out, span, r.precedence(table) == 0);
+            }
+            TermKind::Solve { shown, .. } => shown.write(table, out, span),
+            TermKind::UniOp { op, e } if is_prefix(&op.value) => {
+                // 負の値には括弧を付けて `-(-2)` とする
+                let value = matches!(e.kind, TermKind::Value(_));
+                let (prec, inner) = (uniop_precedence(table, &op.value), e.precedence(table));
+                out.push_str(&op.value.to_string());
+                e.write_operand(table, out, span, inner < prec || (value && inner == prec));
+            }
+            TermKind::UniOp { op, e } => {
+                let paren = e.precedence(table) < uniop_precedence(table, &op.value);
+                e.write_operand(table, out, span, paren);
+                out.push_str(&op.value.to_string());
+            }
+            TermKind::BinOp { op, l, r } => {
+                // 右結合なら左の、左結合なら右の同じ優先度の項を括弧で囲む
+                let (prec, assoc) = binop_precedence(table, &op.value);
+                let right = assoc == Assoc::Right;
+                let (lp, rp) = (l.precedence(table), r.precedence(table));
+                l.write_operand(table, out, span, lp < prec || (right && lp == prec));
+                out.push_str(&format!(" {} ", op.value));
+                r.write_operand(table, out, span, rp < prec || (!right && rp == prec));
+            }
+            TermKind::Vector(items) => {
+                out.push('[');
+                write_list(table, items, out, span);
+                out.push(']');
+            }
+            TermKind::Call { name, args } => {
+                out.push_str(name);
+                out.push('(');
+                write_list(table, args, out, span);
+                out.push(')');
+            }
+        }
+
+        if self.fresh {
+            *span = Some(Location::new(start, out.len()));
+        }
+    }
+
+    fn write_operand(
+        &self,
+        table: &OperatorTable,
+        out: &mut String,
+        span: &mut Option<Location>,
+        paren: bool,
+    ) {
+        if paren {
+            out.push('(');
+        }
+        self.write(table, out, span);
+        if paren {
+            out.push(')');
+        }
+    }
+
+    // 優先度は構文解析と同じ演算子表から取る。
+    // 値は表示したときの形で決める: `-3` は前置の `-`、`1 + 2i` は加算と同じ
+    fn precedence(&self, table: &OperatorTable) -> u8 {
+        match &self.kind {
+            TermKind::Assign { .. } | TermKind::Equation { .. } => 0,
+            TermKind::Solve { shown, .. } => shown.precedence(table),
+            TermKind::BinOp { op, .. } => binop_precedence(table, &op.value).0,
+            TermKind::UniOp { op, .. } => uniop_precedence(table, &op.value),
+            TermKind::Value(Value::Number(n)) if *n < 0.0 => {
+                uniop_precedence(table, &UniOpKind::Minus)
+            }
+            TermKind::Value(v @ Value::Complex(_)) => {
+                let s = v.to_string();
+                if s.contains(" + ") || s.contains(" - ") {
+                    binop_precedence(table, &BinOpKind::Add).0
+                } else if s.starts_with('-') {
+                    uniop_precedence(table, &UniOpKind::Minus)
+                } else {
+                    ATOM
+                }
+            }
+            _ => ATOM,
+        }
+    }
+}
+
+const ATOM: u8 = u8::MAX;
+
+fn is_prefix(op: &UniOpKind) -> bool {
+    matches!(op, UniOpKind::Plus | UniOpKind::Minus)
+}
+
+// 表にない演算子は最も弱いものとして扱い、常に括弧で囲む
+fn uniop_precedence(table: &OperatorTable, op: &UniOpKind) -> u8 {
+    match is_prefix(op) {
+        true => table.find_prefix_op(op).map(|p| p.bp),
+        false => table.find_postfix_op(op).map(|p| p.bp),
+    }
+    .unwrap_or(0)
+}
+
+fn binop_precedence(table: &OperatorTable, op: &BinOpKind) -> (u8, Assoc) {
+    table
+        .find_infix_op(op)
+        .map_or((0, Assoc::Left), |p| (p.bp, p.assoc))
+}
+
+fn write_list(
+    table: &OperatorTable,
+    items: &[Term],
+    out: &mut String,
+    span: &mut Option<Location>,
+) {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        item.write(table, out, span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer;
+    use crate::parser::Parser;
+    use crate::token::TokenKind;
+
+    fn exprs(input: &str, env: &mut Env) -> Vec<String> {
+        let t = trace(
+            &input.parse::<Ast>().unwrap(),
+            env,
+            &OperatorTable::standard(),
+        );
+        std::iter::once(t.start)
+            .chain(t.steps.into_iter().map(|s| s.expr))
+            .collect()
+    }
+
+    #[test]
+    fn test_trace_steps() {
+        let t = trace(
+            &"(1 + 2) * 3 - 4 / 2".parse().unwrap(),
+            &mut Env::new(),
+            &OperatorTable::standard(),
+        );
+
+        assert_eq!(t.start, "(1 + 2) * 3 - 4 / 2");
+        assert_eq!(
+            t.steps,
+            vec![
+                Step {
+                    expr: "3 * 3 - 4 / 2".to_string(),
+                    span: Location::new(0, 1)
+                },
+                Step {
+                    expr: "9 - 4 / 2".to_string(),
+                    span: Location::new(0, 1)
+                },
+                Step {
+                    expr: "9 - 2".to_string(),
+                    span: Location::new(4, 5)
+                },
+                Step {
+                    expr: "7".to_string(),
+                    span: Location::new(0, 1)
+                },
+            ]
+        );
+        assert_eq!(t.result, Ok(Value::Number(7.0)));
+    }
+
+    #[test]
+    fn test_trace_vars_and_errors() {
+        let mut env = Env::new();
+        env.set("x", Value::Number(-2.0));
+
+        assert_eq!(
+            exprs("y = 3! * x + det([[1, 2], [3, 4]])", &mut env),
+            vec![
+                "y = 3! * x + det([[1, 2], [3, 4]])",
+                "y = 6 * x + det([[1, 2], [3, 4]])",
+                "y = 6 * -2 + det([[1, 2], [3, 4]])",
+                "y = -12 + det([[1, 2], [3, 4]])",
+                "y = -12 + -2",
+                "y = -14",
+                "-14",
+            ]
+        );
+        assert_eq!(env.get("y"), Some(Value::Number(-14.0)));
+        assert_eq!(
+            exprs("(-x)!", &mut env),
+            vec!["(-x)!", "(-(-2))!", "2!", "2"]
+        );
+
+        assert_eq!(
+            exprs("2^(-x)^2 * (z^2 = 9)", &mut env),
+            vec![
+                "2 ^ (-x) ^ 2 * (z ^ 2 = 9)",
+                "2 ^ (-(-2)) ^ 2 * (z ^ 2 = 9)",
+                "2 ^ 2 ^ 2 * (z ^ 2 = 9)",
+                "2 ^ 4 * (z ^ 2 = 9)",
+                "16 * (z ^ 2 = 9)",
+                "16 * 3",
+                "48",
+            ]
+        );
+
+        let t = trace(
+            &"1 + 2 / (3 - 3)".parse().unwrap(),
+            &mut env,
+            &OperatorTable::standard(),
+        );
+        assert_eq!(t.steps.len(), 1);
+        assert_eq!(
+            t.result,
+            Err(EvalError::division_by_zero(Location::new(6, 7)))
+        );
+    }
+
+    #[test]
+    fn test_trace_custom_table() {
+        // `+` が `*` より強く結び付く表では、括弧もその表に従って付ける
+        let mut table = OperatorTable::standard();
+        table
+            .infix(TokenKind::Plus, 2, Assoc::Left, BinOpKind::Add)
+            .infix(TokenKind::Asterisk, 1, Assoc::Left, BinOpKind::Mul);
+        let parser = Parser::new(table);
+        let ast = parser.parse(lexer("1 + 2 * 3").unwrap()).unwrap();
+        let t = trace(&ast, &mut Env::new(), parser.table());
+
+        assert_eq!(t.start, "1 + 2 * 3");
+        assert_eq!(t.steps[0].expr, "3 * 3");
+        assert_eq!(t.result, Ok(Value::Number(9.0)));
+
+        let ast = parser.parse(lexer("1 * (2 + 3) + 4").unwrap()).unwrap();
+        let t = trace(&ast, &mut Env::new(), parser.table());
+        assert_eq!(t.start, "1 * 2 + 3 + 4");
+    }
+}
@@ -0,0 +1,68 @@
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+// 実行ファイルの引数を解析した結果を扱う。None は --help で、使い方を標準出力に書いて
+// 正常に終わる。誤りは "name: 理由" と使い方を標準エラーに書いて 2 で終わる
+pub fn args_or_exit<T>(
+    name: &str,
+    usage: &str,
+    parsed: Result<Option<T>, String>,
+) -> Result<T, ExitCode> {
+    args_or_exit_to(name, usage, parsed, &mut io::stdout(), &mut io::stderr())
+}
+
+fn args_or_exit_to<T>(
+    name: &str,
+    usage: &str,
+    parsed: Result<Option<T>, String>,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<T, ExitCode> {
+    match parsed {
+        Ok(Some(args)) => Ok(args),
+        Ok(None) => {
+            let _ = writeln!(out, "{}", usage);
+            Err(ExitCode::SUCCESS)
+        }
+        Err(e) => {
+            let _ = writeln!(err, "{}: {}\n{}", name, e, usage);
+            Err(ExitCode::from(2))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args_or_exit() {
+        let run = |parsed: Result<Option<u8>, String>| {
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            let r = args_or_exit_to("calc", "usage: calc", parsed, &mut out, &mut err);
+            (
+                r,
+                String::from_utf8(out).unwrap(),
+                String::from_utf8(err).unwrap(),
+            )
+        };
+
+        assert_eq!(run(Ok(Some(1))), (Ok(1), String::new(), String::new()));
+        assert_eq!(
+            run(Ok(None)),
+            (
+                Err(ExitCode::SUCCESS),
+                "usage: calc\n".to_string(),
+                String::new()
+            )
+        );
+        assert_eq!(
+            run(Err("unknown option: -x".to_string())),
+            (
+                Err(ExitCode::from(2)),
+                String::new(),
+                "calc: unknown option: -x\nusage: calc\n".to_string()
+            )
+        );
+    }
+}
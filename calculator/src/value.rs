@@ -0,0 +1,587 @@
+use crate::complex::{Complex, ComplexFormat};
+use crate::eval::EvalErrorKind;
+use crate::interval::Interval;
+use crate::numfmt::Rounding;
+use crate::parser::BinOpKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Shape {
+    Vector(usize),
+    Matrix(usize, usize),
+}
+
+impl std::fmt::Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Shape::Vector(n) => write!(f, "[{}]", n),
+            Shape::Matrix(r, c) => write!(f, "[{}x{}]", r, c),
+        }
+    }
+}
+
+// 行優先で要素を持つベクトルまたは行列
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array {
+    shape: Shape,
+    data: Vec<f64>,
+}
+
+impl Array {
+    // sep は要素の区切り。小数点に `,` を使う表記では別の文字にする
+    pub(crate) fn format_with(&self, sep: char, num: &dyn Fn(f64) -> String) -> String {
+        let sep = format!("{} ", sep);
+        let row = |r: &[f64]| {
+            let items: Vec<String> = r.iter().map(|x| num(*x)).collect();
+            format!("[{}]", items.join(&sep))
+        };
+        match self.shape {
+            Shape::Vector(_) => row(&self.data),
+            Shape::Matrix(..) => {
+                let rows: Vec<String> = self.rows().into_iter().map(row).collect();
+                format!("[{}]", rows.join(&sep))
+            }
+        }
+    }
+
+    pub fn vector(data: Vec<f64>) -> Self {
+        Self {
+            shape: Shape::Vector(data.len()),
+            data,
+        }
+    }
+
+    pub fn matrix(rows: usize, cols: usize, data: Vec<f64>) -> Self {
+        assert_eq!(rows * cols, data.len());
+        Self {
+            shape: Shape::Matrix(rows, cols),
+            data,
+        }
+    }
+
+    pub fn identity(n: usize) -> Self {
+        let mut data = vec![0.0; n * n];
+        for i in 0..n {
+            data[i * n + i] = 1.0;
+        }
+        Self::matrix(n, n, data)
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape
+    }
+
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub fn rows(&self) -> Vec<&[f64]> {
+        match self.shape {
+            Shape::Vector(_) => vec![&self.data],
+            Shape::Matrix(_, 0) => vec![],
+            Shape::Matrix(_, c) => self.data.chunks(c).collect(),
+        }
+    }
+
+    // ベクトルは行・列どちらの向きでも引ける
+    pub fn get(&self, r: usize, c: usize) -> f64 {
+        match self.shape {
+            Shape::Vector(_) => self.data[r + c],
+            Shape::Matrix(_, cols) => self.data[r * cols + c],
+        }
+    }
+
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Self {
+        Self {
+            shape: self.shape,
+            data: self.data.iter().map(|x| f(*x)).collect(),
+        }
+    }
+
+    fn zip(&self, other: &Array, f: impl Fn(f64, f64) -> f64) -> Self {
+        Self {
+            shape: self.shape,
+            data: self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(x, y)| f(*x, *y))
+                .collect(),
+        }
+    }
+
+    // 行列積。ベクトルは左からなら行ベクトル、右からなら列ベクトルとして扱う
+    pub fn matmul(&self, other: &Array) -> Result<Value, EvalErrorKind> {
+        let (n, k1, l_vec) = match self.shape {
+            Shape::Vector(n) => (1, n, true),
+            Shape::Matrix(r, c) => (r, c, false),
+        };
+        let (k2, m, r_vec) = match other.shape {
+            Shape::Vector(n) => (n, 1, true),
+            Shape::Matrix(r, c) => (r, c, false),
+        };
+        if k1 != k2 {
+            return Err(EvalErrorKind::shape_mismatch(self.shape, other.shape));
+        }
+
+        let mut data = vec![0.0; n * m];
+        for i in 0..n {
+            for j in 0..m {
+                data[i * m + j] = (0..k1).map(|k| self.get(i, k) * other.get(k, j)).sum();
+            }
+        }
+
+        Ok(match (l_vec, r_vec) {
+            (true, true) => Value::Number(data[0]),
+            (true, false) | (false, true) => Value::Array(Array::vector(data)),
+            (false, false) => Value::Array(Array::matrix(n, m, data)),
+        })
+    }
+
+    pub fn transpose(&self) -> Self {
+        match self.shape {
+            Shape::Vector(n) => Self::matrix(n, 1, self.data.clone()),
+            Shape::Matrix(r, c) => {
+                let mut data = Vec::with_capacity(r * c);
+                for j in 0..c {
+                    for i in 0..r {
+                        data.push(self.data[i * c + j]);
+                    }
+                }
+                Self::matrix(c, r, data)
+            }
+        }
+    }
+
+    fn square(&self) -> Result<usize, EvalErrorKind> {
+        match self.shape {
+            Shape::Matrix(r, c) if r == c => Ok(r),
+            shape => Err(EvalErrorKind::NotSquare(shape.to_string())),
+        }
+    }
+
+    // 部分ピボット選択付きの消去法
+    pub fn det(&self) -> Result<f64, EvalErrorKind> {
+        let n = self.square()?;
+        let mut a = self.data.clone();
+        let mut det = 1.0;
+
+        for col in 0..n {
+            let pivot = (col..n)
+                .max_by(|&x, &y| a[x * n + col].abs().total_cmp(&a[y * n + col].abs()))
+                .unwrap();
+            if a[pivot * n + col] == 0.0 {
+                return Ok(0.0);
+            }
+            if pivot != col {
+                for k in 0..n {
+                    a.swap(pivot * n + k, col * n + k);
+                }
+                det = -det;
+            }
+            det *= a[col * n + col];
+            for row in col + 1..n {
+                let factor = a[row * n + col] / a[col * n + col];
+                for k in col..n {
+                    a[row * n + k] -= factor * a[col * n + k];
+                }
+            }
+        }
+
+        Ok(det)
+    }
+
+    // ガウス・ジョルダン法
+    pub fn inv(&self) -> Result<Self, EvalErrorKind> {
+        let n = self.square()?;
+        let mut a = self.data.clone();
+        let mut inv = Array::identity(n).data;
+        // 丸め誤差で残った軸を 0 とみなす閾値。行列の大きさに比例させる
+        let norm = (0..n)
+            .map(|row| {
+                a[row * n..(row + 1) * n]
+                    .iter()
+                    .map(|x| x.abs())
+                    .sum::<f64>()
+            })
+            .fold(0.0, f64::max);
+        let eps = n as f64 * f64::EPSILON * norm;
+
+        for col in 0..n {
+            let pivot = (col..n)
+                .max_by(|&x, &y| a[x * n + col].abs().total_cmp(&a[y * n + col].abs()))
+                .unwrap();
+            if a[pivot * n + col].abs() <= eps {
+                return Err(EvalErrorKind::SingularMatrix);
+            }
+            for k in 0..n {
+                a.swap(pivot * n + k, col * n + k);
+                inv.swap(pivot * n + k, col * n + k);
+            }
+
+            let p = a[col * n + col];
+            for k in 0..n {
+                a[col * n + k] /= p;
+                inv[col * n + k] /= p;
+            }
+            for row in (0..n).filter(|&row| row != col) {
+                let factor = a[row * n + col];
+                for k in 0..n {
+                    a[row * n + k] -= factor * a[col * n + k];
+                    inv[row * n + k] -= factor * inv[col * n + k];
+                }
+            }
+        }
+
+        Ok(Self::matrix(n, n, inv))
+    }
+}
+
+impl std::fmt::Display for Array {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.format_with(',', &|x| x.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Complex(Complex),
+    Interval(Interval),
+    Array(Array),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Complex(_) => "complex",
+            Value::Interval(_) => "interval",
+            Value::Array(a) => match a.shape {
+                Shape::Vector(_) => "vector",
+                Shape::Matrix(..) => "matrix",
+            },
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Value::Number(_) | Value::Complex(_) | Value::Interval(_) => {
+                self.type_name().to_string()
+            }
+            Value::Array(a) => format!("{} {}", self.type_name(), a.shape),
+        }
+    }
+
+    // 絶対値の最大。区間は両端、配列は全要素を見る
+    pub fn magnitude(&self) -> f64 {
+        match self {
+            Value::Number(n) => n.abs(),
+            Value::Complex(z) => z.abs(),
+            Value::Interval(x) => x.lo.abs().max(x.hi.abs()),
+            Value::Array(a) => a.data().iter().fold(0.0, |m, x| m.max(x.abs())),
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // 実数は虚部 0 の複素数とみなす
+    pub fn as_complex(&self) -> Option<Complex> {
+        match self {
+            Value::Number(n) => Some(Complex::from(*n)),
+            Value::Complex(z) => Some(*z),
+            Value::Interval(_) | Value::Array(_) => None,
+        }
+    }
+
+    // 実数は幅 0 の区間とみなす
+    pub fn as_interval(&self) -> Option<Interval> {
+        match self {
+            Value::Number(n) => Some(Interval::point(*n)),
+            Value::Interval(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    // 数値の表記を num に任せる。区間の端だけは外向きに丸めさせる。
+    // 表示の設定は numfmt::NumberFormat にまとめてある
+    pub(crate) fn format_with(
+        &self,
+        complex: ComplexFormat,
+        sep: char,
+        num: &dyn Fn(f64, Rounding) -> String,
+    ) -> String {
+        let nearest = |x| num(x, Rounding::Nearest);
+        match self {
+            Value::Number(n) => nearest(*n),
+            Value::Complex(z) => z.format_with(complex, &nearest),
+            Value::Interval(x) => format!(
+                "[{}{} {}]",
+                num(x.lo, Rounding::Down),
+                sep,
+                num(x.hi, Rounding::Up)
+            ),
+            Value::Array(a) => a.format_with(sep, &nearest),
+        }
+    }
+
+    // 要素の並びからベクトルや行列を組み立てる
+    pub fn from_items(items: Vec<Value>) -> Result<Value, EvalErrorKind> {
+        if items.iter().all(|v| matches!(v, Value::Number(_))) {
+            let data = items.iter().filter_map(|v| v.as_number()).collect();
+            return Ok(Value::Array(Array::vector(data)));
+        }
+
+        let cols = match items.first() {
+            Some(Value::Array(a)) => match a.shape {
+                Shape::Vector(n) => n,
+                Shape::Matrix(..) => return Err(EvalErrorKind::RaggedMatrix),
+            },
+            _ => return Err(EvalErrorKind::RaggedMatrix),
+        };
+
+        let mut data = Vec::with_capacity(items.len() * cols);
+        for item in &items {
+            match item {
+                Value::Array(a) if a.shape == Shape::Vector(cols) => data.extend(&a.data),
+                _ => return Err(EvalErrorKind::RaggedMatrix),
+            }
+        }
+
+        Ok(Value::Array(Array::matrix(items.len(), cols, data)))
+    }
+
+    pub fn neg(&self) -> Value {
+        match self {
+            Value::Number(n) => Value::Number(-n),
+            Value::Complex(z) => Value::Complex(z.neg()),
+            Value::Interval(x) => Value::Interval(x.neg()),
+            Value::Array(a) => Value::Array(a.map(|x| -x)),
+        }
+    }
+
+    // 百分率。`50%` は 0.5
+    pub fn percent(&self) -> Value {
+        match self {
+            Value::Number(n) => Value::Number(n / 100.0),
+            Value::Complex(z) => Value::Complex(z.div(&Complex::from(100.0))),
+            Value::Interval(x) => Value::Interval(x.div(&Interval::point(100.0))),
+            Value::Array(a) => Value::Array(a.map(|x| x / 100.0)),
+        }
+    }
+
+    pub fn factorial(&self) -> Result<Value, EvalErrorKind> {
+        let domain = || EvalErrorKind::Domain("!".to_string());
+        match self {
+            Value::Number(n) => factorial(*n).map(Value::Number).ok_or_else(domain),
+            Value::Array(a) => {
+                let data = a.data.iter().map(|x| factorial(*x));
+                let data = data.collect::<Option<Vec<_>>>().ok_or_else(domain)?;
+                Ok(Value::Array(Array {
+                    shape: a.shape,
+                    data,
+                }))
+            }
+            _ => Err(EvalErrorKind::TypeMismatch {
+                expected: "number".to_string(),
+                found: self.describe(),
+            }),
+        }
+    }
+
+    // スカラーは配列の各要素に、同じ形の配列同士は要素ごとに演算する
+    pub fn binop(&self, op: &BinOpKind, other: &Value) -> Result<Value, EvalErrorKind> {
+        let f: fn(f64, f64) -> f64 = match op {
+            BinOpKind::Add => |x, y| x + y,
+            BinOpKind::Sub => |x, y| x - y,
+            BinOpKind::Mul => |x, y| x * y,
+            BinOpKind::Div => |x, y| x / y,
+            BinOpKind::Pow => f64::powf,
+            BinOpKind::MatMul => {
+                return match (self, other) {
+                    (Value::Array(l), Value::Array(r)) => l.matmul(r),
+                    _ => Err(EvalErrorKind::type_mismatch("arrays", self, other)),
+                }
+            }
+        };
+
+        if *op == BinOpKind::Div && other.contains_zero() {
+            return Err(EvalErrorKind::DivisionByZero);
+        }
+
+        if matches!(self, Value::Complex(_)) || matches!(other, Value::Complex(_)) {
+            return self.complex_binop(op, other);
+        }
+        if matches!(self, Value::Interval(_)) || matches!(other, Value::Interval(_)) {
+            return self.interval_binop(op, other);
+        }
+
+        let v = match (self, other) {
+            (Value::Number(l), Value::Number(r)) => Value::Number(f(*l, *r)),
+            (Value::Number(l), Value::Array(r)) => Value::Array(r.map(|y| f(*l, y))),
+            (Value::Array(l), Value::Number(r)) => Value::Array(l.map(|x| f(x, *r))),
+            (Value::Array(l), Value::Array(r)) if l.shape == r.shape => Value::Array(l.zip(r, f)),
+            (Value::Array(l), Value::Array(r)) => {
+                return Err(EvalErrorKind::shape_mismatch(l.shape, r.shape))
+            }
+            _ => unreachable!("complex and interval operands are handled above"),
+        };
+
+        // 負の数の非整数乗は実数にならない
+        if *op == BinOpKind::Pow && v.has_nan() && !self.has_nan() && !other.has_nan() {
+            return Err(EvalErrorKind::Domain("^".to_string()));
+        }
+        Ok(v)
+    }
+
+    fn complex_binop(&self, op: &BinOpKind, other: &Value) -> Result<Value, EvalErrorKind> {
+        let (Some(l), Some(r)) = (self.as_complex(), other.as_complex()) else {
+            return Err(EvalErrorKind::type_mismatch("numbers", self, other));
+        };
+        let z = match op {
+            BinOpKind::Add => l.add(&r),
+            BinOpKind::Sub => l.sub(&r),
+            BinOpKind::Mul => l.mul(&r),
+            BinOpKind::Div => l.div(&r),
+            BinOpKind::Pow if r.im == 0.0 => l.powf(r.re),
+            BinOpKind::Pow => {
+                return Err(EvalErrorKind::Unsupported("complex exponent".to_string()))
+            }
+            BinOpKind::MatMul => unreachable!("matmul is handled by the caller"),
+        };
+        Ok(Value::Complex(z))
+    }
+
+    fn interval_binop(&self, op: &BinOpKind, other: &Value) -> Result<Value, EvalErrorKind> {
+        let (Some(l), Some(r)) = (self.as_interval(), other.as_interval()) else {
+            return Err(EvalErrorKind::type_mismatch("intervals", self, other));
+        };
+        let x = match op {
+            BinOpKind::Add => l.add(&r),
+            BinOpKind::Sub => l.sub(&r),
+            BinOpKind::Mul => l.mul(&r),
+            BinOpKind::Div => l.div(&r),
+            BinOpKind::Pow if r.lo == r.hi => l
+                .powf(r.lo)
+                .ok_or_else(|| EvalErrorKind::Domain("^".to_string()))?,
+            BinOpKind::Pow => {
+                return Err(EvalErrorKind::Unsupported("interval exponent".to_string()))
+            }
+            BinOpKind::MatMul => unreachable!("matmul is handled by the caller"),
+        };
+        Ok(Value::Interval(x))
+    }
+
+    fn has_nan(&self) -> bool {
+        match self {
+            Value::Number(n) => n.is_nan(),
+            Value::Array(a) => a.data.iter().any(|x| x.is_nan()),
+            _ => false,
+        }
+    }
+
+    fn contains_zero(&self) -> bool {
+        match self {
+            Value::Number(n) => *n == 0.0,
+            Value::Complex(z) => z.is_zero(),
+            Value::Interval(x) => x.is_zero(),
+            Value::Array(a) => a.data.contains(&0.0),
+        }
+    }
+}
+
+// 非負の整数だけを受け付ける。170 を超えると無限大になる
+pub fn factorial(n: f64) -> Option<f64> {
+    if n < 0.0 || n.fract() != 0.0 {
+        return None;
+    }
+    if n > 170.0 {
+        return Some(f64::INFINITY);
+    }
+    Some((1..=n as u64).map(|k| k as f64).product())
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Number(n) => n.fmt(f),
+            Value::Complex(z) => z.fmt(f),
+            Value::Interval(x) => x.fmt(f),
+            Value::Array(a) => a.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(rows: &[&[f64]]) -> Array {
+        let cols = rows[0].len();
+        Array::matrix(rows.len(), cols, rows.concat())
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let v = Value::Array(Array::vector(vec![1.0, 2.0, 3.0]));
+
+        assert_eq!(
+            v.binop(&BinOpKind::Mul, &Value::Number(2.0)),
+            Ok(Value::Array(Array::vector(vec![2.0, 4.0, 6.0])))
+        );
+        assert_eq!(
+            Value::Number(1.0).binop(&BinOpKind::Sub, &v),
+            Ok(Value::Array(Array::vector(vec![0.0, -1.0, -2.0])))
+        );
+        assert_eq!(
+            v.binop(
+                &BinOpKind::Add,
+                &Value::Array(Array::vector(vec![1.0, 1.0]))
+            ),
+            Err(EvalErrorKind::shape_mismatch(
+                Shape::Vector(3),
+                Shape::Vector(2)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = matrix(&[&[1.0, 2.0], &[3.0, 4.0]]);
+        let b = matrix(&[&[5.0], &[6.0]]);
+
+        assert_eq!(a.matmul(&b), Ok(Value::Array(matrix(&[&[17.0], &[39.0]]))));
+        assert_eq!(
+            Array::vector(vec![1.0, 2.0]).matmul(&Array::vector(vec![3.0, 4.0])),
+            Ok(Value::Number(11.0))
+        );
+        assert!(b.matmul(&b).is_err());
+    }
+
+    #[test]
+    fn test_det_inv() {
+        let a = matrix(&[&[4.0, 7.0], &[2.0, 6.0]]);
+
+        assert!((a.det().unwrap() - 10.0).abs() < 1e-12);
+        let inv = a.inv().unwrap();
+        let expected = [0.6, -0.7, -0.2, 0.4];
+        for (x, y) in inv.data().iter().zip(expected) {
+            assert!((x - y).abs() < 1e-12);
+        }
+
+        let singular = matrix(&[&[1.0, 2.0], &[2.0, 4.0]]);
+        assert_eq!(singular.det(), Ok(0.0));
+        assert_eq!(singular.inv(), Err(EvalErrorKind::SingularMatrix));
+        assert!(Array::vector(vec![1.0]).det().is_err());
+
+        // 小さな値だけの行列も正則
+        let small = matrix(&[&[1e-13]]);
+        assert_eq!(small.inv().unwrap().data(), &[1e13]);
+        let singular = matrix(&[&[0.1, 0.2], &[0.3, 0.6]]);
+        assert_eq!(singular.inv(), Err(EvalErrorKind::SingularMatrix));
+    }
+}
@@ -0,0 +1,63 @@
+use calculator::{evaluate, is_incomplete, parse, tokenize, Engine, Error, TokenKind, Value};
+
+#[test]
+fn test_tokenize() {
+    let kinds: Vec<TokenKind> = tokenize("x = (1 + 2) # sum")
+        .unwrap()
+        .into_iter()
+        .map(|t| t.value)
+        .collect();
+
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::Ident("x".to_string()),
+            TokenKind::Equal,
+            TokenKind::LParen,
+            TokenKind::Number(1.0),
+            TokenKind::Plus,
+            TokenKind::Number(2.0),
+            TokenKind::RParen,
+        ]
+    );
+}
+
+#[test]
+fn test_parse_and_evaluate() {
+    assert!(parse("1 + * 2").is_err());
+    assert!(parse("(1 + 2) * 3")
+        .unwrap()
+        .to_tree()
+        .starts_with("BinOp(*)"));
+
+    assert_eq!(evaluate("(1 + 2) * 3 - 4 / 2"), Ok(Value::Number(7.0)));
+    assert!(matches!(evaluate("1 / 0"), Err(Error::Eval(_))));
+}
+
+#[test]
+fn test_engine_keeps_variables() {
+    let mut engine = Engine::new();
+
+    engine.eval("width = 3").unwrap();
+    engine.set("height", Value::Number(4.0));
+
+    assert_eq!(engine.eval("width * height"), Ok(Value::Number(12.0)));
+    assert_eq!(engine.get("width"), Some(Value::Number(3.0)));
+    assert!(evaluate("width").is_err());
+}
+
+#[test]
+fn test_error_location() {
+    let e = evaluate("1 + 2 $").unwrap_err();
+
+    assert_eq!(e.loc().map(|l| (l.start(), l.end())), Some((6, 7)));
+    assert_eq!(e.to_string(), "invalid character '$'");
+}
+
+#[test]
+fn test_is_incomplete() {
+    assert!(is_incomplete("(1 +"));
+    assert!(is_incomplete("((1 + 2)"));
+    assert!(!is_incomplete("1 +"));
+    assert!(!is_incomplete("(1 + 2)"));
+}
@@ -0,0 +1,43 @@
+/* Exercises the C ABI; tests/capi.rs compiles and runs it. */
+#include <stdio.h>
+#include <string.h>
+
+#include "calculator.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                     \
+    do {                                                                \
+        if (!(cond)) {                                                  \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,      \
+                    __LINE__, #cond);                                   \
+            failures++;                                                 \
+        }                                                               \
+    } while (0)
+
+int main(void) {
+    double result = 0;
+    calc_error error;
+
+    CHECK(calc_eval("(1 + 2) * 3 - 4 / 2", &result, &error) == CALC_OK);
+    CHECK(result == 7);
+    CHECK(error.code == CALC_OK);
+
+    CHECK(calc_eval("1 + * 2", &result, &error) == CALC_ERR_PARSER);
+    CHECK(error.start == 4 && error.end == 5);
+    CHECK(strstr(calc_error_message(&error), "found '*'") != NULL);
+
+    CHECK(calc_eval("1 / 0", NULL, &error) == CALC_ERR_EVAL);
+    CHECK(strcmp(calc_error_message(&error), "division by zero") == 0);
+    CHECK(calc_eval("2 $", NULL, NULL) == CALC_ERR_LEXER);
+
+    calc_session *session = calc_session_new();
+    CHECK(calc_session_eval(session, "rate = 1.5", &result, &error) == CALC_OK);
+    CHECK(calc_session_eval(session, "rate * 4", &result, &error) == CALC_OK);
+    CHECK(result == 6);
+    CHECK(calc_session_eval(session, "[rate]", &result, &error) == CALC_ERR_NOT_A_NUMBER);
+    calc_session_free(session);
+
+    printf("%s\n", failures == 0 ? "ok" : "failed");
+    return failures == 0 ? 0 : 1;
+}
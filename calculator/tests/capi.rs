@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// テストの実行ファイルと同じ場所に静的ライブラリができている
+fn static_lib() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    [deps, deps.parent().unwrap()]
+        .iter()
+        .map(|dir| dir.join("libcalculator.a"))
+        .find(|p| p.exists())
+        .expect("libcalculator.a was not built")
+}
+
+#[test]
+fn test_c_program() {
+    // C コンパイラのない環境では CALC_SKIP_C_TEST=1 を付けたときだけ飛ばす。黙って通しはしない
+    if Command::new("cc").arg("--version").output().is_err() {
+        assert!(
+            std::env::var_os("CALC_SKIP_C_TEST").is_some(),
+            "cc not found; install a C compiler or set CALC_SKIP_C_TEST=1 to skip this test"
+        );
+        eprintln!("test_c_program: SKIPPED (cc not found, CALC_SKIP_C_TEST is set)");
+        return;
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let exe = Path::new(env!("CARGO_TARGET_TMPDIR")).join("capi_test");
+    let status = Command::new("cc")
+        .arg(root.join("tests/c/capi_test.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-o")
+        .arg(&exe)
+        .arg(static_lib())
+        .args(["-lpthread", "-ldl", "-lm"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(&exe).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}